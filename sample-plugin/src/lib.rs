@@ -1,24 +1,74 @@
+use serde::{Deserialize, Serialize};
 use srvr_sysplugin::*;
 
 const PLUGIN_NAME: &'static str = "日本語のSample Plugin✴️";
 const PLUGIN_VERSION: PluginVersion = PluginVersion::new((0, 0, 1));
 
-#[no_mangle]
-pub extern "Rust" fn link() -> Box<dyn Plugin> {Box::new(MyPlugin)}
+declare_plugin!(MyPlugin, MyPlugin::new);
+
+//Written to plugins/<name>/config.toml the first time
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct Config {
+    /// "{player}" is replaced by the player's name
+    welcome: String,
+    reminder_minutes: u64
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {welcome: "Welcome, {player}!".to_string(), reminder_minutes: 5}
+    }
+}
 
 #[derive(Debug)]
-struct MyPlugin;
+struct MyPlugin {
+    logger: Option<PluginLogger>
+}
+
+impl MyPlugin {
+    fn new() -> Self {MyPlugin {logger: None}}
+}
 
 impl Plugin for MyPlugin {
 
     fn name(&self) -> &str {PLUGIN_NAME}
     fn version(&self) -> &PluginVersion {&PLUGIN_VERSION}
+    fn min_server_version(&self) -> PluginVersion {PluginVersion::new((0, 0, 1))}
+
+    fn start(&mut self, ctx: &mut PluginContext) {
+        let logger = ctx.logger();
+        let config: Config = ctx.config().unwrap_or_else(|err| {
+            logger.error(format!("Using the default config (reason: \"{err}\")"));
+            Config::default()
+        });
+
+        //Greet everyone who joins
+        let welcome_logger = logger.clone();
+        ctx.listen(EventKind::PlayerJoin, Priority::Monitor, move |dispatch| {
+            if let Event::PlayerJoin{player} = dispatch.event() {
+                welcome_logger.info(config.welcome.replace("{player}", &player.name));
+            }
+        });
+
+        //Players need "sample.hello" to use /hello
+        ctx.command("hello", "Says hello back", Some("sample.hello"), |call| {
+            call.reply(&format!("Hello, {}!", call.sender().name()));
+        });
 
-    fn start(&mut self) {
-        println!("Started Plugin {}{}", self.name(), self.version());
+        //A minute is 1200 ticks
+        if config.reminder_minutes > 0 {
+            let ticks = config.reminder_minutes * 1200;
+            let reminder_logger = logger.clone();
+            ctx.scheduler().run_repeating(ticks, ticks, move || reminder_logger.info("Still here!"));
+        }
+        logger.info(format!("Started Plugin {}{}", self.name(), self.version()));
+        self.logger = Some(logger);
     }
 
     fn stop(&mut self) {
-        println!("Stopped Plugin {}{}", self.name(), self.version());
+        if let Some(logger) = self.logger.take() {
+            logger.info(format!("Stopped Plugin {}{}", self.name(), self.version()));
+        }
     }
-}
\ No newline at end of file
+}
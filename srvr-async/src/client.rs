@@ -22,19 +22,38 @@
   text of the license in any official language of the European Union.
 */
 
-use std::{net::SocketAddr, time::{Duration, Instant}};
+use std::{error::Error, net::SocketAddr, sync::Arc, time::{Duration, Instant}};
 
-use log::{warn, info};
+use log::{warn, info, debug, trace};
 use rand::Rng;
-use srvr_sysproto::{raw_packet::RawPacketReader};
+use srvr_sysproto::{
+  packets::{
+    CB_LoginDisconnect, CB_LegacyKick, CB_Disconnect, CB_KeepAlive, CB_PlayerInfo,
+    Packet, ServerStatus, ServerBoundPacket, ConnectionState, PacketDecodeError,
+    CB_ChatMessage, CB_PlayerPositionAndLook, PlayerInfoAction, PlayerInfoLatency,
+    SB_PlayerPosition, SB_PlayerPositionAndRotation,
+    CHAT_POSITION_SYSTEM, RELATIVE_YAW, RELATIVE_PITCH, DIGGING_FINISHED
+  },
+  raw_packet::{RawPacketReader, RawPacketWriter, RawPacketError, PacketStream},
+  mc_dtypes::{MCChat, MCUuid}
+};
+use serde_json::json;
+use srvr_sysplugin::{
+  EventBus, Event,
+  events::{Player, Position, BlockPosition},
+  ffi::FfiStr
+};
 use tokio::{
-  sync::{broadcast, mpsc},
+  sync::{broadcast, mpsc, watch},
   net::TcpStream, time::timeout
 };
 
-use crate::messages::{
-  broadcast::BroadcastMsg,
-  client_request::ClientRequest
+use crate::{
+  messages::{
+    broadcast::BroadcastMsg,
+    client_request::{ClientRequest, CReqMsg, CommandSource}
+  },
+  commands::{dispatch_command, describe_response}
 };
 
 
@@ -42,18 +61,24 @@ use crate::messages::{
 mod net;
 use net::*;
 
+pub mod auth;
+use auth::{OnlineMode, GameProfile};
+
+mod keep_alive;
+use keep_alive::{KeepAlive, KeepAliveAction};
+
 //Constants
-const TICK_DURATION: Duration = Duration::from_millis(50);
+pub const TICK_DURATION: Duration = Duration::from_millis(50);
 const TCP_TIMEOUT: Duration = Duration::from_millis(10);
 const BROADCAST_TIMEOUT: Duration = Duration::from_micros(100);
 
 #[derive(Debug)]
 pub struct Client {
   client_id: u128,
-  connection: TcpStream,
+  connection: PacketStream,
   addr: SocketAddr,
-  broadcast_listener: broadcast::Receiver<BroadcastMsg>,
-  superior: mpsc::Sender<ClientRequest>
+  superior: mpsc::Sender<ClientRequest>,
+  online_mode: Option<Arc<OnlineMode>>
 }
 
 impl Client {
@@ -61,10 +86,11 @@ impl Client {
   pub fn get_id(&self) -> u128 {self.client_id}
 
   pub async fn init(
-    mut conn: TcpStream,
+    conn: TcpStream,
     addr: SocketAddr,
-    broadcast: broadcast::Receiver<BroadcastMsg>,
-    server_handle: mpsc::Sender<ClientRequest>
+    server_handle: mpsc::Sender<ClientRequest>,
+    online_mode: Option<Arc<OnlineMode>>,
+    status: watch::Receiver<ServerStatus>
   )
     -> Option<Self>
   {
@@ -83,111 +109,284 @@ impl Client {
       connection (which vanilla clients do) because re-opening the connection to
       receive the ping packet takes too long.
 
-      Therefore, we only close the connection if the reader has confirmed 5 times
-      that the client has indeed disconnected.
+      Therefore, we only close the connection once the reader tells us that the
+      client has closed its end of the connection.
     */
-    let mut disconnect_counter = 0usize;
-
-    'handshake: loop {
-      let packet = RawPacketReader::read(&mut conn).await.unwrap();
-      match packet.get_package_id() {
-        0x00 => {
-          //If the client indicates he wants to login, we break the loop
-          if let 0x02 = x00_handshake::handle_package(packet, &mut conn).await {
-            break 'handshake;
+    let mut conn = PacketStream::new(conn);
+
+    //Legacy (pre-1.7) pings aren't VarInt framed, so we have to catch them
+    //before the packet reader gets to see them
+    match conn.peek(1).await {
+      Ok(bytes) if xfe_serverlist_ping::is_legacy_ping(bytes[0]) => {
+        let legacy_status = CB_LegacyKick::from(&*status.borrow());
+        xfe_serverlist_ping::handle_package(&mut conn, legacy_status).await;
+        info!("Legacy ping! Client disconnected @{}", &addr);
+        return None;
+      },
+      Ok(_) => {},
+      Err(_) => {
+        info!("Client disconnected @{}", &addr);
+        return None;
+      }
+    }
+
+    let mut state = ConnectionState::Handshaking;
+    loop {
+      let mut packet = match RawPacketReader::read(&mut conn).await {
+        Ok(packet) => packet,
+        Err(RawPacketError::Disconnected) => {
+          info!("Client disconnected @{}", &addr);
+          return None;
+        },
+        Err(err) => {
+          warn!("Dropping client @{}, could not read packet: \"{err}\"", &addr);
+          return None;
+        }
+      };
+      let packet = match ServerBoundPacket::decode(state, &mut packet) {
+        Ok(packet) => packet,
+        Err(err) => {
+          warn!("Dropping client @{}: {err}", &addr);
+          return None;
+        }
+      };
+      match packet {
+        ServerBoundPacket::Handshake(handshake) => {
+          //If the client indicates it wants to login, we break the loop
+          match x00_handshake::handle_package(handshake) {
+            Some(ConnectionState::Login) => break,
+            Some(next_state) => state = next_state,
+            None => {
+              warn!("Client @{} requested an invalid state after the handshake", &addr);
+              return None;
+            }
           }
         },
-        0x01 => {
+        ServerBoundPacket::StatusRequest(request) => {
+          //We send the latest status published by the manager
+          let current_status = status.borrow().clone();
+          x00_status::handle_package(request, &mut conn, &current_status).await;
+        },
+        ServerBoundPacket::Ping(ping) => {
           //We answer the ping with a pong, then drop the connection
-          x01_pingpong::handle_package(packet, &mut conn).await;
+          x01_pingpong::handle_package(ping, &mut conn).await;
           info!("Ping-Pong! Client disconnected @{}", &addr);
           return None;
         },
-        0xfe => {}, //legacy ping, not implemented for now
-        usize::MAX if disconnect_counter >= 5 => {
-          //Connection was dropped 5 times, give up on this client
-          info!("Client disconnected @{}", &addr);
-          return None;
-        },
-        usize::MAX => disconnect_counter += 1,
-        invalid_opcode => {
-          //Invalid Opcode
-          warn!("Client @{} sent invalid opcode {invalid_opcode:#04x}", &addr);
-          return None;
+        other => {
+          //The registry only decodes packets of the current state
+          unreachable!("{other:?} decoded in {state} state");
         }
       }
     }
 
     //We broke out of the loop because the client wants to login and then play
-    //We hand control of the client over to the server manager
     Some(Client {
       client_id: rand::thread_rng().gen(),
       connection: conn,
       addr: addr,
-      broadcast_listener: broadcast,
-      superior: server_handle
+      superior: server_handle,
+      online_mode
     })
   }
 
-  pub fn login(mut self) {
+  pub async fn login(&mut self) -> Option<GameProfile> {
     /*(Note to future self)
-      We start the login process right here
+      The acceptor runs the login under a deadline, so we borrow the client
+      instead of consuming it. That way the acceptor can still tell the client
+      why it was kicked when the deadline passes.
     */
-    tokio::spawn(async move {
-      //(1) We start the login loop
-      info!("Login Request from Client @{}", &self.addr);
-      let username;
-
-      'login: loop {
-        let packet = RawPacketReader::read(&mut self.connection).await.unwrap();
-        match packet.get_package_id() {
-          0x00 => {
-            //We handle the login request and break the loop to continue to the
-            //play phase
-            username = x00_login::handle_package(packet, &mut self.connection).await;
-            break 'login;
-          }
-          usize::MAX => {
-            //Client has disconnected, we should NOT try to proceed to play phase
-            info!("Client disconnected @{}", &self.addr);
-            return;
-          },
-          invalid_opcode => {
-            //Invalid Opcode
-            warn!("Client @{} sent invalid opcode {invalid_opcode:#04x}", &self.addr);
-          }
+    info!("Login Request from Client @{}", &self.addr);
+    let profile = loop {
+      let mut packet = match RawPacketReader::read(&mut self.connection).await {
+        Ok(packet) => packet,
+        Err(RawPacketError::Disconnected) => {
+          //Client has disconnected, we should NOT try to proceed to play phase
+          info!("Client disconnected @{}", &self.addr);
+          return None;
+        },
+        Err(err) => {
+          warn!("Dropping client @{}, could not read packet: \"{err}\"", &self.addr);
+          return None;
+        }
+      };
+      let packet = match ServerBoundPacket::decode(ConnectionState::Login, &mut packet) {
+        Ok(packet) => packet,
+        Err(err) => {
+          warn!("Dropping client @{}: {err}", &self.addr);
+          return None;
+        }
+      };
+      match packet {
+        ServerBoundPacket::LoginStart(login_start) => {
+          //We handle the login request and break the loop to continue to the
+          //play phase
+          let online = self.online_mode.clone();
+          let reason = match x00_login::handle_package(
+            login_start, &mut self.connection, online.as_deref()
+          ).await {
+            Ok(login_profile) => break login_profile,
+            Err(err) => err.to_string()
+          };
+
+          //Login failed, so we kick the client
+          warn!("Login failed for client @{}: \"{reason}\"", &self.addr);
+          self.disconnect_login(&reason).await;
+          return None;
+        },
+        other => {
+          //Only valid in the login state, but not before a login start
+          warn!("Client @{} sent unexpected {other:?}", &self.addr);
         }
       }
+    };
 
-      //(2) Loop was broken so login was successful! We may continue to the play
-      //phase
-      self.play(username).await;
-    });
+    //Loop was broken so login was successful! The client may continue to the
+    //play phase
+    info!("UUID of player \"{}\" is {}", profile.name, MCUuid::from(profile.uuid));
+    Some(profile)
+  }
+
+  async fn send_packet<P: Packet>(&mut self, packet: &P) -> Result<(), Box<dyn Error>> {
+    let mut writer = RawPacketWriter::new(packet.packet_id());
+    packet.encode(&mut writer);
+    writer.write(&mut self.connection).await
   }
 
-  async fn play(mut self, username: String) {
-    /*(Note to future self) */
-    info!("Player \"{username}\" joined the game!");
+  pub async fn disconnect_login(&mut self, reason: &str) {
+    //Tell the client why we're kicking it during the login phase
+    let packet = CB_LoginDisconnect{reason: MCChat::from(json!({"text": reason}))};
+    if let Err(err) = self.send_packet(&packet).await {
+      warn!("Could not send disconnect to client @{}: \"{err}\"", &self.addr);
+    }
+  }
+
+  async fn disconnect(&mut self, reason: MCChat) {
+    //Tell the client why we're kicking it during the play phase
+    let packet = CB_Disconnect{reason};
+    if let Err(err) = self.send_packet(&packet).await {
+      warn!("Could not send disconnect to client @{}: \"{err}\"", &self.addr);
+    }
+  }
+
+  pub async fn play(
+    mut self,
+    profile: GameProfile,
+    broadcast_listener: broadcast::Receiver<BroadcastMsg>,
+    events: Arc<EventBus>
+  ) {
+    //(1) Register with the server manager, which may still refuse us
+    let join = CReqMsg::Join{name: profile.name.clone(), uuid: profile.uuid, addr: self.addr};
+    if let Err(denied) = ClientRequest::send(join, self.superior.clone()).await {
+      info!("Player \"{}\" could not join: \"{}\"", profile.name, denied.reason());
+      self.disconnect(MCChat::from(json!({"text": denied.reason()}))).await;
+      return;
+    }
+    info!("Player \"{}\" joined the game!", profile.name);
+
+    //(2) Play until either side ends the connection
+    self.play_loop(&profile, broadcast_listener, &events).await;
+
+    //(3) However the connection ended, the manager must forget about us
+    info!("Player \"{}\" left the game", profile.name);
+    let leave = CReqMsg::Leave{uuid: profile.uuid};
+    if let Err(err) = ClientRequest::send(leave, self.superior.clone()).await {
+      warn!("Could not deregister client @{}: \"{err}\"", &self.addr);
+    }
+  }
+
+  async fn play_loop(
+    &mut self,
+    profile: &GameProfile,
+    mut broadcast_listener: broadcast::Receiver<BroadcastMsg>,
+    events: &EventBus
+  ) {
+    /*(Note to future self)
+      The manager only subscribes us to the broadcast once we have logged in,
+      since nobody reads it during the login. Every tick we (1) handle the
+      packets from the client, (2) keep the connection alive and (3) pass on
+      whatever the server broadcast.
+    */
 
     //First lets define some global vars
     let mut loop_start = Instant::now();
-    'tick_loop: loop {
-      //(*) Listen for client packages
-      if let Ok(read_result) = timeout(
-        TCP_TIMEOUT,
-        RawPacketReader::read(&mut self.connection)
-      ).await {
-        if let Ok(packet) = read_result { match packet.get_package_id() {
-          usize::MAX => {
-            //Client has disconnected -> shutdown
-            info!("Client disconnected @{}", &self.addr);
+    let mut keep_alive = KeepAlive::new(loop_start);
+    let mut position = None;
+    loop {
+      //(1) Handle everything the client sent since the last tick
+      if !self.read_packets(loop_start, profile, events, &mut keep_alive, &mut position).await {
+        return;
+      }
+
+      //(2) Send a keep-alive when it's time, or kick the client if it didn't
+      //answer the last one
+      match keep_alive.poll(Instant::now()) {
+        KeepAliveAction::Send(keep_alive_id) => {
+          if let Err(err) = self.send_packet(&CB_KeepAlive{keep_alive_id}).await {
+            warn!("Dropping client @{}, could not send keep-alive: \"{err}\"", &self.addr);
             return;
+          }
+        },
+        KeepAliveAction::TimedOut => {
+          info!("Player \"{}\" timed out", profile.name);
+          self.disconnect(MCChat::from(json!({"translate": "disconnect.timeout"}))).await;
+          return;
+        },
+        KeepAliveAction::Wait => {}
+      }
+
+      //(3) Handle broadcasts from the server manager
+      loop {
+        let msg = match broadcast_listener.try_recv() {
+          Ok(msg) => msg,
+          Err(broadcast::error::TryRecvError::Empty) => break,
+          Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+            warn!("Client @{} missed {skipped} broadcasts", &self.addr);
+            continue;
           },
-          invalid_opcode => {
-            //Invalid Opcode
-            warn!("Client @{} sent invalid opcode {invalid_opcode:#04x}", &self.addr);
+          Err(broadcast::error::TryRecvError::Closed) => {
+            //The server manager is gone
+            return;
           }
-        }}
+        };
+        match msg {
+          BroadcastMsg::Die{reason} => {
+            //The server is stopping, so we kick the player ourselves
+            self.disconnect(MCChat::from(json!({"text": reason}))).await;
+            return;
+          },
+          BroadcastMsg::PlayerLatency{uuid, latency} => {
+            //Update the ping shown in the player list
+            let update = CB_PlayerInfo{action: PlayerInfoAction::UpdateLatency(vec![
+              PlayerInfoLatency{uuid, ping: latency.as_millis() as i32}
+            ])};
+            if let Err(err) = self.send_packet(&update).await {
+              warn!("Dropping client @{}, could not update player list: \"{err}\"", &self.addr);
+              return;
+            }
+          },
+          BroadcastMsg::Chat{message} => {
+            let chat = CB_ChatMessage{message, position: CHAT_POSITION_SYSTEM, sender: 0};
+            if let Err(err) = self.send_packet(&chat).await {
+              warn!("Dropping client @{}, could not send chat: \"{err}\"", &self.addr);
+              return;
+            }
+          },
+          BroadcastMsg::Kick{uuid, reason} if uuid == profile.uuid => {
+            info!("Player \"{}\" was kicked: \"{reason}\"", profile.name);
+            self.disconnect(MCChat::from(json!({"text": reason}))).await;
+            return;
+          },
+          BroadcastMsg::Teleport{uuid, x, y, z} if uuid == profile.uuid => {
+            position = Some(Position{x, y, z});
+            if let Err(err) = self.teleport(Position{x, y, z}).await {
+              warn!("Dropping client @{}, could not teleport player: \"{err}\"", &self.addr);
+              return;
+            }
+          },
+          //Meant for another player
+          BroadcastMsg::Kick{..} | BroadcastMsg::Teleport{..} => {}
+        }
       }
 
       /*(*)
@@ -201,7 +400,223 @@ impl Client {
       }
       loop_start = Instant::now();
     }
-    info!("Client disconnected @{}", &self.addr);
   }
 
-}
\ No newline at end of file
+  async fn teleport(&mut self, to: Position) -> Result<(), Box<dyn Error>> {
+    //Keep looking the same way, the client confirms with the same ID
+    let teleport = CB_PlayerPositionAndLook{
+      x: to.x,
+      y: to.y,
+      z: to.z,
+      yaw: 0.0,
+      pitch: 0.0,
+      flags: RELATIVE_YAW | RELATIVE_PITCH,
+      teleport_id: rand::thread_rng().gen(),
+      dismount_vehicle: true
+    };
+    self.send_packet(&teleport).await
+  }
+
+  async fn on_chat(&mut self, profile: &GameProfile, events: &EventBus, message: String)
+    -> Result<(), Box<dyn Error>>
+  {
+    //(1) Commands start with a slash, players only have the plugins' ones
+    if let Some(line) = message.strip_prefix('/') {
+      if dispatch_command(events, &profile.name, line) {
+        return Ok(());
+      }
+      let source = CommandSource::Player{name: profile.name.clone(), uuid: profile.uuid};
+      let command = CReqMsg::PluginCommand{source, line: line.to_string()};
+      let replies = match ClientRequest::send(command, self.superior.clone()).await {
+        Ok(rsp) => describe_response(rsp).into_iter().map(|line| json!({"text": line})).collect(),
+        Err(denied) => vec![json!({"text": denied.reason(), "color": "red"})]
+      };
+      for reply in replies {
+        self.send_packet(&CB_ChatMessage{message: MCChat::from(reply), position: CHAT_POSITION_SYSTEM, sender: 0}).await?;
+      }
+      return Ok(());
+    }
+
+    //(2) Plugins may keep the message from the other players
+    let player = Player{name: FfiStr::new(&profile.name), uuid: profile.uuid};
+    if events.dispatch(Event::Chat{player, message: FfiStr::new(&message)}) {
+      return Ok(());
+    }
+
+    //(R) The manager passes it on to everyone
+    let chat = CReqMsg::Chat{name: profile.name.clone(), message};
+    if let Err(err) = ClientRequest::send(chat, self.superior.clone()).await {
+      warn!("Could not send chat of client @{}: \"{err}\"", &self.addr);
+    }
+    Ok(())
+  }
+
+  async fn read_packets(
+    &mut self,
+    tick_start: Instant,
+    profile: &GameProfile,
+    events: &EventBus,
+    keep_alive: &mut KeepAlive,
+    position: &mut Option<Position>
+  ) -> bool {
+    /*(Note to future self)
+      Clients send several packets a tick (moving alone is one), so reading
+      one per tick falls behind, and a keep-alive echo stuck behind the rest
+      times out. We read until the client has nothing more for us, or the
+      tick is over. Returns false if the client has to be dropped.
+    */
+    while tick_start.elapsed() < TICK_DURATION {
+      let read_result = match timeout(TCP_TIMEOUT, RawPacketReader::read(&mut self.connection)).await {
+        Ok(read_result) => read_result,
+        //Nothing left to read this tick
+        Err(_) => return true
+      };
+      match read_result {
+        Ok(mut packet) => match ServerBoundPacket::decode(ConnectionState::Play, &mut packet) {
+          Ok(ServerBoundPacket::KeepAlive(answer)) => {
+            //The client echoed a keep-alive, so we know its latency
+            if let Some(latency) = keep_alive.on_response(answer.keep_alive_id, Instant::now()) {
+              trace!("Client @{} has a latency of {}ms", &self.addr, latency.as_millis());
+              self.report_latency(profile.uuid, latency).await;
+            }
+          },
+          Ok(ServerBoundPacket::ChatMessage(chat)) => {
+            if let Err(err) = self.on_chat(profile, events, chat.message).await {
+              warn!("Dropping client @{}, could not answer chat: \"{err}\"", &self.addr);
+              return false;
+            }
+          },
+          Ok(ServerBoundPacket::PlayerPosition(SB_PlayerPosition{x, y, z, ..}))
+          | Ok(ServerBoundPacket::PlayerPositionAndRotation(SB_PlayerPositionAndRotation{x, y, z, ..})) => {
+            match self.on_move(profile, events, *position, Position{x, y, z}).await {
+              Ok(new_position) => *position = Some(new_position),
+              Err(err) => {
+                warn!("Dropping client @{}, could not move player back: \"{err}\"", &self.addr);
+                return false;
+              }
+            }
+          },
+          Ok(ServerBoundPacket::PlayerDigging(digging)) if digging.status == DIGGING_FINISHED => {
+            /*(Note to future self)
+              Players in creative mode break blocks instantly, without ever
+              finishing, but we don't know about game modes yet. We don't
+              store blocks yet either, so a cancelled break isn't undone.
+            */
+            let (x, z, y) = digging.location;
+            events.dispatch(Event::BlockBreak{
+              player: Player{name: FfiStr::new(&profile.name), uuid: profile.uuid},
+              position: BlockPosition{x, y: y as i32, z}
+            });
+          },
+          Ok(ServerBoundPacket::PlayerBlockPlacement(placement)) => {
+            //Same as breaking: nothing to undo if a plugin cancels
+            let (x, z, y) = placement.location;
+            events.dispatch(Event::BlockPlace{
+              player: Player{name: FfiStr::new(&profile.name), uuid: profile.uuid},
+              position: BlockPosition{x, y: y as i32, z},
+              face: placement.face
+            });
+          },
+          Ok(packet) => {
+            //Nothing is done with the other play packets yet
+            trace!("Client @{} sent {packet:?}", &self.addr);
+          },
+          Err(err @ PacketDecodeError::UnknownPacket{..}) => {
+            //Most play packets aren't implemented yet, so these are expected
+            debug!("Client @{} sent {err}", &self.addr);
+          },
+          Err(err) => {
+            warn!("Dropping client @{}: {err}", &self.addr);
+            return false;
+          }
+        },
+        Err(RawPacketError::Disconnected) => {
+          //Client has disconnected -> shutdown
+          info!("Client disconnected @{}", &self.addr);
+          return false;
+        },
+        Err(err) => {
+          warn!("Dropping client @{}, could not read packet: \"{err}\"", &self.addr);
+          return false;
+        }
+      }
+    }
+    true
+  }
+
+  async fn on_move(&mut self, profile: &GameProfile, events: &EventBus, from: Option<Position>, to: Position)
+    -> Result<Position, Box<dyn Error>>
+  {
+    //Returns where the player ended up. The first move we see has nowhere to
+    //come from, so it starts where it ends.
+    let from = from.unwrap_or(to);
+    let cancelled = events.dispatch(Event::PlayerMove{
+      player: Player{name: FfiStr::new(&profile.name), uuid: profile.uuid},
+      from,
+      to
+    });
+    if !cancelled {
+      return Ok(to);
+    }
+    self.teleport(from).await?;
+    Ok(from)
+  }
+
+  async fn report_latency(&mut self, uuid: u128, latency: Duration) {
+    //The server manager passes the latency on to the other clients
+    let msg = CReqMsg::UpdateLatency{uuid, latency};
+    if let Err(err) = ClientRequest::send(msg, self.superior.clone()).await {
+      warn!("Could not report latency of client @{}: \"{err}\"", &self.addr);
+    }
+  }
+
+}
+#[cfg(test)]
+mod client_test {
+  use super::*;
+  use srvr_sysproto::packets::SB_KeepAlive;
+  use tokio::net::TcpListener;
+
+  use crate::messages::client_request::CReqRsp;
+
+  async fn send<P: Packet>(peer: &mut PacketStream, packet: &P) {
+    let mut writer = RawPacketWriter::new(packet.packet_id());
+    packet.encode(&mut writer);
+    writer.write(peer).await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn backlog_before_keep_alive() {
+    //(1) A player that is connected to us, and a manager that hears about it
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut peer = PacketStream::new(TcpStream::connect(listener.local_addr().unwrap()).await.unwrap());
+    let (conn, addr) = listener.accept().await.unwrap();
+    let (superior, mut requests) = mpsc::channel(1);
+    let mut client = Client {client_id: 0, connection: PacketStream::new(conn), addr, superior, online_mode: None};
+    let manager = tokio::spawn(async move {
+      let (msg, tx) = requests.recv().await.unwrap().open();
+      let _ = tx.send(Ok(CReqRsp::Done));
+      msg
+    });
+
+    //(2) The keep-alive echo comes in behind a tick's worth of moving around
+    let start = Instant::now();
+    let mut keep_alive = KeepAlive::new(start);
+    let keep_alive_id = match keep_alive.poll(start + Duration::from_secs(15)) {
+      KeepAliveAction::Send(id) => id,
+      other => panic!("expected a keep-alive, got {other:?}")
+    };
+    for step in 0..50 {
+      send(&mut peer, &SB_PlayerPosition{x: step as f64, y: 64.0, z: 0.0, on_ground: true}).await;
+    }
+    send(&mut peer, &SB_KeepAlive{keep_alive_id}).await;
+
+    //(3) All of it is handled in a single tick
+    let profile = GameProfile {uuid: 7, name: "jeb_".to_string(), properties: Vec::new()};
+    let mut position = None;
+    assert!(client.read_packets(Instant::now(), &profile, &EventBus::new(), &mut keep_alive, &mut position).await);
+    assert_eq!(position, Some(Position{x: 49.0, y: 64.0, z: 0.0}));
+    assert!(matches!(manager.await.unwrap(), CReqMsg::UpdateLatency{uuid: 7, ..}));
+  }
+
+}
@@ -0,0 +1,268 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::{
+  error::Error,
+  fmt::{self, Debug, Display, Formatter},
+  future::Future,
+  pin::Pin
+};
+
+use rand::RngCore;
+use rsa::{
+  RsaPrivateKey, Pkcs1v15Encrypt,
+  pkcs8::EncodePublicKey
+};
+use serde::Deserialize;
+use srvr_sysproto::mc_dtypes::MCUuid;
+
+//Mojang's session server, used by all vanilla clients
+const MOJANG_SESSION_SERVER: &str = "https://sessionserver.mojang.com";
+const HAS_JOINED_ENDPOINT: &str = "/session/minecraft/hasJoined";
+
+//Key sizes mandated by the protocol
+const RSA_KEY_BITS: usize = 1024;
+const VERIFY_TOKEN_LEN: usize = 4;
+
+/// Future returned by an [`Authenticator`]
+pub type AuthFuture<'a> = Pin<Box<dyn Future<Output = Result<GameProfile, AuthError>> + Send + 'a>>;
+
+/// Verifies with a session server that a player has really joined this server.
+/// The vanilla implementation is [`MojangAuthenticator`], but the trait allows
+/// plugging in other session servers (or a mock server for testing).
+pub trait Authenticator: Debug + Send + Sync {
+  fn has_joined<'a>(&'a self, username: &'a str, server_hash: &'a str) -> AuthFuture<'a>;
+}
+
+#[derive(Debug)]
+pub struct OnlineMode {
+  /*(Note to future self)
+    Everything a client task needs to log a player in in online mode. There is
+    only one keypair for the entire server: it is generated on startup and
+    shared by all clients through an Arc.
+  */
+  private_key: RsaPrivateKey,
+  public_key_der: Vec<u8>,
+  authenticator: Box<dyn Authenticator>
+}
+
+impl OnlineMode {
+
+  pub fn new(authenticator: Box<dyn Authenticator>) -> Result<Self, Box<dyn Error>> {
+    //(1) Generate the server's keypair
+    let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), RSA_KEY_BITS)?;
+
+    //(2) Clients expect the public key in ASN.1 DER (SubjectPublicKeyInfo) form
+    let public_key_der = private_key.to_public_key().to_public_key_der()?.into_vec();
+
+    Ok(OnlineMode { private_key, public_key_der, authenticator })
+  }
+
+  pub fn public_key_der(&self) -> &[u8] {&self.public_key_der}
+
+  pub fn new_verify_token(&self) -> Vec<u8> {
+    let mut token = vec![0u8; VERIFY_TOKEN_LEN];
+    rand::thread_rng().fill_bytes(&mut token);
+    token
+  }
+
+  pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, AuthError> {
+    self.private_key.decrypt(Pkcs1v15Encrypt, data)
+      .map_err(|err| AuthError(format!("could not decrypt client data: {err}")))
+  }
+
+  pub fn authenticator(&self) -> &dyn Authenticator {self.authenticator.as_ref()}
+
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameProfile {
+  pub uuid: u128,
+  pub name: String,
+  pub properties: Vec<ProfileProperty>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ProfileProperty {
+  pub name: String,
+  pub value: String,
+  pub signature: Option<String>
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionResponse {
+  //The session server sends the UUID as 32 hex digits without hyphens
+  id: String,
+  name: String,
+  #[serde(default)]
+  properties: Vec<ProfileProperty>
+}
+
+#[derive(Debug)]
+pub struct MojangAuthenticator {
+  session_server: String,
+  http: reqwest::Client
+}
+
+impl MojangAuthenticator {
+
+  pub fn new() -> Self {Self::with_url(MOJANG_SESSION_SERVER)}
+
+  pub fn with_url(session_server: &str) -> Self {
+    MojangAuthenticator {
+      session_server: session_server.trim_end_matches('/').to_string(),
+      http: reqwest::Client::new()
+    }
+  }
+
+  async fn query(&self, username: &str, server_hash: &str) -> Result<GameProfile, AuthError> {
+    //(1) Ask the session server whether this player joined with our hash
+    let url = format!("{}{}", self.session_server, HAS_JOINED_ENDPOINT);
+    let rsp = self.http.get(url)
+      .query(&[("username", username), ("serverId", server_hash)])
+      .send().await?;
+
+    //(2) Anything other than 200 means that the player is not authenticated
+    if rsp.status() != reqwest::StatusCode::OK {
+      return Err(AuthError(format!(
+        "session server did not authenticate \"{username}\" (status {})", rsp.status()
+      )));
+    }
+
+    //(3) Parse the profile
+    let session: SessionResponse = rsp.json().await?;
+    let uuid: MCUuid = session.id.parse()
+      .map_err(|err| AuthError(format!("session server sent invalid UUID: {err}")))?;
+
+    Ok(GameProfile { uuid: uuid.into(), name: session.name, properties: session.properties })
+  }
+
+}
+
+impl Default for MojangAuthenticator {
+  fn default() -> Self {Self::new()}
+}
+
+impl Authenticator for MojangAuthenticator {
+  fn has_joined<'a>(&'a self, username: &'a str, server_hash: &'a str) -> AuthFuture<'a> {
+    Box::pin(self.query(username, server_hash))
+  }
+}
+
+#[derive(Debug)]
+pub struct AuthError(String);
+
+impl Display for AuthError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "Authentication failed: \"{}\"", self.0)
+  }
+}
+
+impl Error for AuthError {}
+
+impl From<String> for AuthError {
+  fn from(msg: String) -> Self {AuthError(msg)}
+}
+impl From<&str> for AuthError {
+  fn from(msg: &str) -> Self {AuthError(msg.to_string())}
+}
+
+impl From<reqwest::Error> for AuthError {
+  fn from(err: reqwest::Error) -> Self {AuthError(format!("{err}"))}
+}
+
+#[cfg(test)]
+mod auth_test {
+
+  use rsa::{RsaPublicKey, Pkcs1v15Encrypt, pkcs8::DecodePublicKey};
+  use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener
+  };
+
+  use super::{Authenticator, MojangAuthenticator, OnlineMode};
+
+  async fn mock_session_server(status: &'static str, body: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+    //Serves exactly one HTTP request and returns the request line
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let handle = tokio::spawn(async move {
+      let (mut conn, _) = listener.accept().await.unwrap();
+      let mut request = vec![0u8; 4096];
+      let len = conn.read(&mut request).await.unwrap();
+      let request = String::from_utf8_lossy(&request[..len]).to_string();
+
+      let rsp = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+      );
+      conn.write_all(rsp.as_bytes()).await.unwrap();
+      request.lines().next().unwrap().to_string()
+    });
+
+    (url, handle)
+  }
+
+  #[tokio::test]
+  async fn has_joined() {
+    let (url, server) = mock_session_server("200 OK", r#"{
+      "id": "069a79f444e94726a5befca90e38aaf5",
+      "name": "Notch",
+      "properties": [{"name": "textures", "value": "abc", "signature": "def"}]
+    }"#).await;
+
+    let profile = MojangAuthenticator::with_url(&url)
+      .has_joined("Notch", "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1").await
+      .unwrap();
+    assert_eq!(profile.uuid, 0x069a79f444e94726a5befca90e38aaf5);
+    assert_eq!(profile.name, "Notch");
+    assert_eq!(profile.properties[0].name, "textures");
+
+    let request_line = server.await.unwrap();
+    assert!(request_line.starts_with("GET /session/minecraft/hasJoined?"));
+    assert!(request_line.contains("username=Notch"));
+    assert!(request_line.contains("serverId=-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1"));
+  }
+
+  #[tokio::test]
+  async fn not_joined() {
+    let (url, _server) = mock_session_server("204 No Content", "").await;
+    assert!(MojangAuthenticator::with_url(&url).has_joined("Notch", "abc").await.is_err());
+  }
+
+  #[test]
+  fn shared_secret_roundtrip() {
+    //The client encrypts the shared secret with the public key we sent it
+    let online = OnlineMode::new(Box::new(MojangAuthenticator::new())).unwrap();
+    let public_key = RsaPublicKey::from_public_key_der(online.public_key_der()).unwrap();
+
+    let secret = [0x37u8; 16];
+    let encrypted = public_key
+      .encrypt(&mut rand::thread_rng(), Pkcs1v15Encrypt, &secret)
+      .unwrap();
+    assert_eq!(online.decrypt(&encrypted).unwrap(), secret.to_vec());
+  }
+
+}
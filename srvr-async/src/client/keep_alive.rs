@@ -0,0 +1,157 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+//Vanilla sends a keep-alive every 15 seconds and gives the client just as long
+//to answer. We spread ours out a bit so that not every client pings at once
+const MIN_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_INTERVAL: Duration = Duration::from_secs(15);
+pub const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepAliveAction {
+  //Nothing to do this tick
+  Wait,
+  //Send a keep-alive with this ID
+  Send(i64),
+  //The client did not answer in time and should be kicked
+  TimedOut
+}
+
+#[derive(Debug)]
+pub struct KeepAlive {
+  /*(Note to future self)
+    Keeps track of the keep-alive exchange with a single client. It doesn't do
+    any IO itself: the client polls it every tick and tells it when an answer
+    comes in. That keeps the timing logic testable.
+  */
+  next_send: Instant,
+  pending: Option<(i64, Instant)>,
+  latency: Option<Duration>
+}
+
+impl KeepAlive {
+
+  pub fn new(now: Instant) -> Self {
+    KeepAlive { next_send: now + random_interval(), pending: None, latency: None }
+  }
+
+  pub fn poll(&mut self, now: Instant) -> KeepAliveAction {
+    match self.pending {
+      //(1) Still waiting for an answer
+      Some((_, sent)) if now.duration_since(sent) > KEEP_ALIVE_TIMEOUT => KeepAliveAction::TimedOut,
+      Some(_) => KeepAliveAction::Wait,
+      //(2) Time for the next keep-alive
+      None if now >= self.next_send => {
+        let id = rand::thread_rng().gen();
+        self.pending = Some((id, now));
+        self.next_send = now + random_interval();
+        KeepAliveAction::Send(id)
+      },
+      None => KeepAliveAction::Wait
+    }
+  }
+
+  /// Handles the client's answer. Returns the new smoothed latency if the ID
+  /// matches the keep-alive we are waiting for, otherwise the answer is ignored.
+  pub fn on_response(&mut self, id: i64, now: Instant) -> Option<Duration> {
+    let (expected, sent) = self.pending?;
+    if id != expected {
+      return None;
+    }
+    self.pending = None;
+
+    //Same smoothing as vanilla: every new sample counts for a quarter
+    let sample = now.duration_since(sent);
+    let latency = match self.latency {
+      Some(latency) => (latency * 3 + sample) / 4,
+      None => sample
+    };
+    self.latency = Some(latency);
+    Some(latency)
+  }
+
+}
+
+fn random_interval() -> Duration {
+  rand::thread_rng().gen_range(MIN_INTERVAL..=MAX_INTERVAL)
+}
+
+#[cfg(test)]
+mod keep_alive_test {
+
+  use std::time::{Duration, Instant};
+
+  use super::{KeepAlive, KeepAliveAction, MAX_INTERVAL, KEEP_ALIVE_TIMEOUT};
+
+  fn send(keep_alive: &mut KeepAlive, now: Instant) -> i64 {
+    match keep_alive.poll(now) {
+      KeepAliveAction::Send(id) => id,
+      other => panic!("expected a keep-alive, got {other:?}")
+    }
+  }
+
+  #[test]
+  fn latency_smoothing() {
+    let start = Instant::now();
+    let mut keep_alive = KeepAlive::new(start);
+    assert_eq!(keep_alive.poll(start), KeepAliveAction::Wait);
+
+    //First sample is taken as is
+    let now = start + MAX_INTERVAL;
+    let id = send(&mut keep_alive, now);
+    assert_eq!(keep_alive.poll(now), KeepAliveAction::Wait);
+    assert_eq!(keep_alive.on_response(id, now + Duration::from_millis(100)), Some(Duration::from_millis(100)));
+
+    //Later samples only count for a quarter
+    let now = now + MAX_INTERVAL + Duration::from_millis(100);
+    let id = send(&mut keep_alive, now);
+    assert_eq!(keep_alive.on_response(id, now + Duration::from_millis(20)), Some(Duration::from_millis(80)));
+  }
+
+  #[test]
+  fn wrong_id() {
+    let start = Instant::now();
+    let mut keep_alive = KeepAlive::new(start);
+    let id = send(&mut keep_alive, start + MAX_INTERVAL);
+    assert_eq!(keep_alive.on_response(id.wrapping_add(1), start + MAX_INTERVAL), None);
+
+    //The right ID is still accepted afterwards
+    assert!(keep_alive.on_response(id, start + MAX_INTERVAL).is_some());
+  }
+
+  #[test]
+  fn timeout() {
+    let start = Instant::now();
+    let mut keep_alive = KeepAlive::new(start);
+    let sent = start + MAX_INTERVAL;
+    send(&mut keep_alive, sent);
+    assert_eq!(keep_alive.poll(sent + KEEP_ALIVE_TIMEOUT), KeepAliveAction::Wait);
+    assert_eq!(keep_alive.poll(sent + KEEP_ALIVE_TIMEOUT + Duration::from_millis(1)), KeepAliveAction::TimedOut);
+  }
+
+}
@@ -27,10 +27,12 @@
 */
 //(A) handshake procedure
 pub mod x00_handshake;
+pub mod x00_status;
 pub mod x01_pingpong;
-//pub mod xfe_serverlist_ping;
+pub mod xfe_serverlist_ping;
 
 //(B) login procedure
 pub mod x00_login;
+pub mod x01_encryption;
 
 //(C) Play
\ No newline at end of file
@@ -23,54 +23,13 @@
 */
 
 use log::trace;
-use tokio::net::TcpStream;
 
-use srvr_sysproto::{
-  packets::{SB_Handshake, Packet, CB_Status},
-  raw_packet::{RawPacketReader, RawPacketWriter}
-};
+use srvr_sysproto::packets::{SB_Handshake, ConnectionState};
 
-pub async fn handle_package(mut raw_pck: RawPacketReader, stream: &mut TcpStream)
-  -> u8
-{
-  //(1) Decode handshake
-  let handshake = SB_Handshake::decode(&mut raw_pck).unwrap();
+pub fn handle_package(handshake: SB_Handshake) -> Option<ConnectionState> {
+  //(1) The handshake tells us what the client wants to do next
+  trace!("{handshake:?}");
 
-  //(2) Decide where we go next
-  match handshake.next_state_code() {
-    0x01 => {
-      //Code 1: Status Request, for now, we send a basic JSON response:
-
-      //(2.1) Create sample response
-      let response = CB_Status::new(format!("{{
-        \"version\": {{
-          \"name\": \"1.18.2\",
-          \"protocol\": {}
-        }},
-        \"players\": {{
-          \"max\": 1000,
-          \"online\": 5,
-          \"sample\": [
-            {{
-              \"name\": \"thinkofdeath\",
-              \"id\": \"4566e69f-c907-48ee-8d71-d7ba5aa00d20\"
-            }}
-          ]
-        }},
-        \"description\": {{
-          \"text\": \"Hello world\"
-        }}
-      }}", srvr_sysproto::PROTOCOL_VERSION));
-      trace!("{response:?}");
-      
-      //(2.2) Reply with response
-      let mut writer = RawPacketWriter::new(response.packet_id());
-      response.encode(&mut writer);
-      writer.write(stream).await.unwrap();
-
-      //(R) Do nothing
-      0x01
-    },
-    other => other
-  }
+  //(R) Either status or login, anything else is invalid
+  handshake.next_state()
 }
\ No newline at end of file
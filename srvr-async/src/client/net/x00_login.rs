@@ -22,31 +22,84 @@
   text of the license in any official language of the European Union.
 */
 
-use tokio::net::TcpStream;
-
-use rand::Rng;
+use std::error::Error;
 
 use srvr_sysproto::{
-  packets::{SB_LoginStart, Packet, CB_LoginSuccess},
-  raw_packet::{RawPacketReader, RawPacketWriter}
+  packets::{
+    SB_LoginStart, Packet, ServerBoundPacket, ConnectionState,
+    CB_EncryptionRequest, CB_LoginSuccess, CB_SetCompression
+  },
+  raw_packet::{RawPacketReader, RawPacketWriter, PacketStream},
+  mc_dtypes::MCUuid
 };
 
-pub async fn handle_package(mut raw_pck: RawPacketReader, stream: &mut TcpStream)
-  -> String
-{
-  //(1) Decode the package
-  let login_req = SB_LoginStart::decode(&mut raw_pck).unwrap();
+use crate::client::auth::{OnlineMode, GameProfile, AuthError};
 
-  //For now, we'll forgo encryption. Just get the username and think of an UUID
+use super::x01_encryption;
+
+pub async fn handle_package(
+  login_req: SB_LoginStart,
+  stream: &mut PacketStream,
+  online: Option<&OnlineMode>
+)
+  -> Result<GameProfile, Box<dyn Error>>
+{
+  //(1) The client tells us who it claims to be
   let username = login_req.player_name;
-  let uuid = rand::thread_rng().gen::<u128>();
 
-  //(2) Reply with a Login Success packet
-  let rsp = CB_LoginSuccess{uuid: uuid.clone(), player_name: username.clone()};
+  //(2) Find out who this player is
+  let profile = match online {
+    Some(online) => {
+      //(2a) Online mode: ask for encryption and let the session server
+      //authenticate the player
+      let verify_token = online.new_verify_token();
+      let request = CB_EncryptionRequest{
+        server_id: String::new(),
+        public_key: online.public_key_der().to_vec(),
+        token: verify_token.clone()
+      };
+      let mut writer = RawPacketWriter::new(request.packet_id());
+      request.encode(&mut writer);
+      writer.write(stream).await?;
+
+      //The client must respond with an Encryption Response
+      let mut raw_rsp = RawPacketReader::read(stream).await?;
+      let response = match ServerBoundPacket::decode(ConnectionState::Login, &mut raw_rsp)? {
+        ServerBoundPacket::EncryptionResponse(response) => response,
+        other => return Err(AuthError::from(format!(
+          "expected encryption response, got opcode {:#04x}", other.packet_id()
+        )).into())
+      };
+      x01_encryption::handle_package(response, stream, online, &verify_token, &username).await?
+    },
+    None => {
+      //(2b) Offline mode: just take the username and derive the UUID from it
+      //like vanilla does, so that a player keeps the same UUID between joins
+      GameProfile {
+        uuid: MCUuid::offline_player(&username).into(),
+        name: username,
+        properties: Vec::new()
+      }
+    }
+  };
+
+  //(3) Enable compression if the config asks for it. The Set Compression
+  //packet itself is still sent uncompressed
+  let threshold = crate::config::copy_config().network_settings.compression_threshold;
+  if threshold >= 0 {
+    let set_compression = CB_SetCompression{threshold_len: threshold as usize};
+    let mut writer = RawPacketWriter::new(set_compression.packet_id());
+    set_compression.encode(&mut writer);
+    writer.write(stream).await?;
+    stream.set_compression(Some(threshold as usize));
+  }
+
+  //(4) Reply with a Login Success packet
+  let rsp = CB_LoginSuccess{uuid: profile.uuid, player_name: profile.name.clone()};
   let mut writer = RawPacketWriter::new(rsp.packet_id());
   rsp.encode(&mut writer);
-  writer.write(stream).await.unwrap();
+  writer.write(stream).await?;
 
-  //(3) Return the player's name
-  return username;
-}
\ No newline at end of file
+  //(R) Return the player's profile
+  Ok(profile)
+}
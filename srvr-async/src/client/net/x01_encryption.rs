@@ -0,0 +1,60 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::error::Error;
+
+use srvr_sysproto::{
+  packets::SB_EncryptionResponse,
+  raw_packet::PacketStream,
+  encryption::server_hash
+};
+
+use crate::client::auth::{OnlineMode, GameProfile, AuthError};
+
+pub async fn handle_package(
+  response: SB_EncryptionResponse,
+  stream: &mut PacketStream,
+  online: &OnlineMode,
+  verify_token: &[u8],
+  username: &str
+)
+  -> Result<GameProfile, Box<dyn Error>>
+{
+  //(1) Both the secret and the token were encrypted with our public key. The
+  //token must be the one we sent, otherwise someone is messing with us
+  let shared_secret = online.decrypt(&response.secret_key)?;
+  if online.decrypt(&response.token)? != verify_token {
+    return Err(AuthError::from("verify token does not match").into());
+  }
+
+  //(2) From here on out, everything is encrypted with the shared secret
+  stream.enable_encryption(&shared_secret)?;
+
+  //(3) Ask the session server if the player is who they claim to be
+  let hash = server_hash("", &shared_secret, online.public_key_der());
+  let profile = online.authenticator().has_joined(username, &hash).await?;
+
+  //(R) The profile we got back from the session server
+  Ok(profile)
+}
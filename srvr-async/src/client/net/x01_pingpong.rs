@@ -23,16 +23,14 @@
 */
 
 use log::trace;
-use tokio::net::TcpStream;
 
 use srvr_sysproto::{
   packets::{Packet, SB_Ping, CB_Pong},
-  raw_packet::{RawPacketReader, RawPacketWriter}
+  raw_packet::{RawPacketWriter, PacketStream}
 };
 
-pub async fn handle_package(mut raw_pck: RawPacketReader, stream: &mut TcpStream) {
-  //(1) Decode ping packet
-  let ping = SB_Ping::decode(&mut raw_pck).unwrap();
+pub async fn handle_package(ping: SB_Ping, stream: &mut PacketStream) {
+  //(1) Log ping packet
   trace!("{ping:?}");
 
   //(2) Return pong packet
@@ -22,23 +22,47 @@
   text of the license in any official language of the European Union.
 */
 
-use std::net::TcpStream;
+use std::time::Duration;
 
-use super::PackageHandler;
-
-use crate::task::Task;
+use log::trace;
+use tokio::{io::AsyncWriteExt, time::timeout};
 
 use srvr_sysproto::{
-  packets::{Packet, SB_Ping, CB_Pong},
-  raw_packet::{RawPacketReader, RawPacketWriter}
+  packets::{CB_LegacyKick, LEGACY_PING_ID},
+  raw_packet::PacketStream
 };
 
-#[derive(Debug)]
-pub struct Handler;
+//1.4+ clients send at least 0xFE 0x01 in one go. If the second byte doesn't
+//show up within this time, we're dealing with a pre-1.4 client
+const LEGACY_PING_TIMEOUT: Duration = Duration::from_millis(100);
+const LEGACY_PING_V1_4: u8 = 0x01;
+
+pub fn is_legacy_ping(first_byte: u8) -> bool {
+  /*
+    A modern handshake starts with its VarInt length. 0xFE would be the first
+    byte of a length of at least 254, which no real handshake ever reaches, so
+    vanilla uses the first byte to tell the two apart. We do the same.
+  */
+  first_byte == LEGACY_PING_ID
+}
+
+pub async fn handle_package(stream: &mut PacketStream, status: CB_LegacyKick) {
+  //(1) Figure out which variant of the legacy ping this is. We never read the
+  //1.6 MC|PingHost payload since the response does not depend on it.
+  let is_v1_4 = match timeout(LEGACY_PING_TIMEOUT, stream.peek(2)).await {
+    Ok(Ok(bytes)) => bytes[1] == LEGACY_PING_V1_4,
+    _ => false
+  };
+  trace!("Legacy ping (1.4+ format: {is_v1_4})");
 
-impl PackageHandler for Handler {
-  fn handle_package(_: RawPacketReader, _: &mut TcpStream) -> Vec<Task> {
-    println!("Serverlist ping");
-    vec![Task::DoNothing]
+  //(2) Answer with the kick packet in the matching format. The client closes
+  //the connection after reading it
+  let response = match is_v1_4 {
+    true => status.encode_v1_4(),
+    false => status.encode_pre_v1_4()
+  };
+  let conn = stream.get_mut();
+  if conn.write_all(&response).await.is_ok() {
+    let _ = conn.flush().await;
   }
-}
\ No newline at end of file
+}
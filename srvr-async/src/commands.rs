@@ -0,0 +1,316 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::{
+  collections::BTreeMap,
+  error::Error,
+  fmt::{Debug, Display, Formatter}
+};
+
+use srvr_sysplugin::{EventBus, Event, ffi::FfiStr};
+
+use crate::messages::client_request::{CReqMsg, CReqRsp};
+
+mod args;
+pub use args::{ArgKind, ArgSpec, Args};
+
+mod builtin;
+
+/*(Note to future self)
+  A command turns a line of text into a [CReqMsg] for the server manager, so
+  it doesn't matter who typed it: the console now, players (through /commands)
+  later. The registry only parses, the manager decides what actually happens.
+*/
+
+type Handler = Box<dyn Fn(&Args) -> Result<CReqMsg, String> + Send + Sync>;
+
+pub struct Command {
+  name: String,
+  description: String,
+  args: Vec<ArgSpec>,
+  handler: Handler
+}
+
+impl Command {
+
+  pub fn builder(name: &str, description: &str) -> CommandBuilder {
+    CommandBuilder {
+      name: name.to_string(),
+      description: description.to_string(),
+      args: Vec::new()
+    }
+  }
+
+  pub fn usage(&self) -> String {
+    let mut usage = self.name.clone();
+    for arg in &self.args {
+      usage.push(' ');
+      usage.push_str(&arg.to_string());
+    }
+    usage
+  }
+
+}
+
+pub struct CommandBuilder {
+  name: String,
+  description: String,
+  args: Vec<ArgSpec>
+}
+
+impl CommandBuilder {
+
+  pub fn arg(mut self, name: &str, kind: ArgKind) -> Self {
+    self.args.push(ArgSpec {name: name.to_string(), kind, optional: false});
+    self
+  }
+
+  pub fn optional(mut self, name: &str, kind: ArgKind) -> Self {
+    self.args.push(ArgSpec {name: name.to_string(), kind, optional: true});
+    self
+  }
+
+  pub fn build<F>(self, handler: F) -> Command
+    where F: Fn(&Args) -> Result<CReqMsg, String> + Send + Sync + 'static
+  {
+    Command {
+      name: self.name,
+      description: self.description,
+      args: self.args,
+      handler: Box::new(handler)
+    }
+  }
+
+}
+
+//What a line of input asks for
+#[derive(Debug)]
+pub enum Invocation {
+  //Something the server manager has to do
+  Request(CReqMsg),
+  //Help text, which the registry can answer by itself
+  Help(Vec<String>)
+}
+
+//Names the completer can choose from, the registry doesn't know who is online
+#[derive(Debug, Default)]
+pub struct CompletionContext {
+  pub players: Vec<String>,
+  pub worlds: Vec<String>
+}
+
+#[derive(Default)]
+pub struct CommandRegistry {
+  commands: BTreeMap<String, Command>
+}
+
+impl Debug for CommandRegistry {
+  fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+    //The handlers can't be printed, their names will do
+    f.debug_set().entries(self.commands.keys()).finish()
+  }
+}
+
+impl CommandRegistry {
+
+  pub fn new() -> Self {Self::default()}
+
+  pub fn with_builtins() -> Self {
+    let mut registry = Self::new();
+    for command in builtin::commands() {
+      registry.register(command);
+    }
+    registry
+  }
+
+  pub fn register(&mut self, command: Command) {
+    self.commands.insert(command.name.clone(), command);
+  }
+
+  pub fn parse(&self, line: &str) -> Result<Invocation, CommandError> {
+    //(1) The first word is the command, the rest are its arguments
+    let line = line.trim();
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    if name.is_empty() {
+      return Err(CommandError::Empty);
+    }
+
+    //(2) Help is answered right away
+    if name == "help" {
+      return self.help(rest.trim()).map(Invocation::Help);
+    }
+
+    //(3) Parse the arguments and let the command build the request
+    let command = self.commands.get(name)
+      .ok_or_else(|| CommandError::Unknown(name.to_string()))?;
+    let usage = || command.usage();
+    let args = Args::parse(&command.args, rest)
+      .map_err(|err| CommandError::BadArguments{reason: err.to_string(), usage: usage()})?;
+    (command.handler)(&args)
+      .map(Invocation::Request)
+      .map_err(|reason| CommandError::BadArguments{reason, usage: usage()})
+  }
+
+  pub fn help(&self, name: &str) -> Result<Vec<String>, CommandError> {
+    //Without a name we list every command, otherwise just the one
+    if name.is_empty() {
+      let mut lines = vec!["help [command]: Show how to use the commands".to_string()];
+      lines.extend(self.commands.values()
+        .map(|command| format!("{}: {}", command.usage(), command.description)));
+      return Ok(lines);
+    }
+    match self.commands.get(name) {
+      Some(command) => Ok(vec![
+        format!("Usage: {}", command.usage()),
+        command.description.clone()
+      ]),
+      None => Err(CommandError::Unknown(name.to_string()))
+    }
+  }
+
+  pub fn complete(&self, line: &str, context: &CompletionContext) -> Vec<String> {
+    //(1) Find the word that is being typed, and its position on the line
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (position, partial) = match line.ends_with(char::is_whitespace) || words.is_empty() {
+      true => (words.len(), ""),
+      false => (words.len() - 1, words[words.len() - 1])
+    };
+
+    //(2) The first word is a command (or help, which takes one)
+    let command_names = || -> Vec<&str> {
+      let mut names: Vec<&str> = self.commands.keys().map(String::as_str).collect();
+      names.push("help");
+      names
+    };
+    let candidates: Vec<&str> = match position {
+      0 => command_names(),
+      1 if words[0] == "help" => command_names(),
+      _ => {
+        //(3) Otherwise we complete the argument at this position
+        let spec = self.commands.get(words[0])
+          .and_then(|command| command.args.get(position - 1));
+        match spec.map(|spec| spec.kind) {
+          Some(ArgKind::Player) => context.players.iter().map(String::as_str).collect(),
+          Some(ArgKind::World) => context.worlds.iter().map(String::as_str).collect(),
+          _ => Vec::new()
+        }
+      }
+    };
+
+    //(R) Only keep the candidates that fit what was typed so far
+    let partial = partial.to_lowercase();
+    let mut matches: Vec<String> = candidates.into_iter()
+      .filter(|candidate| candidate.to_lowercase().starts_with(&partial))
+      .map(str::to_string)
+      .collect();
+    matches.sort();
+    matches
+  }
+
+}
+
+//Plugins see every command first, and returns true if one of them took it
+pub fn dispatch_command(events: &EventBus, sender: &str, line: &str) -> bool {
+  let line = line.trim();
+  !line.is_empty() && events.dispatch(Event::Command{sender: FfiStr::new(sender), line: FfiStr::new(line)})
+}
+
+//The answer to a command as text, one entry per line
+pub fn describe_response(rsp: CReqRsp) -> Vec<String> {
+  match rsp {
+    CReqRsp::Done => Vec::new(),
+    CReqRsp::Players(players) => {
+      let names: Vec<&str> = players.iter().map(|player| player.name.as_str()).collect();
+      vec![format!("There are {} players online: {}", players.len(), names.join(", "))]
+    },
+    CReqRsp::Worlds(worlds) => vec![format!("Worlds ({}): {}", worlds.len(), worlds.join(", "))],
+    CReqRsp::Plugins(plugins) => vec![format!("Plugins ({}): {}", plugins.len(), plugins.join(", "))],
+    CReqRsp::Lines(lines) => lines,
+    other => vec![format!("Unexpected answer from the server: {other:?}")]
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+  Empty,
+  Unknown(String),
+  BadArguments{reason: String, usage: String}
+}
+
+impl Display for CommandError {
+  fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+    match self {
+      CommandError::Empty => write!(f, "No command given, try \"help\""),
+      CommandError::Unknown(name) => write!(f, "Unknown command \"{name}\", try \"help\""),
+      CommandError::BadArguments{reason, usage} => write!(f, "{reason}. Usage: {usage}")
+    }
+  }
+}
+impl Error for CommandError {}
+
+#[cfg(test)]
+mod commands_test {
+  use super::*;
+
+  #[test]
+  fn parse_builtins() {
+    let registry = CommandRegistry::with_builtins();
+    let kick = registry.parse("kick Notch  being   mean").unwrap();
+    assert!(matches!(kick,
+      Invocation::Request(CReqMsg::Kick{name, reason: Some(reason)})
+        if name == "Notch" && reason == "being   mean"
+    ));
+    assert!(matches!(registry.parse("list"), Ok(Invocation::Request(CReqMsg::ListPlayers))));
+    assert!(matches!(registry.parse("help kick"), Ok(Invocation::Help(lines)) if lines.len() == 2));
+    assert!(matches!(registry.parse("plugins"), Ok(Invocation::Request(CReqMsg::ListPlugins))));
+    assert!(matches!(registry.parse("plugins reload Sample Plugin"),
+      Ok(Invocation::Request(CReqMsg::ReloadPlugin{name})) if name == "Sample Plugin"
+    ));
+  }
+
+  #[test]
+  fn parse_errors() {
+    let registry = CommandRegistry::with_builtins();
+    assert_eq!(registry.parse("   ").unwrap_err(), CommandError::Empty);
+    assert_eq!(registry.parse("fly").unwrap_err(), CommandError::Unknown("fly".to_string()));
+    let err = registry.parse("tp Notch lobby 1 2").unwrap_err();
+    assert!(matches!(err, CommandError::BadArguments{usage, ..} if usage == "tp <player> <world> [x] [y] [z]"));
+    assert!(matches!(registry.parse("plugins reload"), Err(CommandError::BadArguments{..})));
+  }
+
+  #[test]
+  fn completion() {
+    let registry = CommandRegistry::with_builtins();
+    let context = CompletionContext {
+      players: vec!["Notch".to_string(), "jeb_".to_string(), "Nathan".to_string()],
+      worlds: vec!["lobby".to_string(), "nether".to_string()]
+    };
+    assert_eq!(registry.complete("sa", &context), vec!["save-all", "say"]);
+    assert_eq!(registry.complete("help w", &context), vec!["worlds"]);
+    assert_eq!(registry.complete("kick n", &context), vec!["Nathan", "Notch"]);
+    assert_eq!(registry.complete("tp Notch ", &context), vec!["lobby", "nether"]);
+    assert!(registry.complete("say n", &context).is_empty());
+  }
+
+}
@@ -0,0 +1,249 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::{
+  collections::HashMap,
+  error::Error,
+  fmt::{Display, Formatter},
+  time::Duration
+};
+
+//One game tick, durations may be given in ticks as well
+const TICK: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+  //A (syntactically valid) player name
+  Player,
+  //The name of a world
+  World,
+  Integer,
+  //A number with a unit, like "30s", "5m" or "20t"
+  Duration,
+  //Everything that is left of the line, must be the last argument
+  Text
+}
+
+impl ArgKind {
+  pub fn parse(&self, token: &str) -> Result<ArgValue, ArgError> {
+    match self {
+      ArgKind::Player => match is_valid_username(token) {
+        true => Ok(ArgValue::Player(token.to_string())),
+        false => Err(ArgError::Invalid(format!("\"{token}\" is not a valid player name")))
+      },
+      ArgKind::World => Ok(ArgValue::World(token.to_string())),
+      ArgKind::Integer => token.parse()
+        .map(ArgValue::Integer)
+        .map_err(|_| ArgError::Invalid(format!("\"{token}\" is not a whole number"))),
+      ArgKind::Duration => parse_duration(token)
+        .map(ArgValue::Duration)
+        .ok_or_else(|| ArgError::Invalid(format!("\"{token}\" is not a duration (try 30s, 5m or 1h)"))),
+      ArgKind::Text => Ok(ArgValue::Text(token.to_string()))
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgValue {
+  Player(String),
+  World(String),
+  Integer(i32),
+  Duration(Duration),
+  Text(String)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgSpec {
+  pub name: String,
+  pub kind: ArgKind,
+  pub optional: bool
+}
+
+impl Display for ArgSpec {
+  fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+    //Usage notation: <required>, [optional] and ... for the rest of the line
+    let rest = if self.kind == ArgKind::Text {"..."} else {""};
+    match self.optional {
+      true => write!(f, "[{}{rest}]", self.name),
+      false => write!(f, "<{}{rest}>", self.name)
+    }
+  }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+  values: HashMap<String, ArgValue>
+}
+
+impl Args {
+
+  pub fn parse(specs: &[ArgSpec], line: &str) -> Result<Self, ArgError> {
+    //(1) Split the line into words, keeping track of where each one starts so
+    //that text arguments can take the rest of the line verbatim
+    let mut rest = line.trim_start();
+    let mut values = HashMap::new();
+
+    //(2) Match every word to the argument at the same position
+    for spec in specs {
+      if rest.is_empty() {
+        match spec.optional {
+          true => continue,
+          false => return Err(ArgError::Missing(spec.name.clone()))
+        }
+      }
+      let token = match spec.kind {
+        ArgKind::Text => std::mem::take(&mut rest).trim_end(),
+        _ => {
+          let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+          let (token, tail) = rest.split_at(end);
+          rest = tail.trim_start();
+          token
+        }
+      };
+      values.insert(spec.name.clone(), spec.kind.parse(token)?);
+    }
+
+    //(R) Anything that's left over doesn't belong to any argument
+    match rest.is_empty() {
+      true => Ok(Args {values}),
+      false => Err(ArgError::TooMany(rest.to_string()))
+    }
+  }
+
+  pub fn text(&self, name: &str) -> Option<&str> {
+    match self.values.get(name)? {
+      ArgValue::Player(text) | ArgValue::World(text) | ArgValue::Text(text) => Some(text),
+      _ => None
+    }
+  }
+
+  pub fn integer(&self, name: &str) -> Option<i32> {
+    match self.values.get(name)? {
+      ArgValue::Integer(integer) => Some(*integer),
+      _ => None
+    }
+  }
+
+  pub fn duration(&self, name: &str) -> Option<Duration> {
+    match self.values.get(name)? {
+      ArgValue::Duration(duration) => Some(*duration),
+      _ => None
+    }
+  }
+
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgError {
+  Missing(String),
+  Invalid(String),
+  TooMany(String)
+}
+
+impl Display for ArgError {
+  fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+    match self {
+      ArgError::Missing(name) => write!(f, "Missing argument <{name}>"),
+      ArgError::Invalid(reason) => write!(f, "{reason}"),
+      ArgError::TooMany(rest) => write!(f, "Too many arguments: \"{rest}\"")
+    }
+  }
+}
+impl Error for ArgError {}
+
+pub fn is_valid_username(name: &str) -> bool {
+  //Vanilla names are 3 to 16 letters, digits and underscores
+  (3..=16).contains(&name.len())
+    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub fn parse_duration(token: &str) -> Option<Duration> {
+  //(1) Split the number from its unit, no unit means seconds
+  let split = token.find(|c: char| !c.is_ascii_digit()).unwrap_or(token.len());
+  let (number, unit) = token.split_at(split);
+  let number: u32 = number.parse().ok()?;
+
+  //(R) Scale the number by the unit
+  let unit = match unit {
+    "t" => TICK,
+    "" | "s" => Duration::from_secs(1),
+    "m" => Duration::from_secs(60),
+    "h" => Duration::from_secs(60 * 60),
+    "d" => Duration::from_secs(24 * 60 * 60),
+    _ => return None
+  };
+  Some(unit * number)
+}
+
+#[cfg(test)]
+mod args_test {
+  use super::*;
+
+  fn spec(name: &str, kind: ArgKind, optional: bool) -> ArgSpec {
+    ArgSpec {name: name.to_string(), kind, optional}
+  }
+
+  #[test]
+  fn durations() {
+    assert_eq!(parse_duration("20t"), Some(Duration::from_secs(1)));
+    assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+    assert_eq!(parse_duration("5m"), Some(Duration::from_secs(300)));
+    assert_eq!(parse_duration("2d"), Some(Duration::from_secs(172_800)));
+    assert_eq!(parse_duration("m"), None);
+    assert_eq!(parse_duration("5y"), None);
+  }
+
+  #[test]
+  fn parse_args() {
+    let specs = [
+      spec("player", ArgKind::Player, false),
+      spec("x", ArgKind::Integer, true),
+      spec("reason", ArgKind::Text, true)
+    ];
+    let args = Args::parse(&specs, "  Notch -12   spamming  the chat ").unwrap();
+    assert_eq!(args.text("player"), Some("Notch"));
+    assert_eq!(args.integer("x"), Some(-12));
+    assert_eq!(args.text("reason"), Some("spamming  the chat"));
+
+    let args = Args::parse(&specs, "Notch").unwrap();
+    assert_eq!(args.integer("x"), None);
+    assert_eq!(args.text("reason"), None);
+  }
+
+  #[test]
+  fn bad_args() {
+    let specs = [spec("player", ArgKind::Player, false), spec("x", ArgKind::Integer, true)];
+    assert_eq!(Args::parse(&specs, ""), Err(ArgError::Missing("player".to_string())));
+    assert!(matches!(Args::parse(&specs, "No!"), Err(ArgError::Invalid(_))));
+    assert!(matches!(Args::parse(&specs, "Notch ten"), Err(ArgError::Invalid(_))));
+    assert_eq!(Args::parse(&specs, "Notch 1 2"), Err(ArgError::TooMany("2".to_string())));
+  }
+
+  #[test]
+  fn usage() {
+    assert_eq!(spec("player", ArgKind::Player, false).to_string(), "<player>");
+    assert_eq!(spec("reason", ArgKind::Text, true).to_string(), "[reason...]");
+  }
+
+}
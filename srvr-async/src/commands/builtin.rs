@@ -0,0 +1,88 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use crate::messages::client_request::CReqMsg;
+
+use super::{Command, ArgKind};
+
+//The commands every server has
+pub fn commands() -> Vec<Command> {
+  vec![
+    Command::builder("stop", "Stop the server, optionally after a delay")
+      .optional("delay", ArgKind::Duration)
+      .build(|args| Ok(match args.duration("delay") {
+        Some(delay) => CReqMsg::StopIn{delay},
+        None => CReqMsg::ConsoleKill
+      })),
+    Command::builder("list", "List the players that are online")
+      .build(|_| Ok(CReqMsg::ListPlayers)),
+    Command::builder("kick", "Disconnect a player from the server")
+      .arg("player", ArgKind::Player)
+      .optional("reason", ArgKind::Text)
+      .build(|args| Ok(CReqMsg::Kick{
+        name: args.text("player").unwrap_or_default().to_string(),
+        reason: args.text("reason").map(str::to_string)
+      })),
+    Command::builder("say", "Send a message to every player")
+      .arg("message", ArgKind::Text)
+      .build(|args| Ok(CReqMsg::Say{
+        message: args.text("message").unwrap_or_default().to_string()
+      })),
+    Command::builder("tp", "Move a player to a world, and to a block in it")
+      .arg("player", ArgKind::Player)
+      .arg("world", ArgKind::World)
+      .optional("x", ArgKind::Integer)
+      .optional("y", ArgKind::Integer)
+      .optional("z", ArgKind::Integer)
+      .build(|args| {
+        //Either all three coordinates or none of them
+        let position = match (args.integer("x"), args.integer("y"), args.integer("z")) {
+          (Some(x), Some(y), Some(z)) => Some((x, y, z)),
+          (None, None, None) => None,
+          _ => return Err("Give all three coordinates or none".to_string())
+        };
+        Ok(CReqMsg::Teleport{
+          name: args.text("player").unwrap_or_default().to_string(),
+          world: args.text("world").unwrap_or_default().to_string(),
+          position
+        })
+      }),
+    Command::builder("save-all", "Save every world to disk")
+      .build(|_| Ok(CReqMsg::SaveAll)),
+    Command::builder("worlds", "List the worlds that are loaded")
+      .build(|_| Ok(CReqMsg::ListWorlds)),
+    Command::builder("plugins", "List the plugins that are loaded, or \"reload <plugin>\" one")
+      .optional("action", ArgKind::Text)
+      .build(|args| match args.text("action") {
+        None => Ok(CReqMsg::ListPlugins),
+        //Plugin names may have spaces, so the name is the rest of the line
+        Some(action) => match action.split_once(char::is_whitespace) {
+          Some(("reload", name)) => Ok(CReqMsg::ReloadPlugin{name: name.trim().to_string()}),
+          _ => Err(format!("\"{action}\" is not something plugins can do, try \"reload <plugin>\""))
+        }
+      }),
+    Command::builder("reload", "Reload the config file")
+      .build(|_| Ok(CReqMsg::Reload))
+  ]
+}
@@ -22,7 +22,7 @@
   text of the license in any official language of the European Union.
 */
 
-use std::{fs::File, io::Read, error::Error};
+use std::{collections::HashMap, fs::File, io::Read, error::Error};
 
 use serde::{Serialize, Deserialize};
 
@@ -46,8 +46,14 @@ pub fn copy_config() -> Config {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
   pub general_settings: GeneralSettings,
+  #[serde(default)]
+  pub server_settings: ServerSettings,
   pub network_settings: NetworkSettings,
-  pub world_settings: WorldSettings
+  pub world_settings: WorldSettings,
+  #[serde(default)]
+  pub permission_settings: PermissionSettings,
+  #[serde(default)]
+  pub plugin_settings: PluginSettings
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -57,10 +63,62 @@ pub struct GeneralSettings {
   pub stack_size: usize
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerSettings {
+  #[serde(default = "default_motd")]
+  pub motd: String,
+  #[serde(default = "default_max_players")]
+  pub max_players: usize,
+  //Path to a 64x64 PNG shown in the server list (optional)
+  pub favicon: Option<String>,
+  //Reason shown to players that are online when the server stops
+  #[serde(default = "default_shutdown_message")]
+  pub shutdown_message: String,
+  //Seconds we wait for clients to disconnect before stopping anyway
+  #[serde(default = "default_shutdown_timeout")]
+  pub shutdown_timeout: u64
+}
+
+impl Default for ServerSettings {
+  fn default() -> Self {
+    ServerSettings {
+      motd: default_motd(),
+      max_players: default_max_players(),
+      favicon: None,
+      shutdown_message: default_shutdown_message(),
+      shutdown_timeout: default_shutdown_timeout()
+    }
+  }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkSettings {
   pub ip: [u8; 4],
-  pub port: u16
+  pub port: u16,
+  //Authenticate players with the session server and encrypt the connection
+  #[serde(default)]
+  pub online_mode: bool,
+  //Packets of at least this many bytes are compressed, negative disables
+  #[serde(default = "default_compression_threshold")]
+  pub compression_threshold: i32,
+  //Seconds a new connection gets to finish the handshake and login
+  #[serde(default = "default_handshake_timeout")]
+  pub handshake_timeout: u64,
+  //Connections that are still in the handshake or login, new ones are refused
+  #[serde(default = "default_max_pending_connections")]
+  pub max_pending_connections: usize,
+  //Remote console, listens on the same ip as the server
+  #[serde(default)]
+  pub rcon_enabled: bool,
+  #[serde(default = "default_rcon_port")]
+  pub rcon_port: u16,
+  #[serde(default)]
+  pub rcon_password: String,
+  //GameSpy4 query over UDP, for server lists and monitoring
+  #[serde(default)]
+  pub query_enabled: bool,
+  #[serde(default = "default_query_port")]
+  pub query_port: u16
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -74,4 +132,125 @@ pub struct World {
   pub name: String,
   pub file_name: String,
   pub generator: String
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PermissionSettings {
+  //Players that have every permission
+  pub operators: Vec<String>,
+  //Permission nodes every player has, like "plugin.command"
+  pub default: Vec<String>,
+  //Extra nodes by player name, "plugin.*" grants everything under "plugin."
+  pub players: HashMap<String, Vec<String>>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PluginSettings {
+  //Fuel a WebAssembly plugin gets for every event, command and task. Most
+  //instructions cost one
+  pub wasm_fuel: u64,
+  //Memory a WebAssembly plugin may use, in MiB
+  pub wasm_memory: usize
+}
+
+impl Default for PluginSettings {
+  fn default() -> Self {
+    PluginSettings {wasm_fuel: 10_000_000, wasm_memory: 16}
+  }
+}
+
+/*(Note to future self)
+  Settings added after the first release need a default here, or config files
+  written before them stop loading. Keep the defaults equal to config.toml
+*/
+fn default_compression_threshold() -> i32 { 256 }
+fn default_handshake_timeout() -> u64 { 30 }
+fn default_max_pending_connections() -> usize { 64 }
+fn default_motd() -> String { "Hello world".to_string() }
+fn default_max_players() -> usize { 1000 }
+fn default_shutdown_message() -> String { "Server closed".to_string() }
+fn default_shutdown_timeout() -> u64 { 10 }
+fn default_rcon_port() -> u16 { 25575 }
+fn default_query_port() -> u16 { 25565 }
+
+#[cfg(test)]
+mod config_test {
+  use super::*;
+
+  //The shipped config without the lines that start with one of `removed`, like
+  //a config file written before those settings existed
+  fn shipped_without(removed: &[&str]) -> Config {
+    let config: String = include_str!("../../config.toml").lines()
+      .filter(|line| !removed.iter().any(|prefix| line.trim_start().starts_with(prefix)))
+      .map(|line| format!("{line}\n"))
+      .collect();
+    toml::from_str(&config).unwrap()
+  }
+
+  #[test]
+  fn compression_threshold_default() {
+    let config = shipped_without(&["compression_threshold"]);
+    assert_eq!(config.network_settings.compression_threshold, 256);
+  }
+
+  #[test]
+  fn online_mode_default() {
+    let config = shipped_without(&["online_mode"]);
+    assert!(!config.network_settings.online_mode);
+  }
+
+  #[test]
+  fn acceptor_defaults() {
+    let config = shipped_without(&["handshake_timeout", "max_pending_connections"]);
+    assert_eq!(config.network_settings.handshake_timeout, 30);
+    assert_eq!(config.network_settings.max_pending_connections, 64);
+  }
+
+  #[test]
+  fn shutdown_defaults() {
+    let config = shipped_without(&["shutdown_"]);
+    assert_eq!(config.server_settings.shutdown_message, "Server closed");
+    assert_eq!(config.server_settings.shutdown_timeout, 10);
+  }
+
+  #[test]
+  fn rcon_defaults() {
+    let config = shipped_without(&["rcon_"]);
+    assert!(!config.network_settings.rcon_enabled);
+    assert_eq!(config.network_settings.rcon_port, 25575);
+    assert_eq!(config.network_settings.rcon_password, "");
+  }
+
+  #[test]
+  fn query_defaults() {
+    let config = shipped_without(&["query_"]);
+    assert!(!config.network_settings.query_enabled);
+    assert_eq!(config.network_settings.query_port, 25565);
+  }
+
+  #[test]
+  fn server_settings_default() {
+    let config = shipped_without(&["[server_settings]", "motd", "max_players", "#favicon", "shutdown_"]);
+    assert_eq!(config.server_settings.motd, "Hello world");
+    assert_eq!(config.server_settings.max_players, 1000);
+    assert_eq!(config.server_settings.favicon, None);
+    assert_eq!(config.server_settings.shutdown_timeout, 10);
+  }
+
+  #[test]
+  fn permission_settings_default() {
+    let config = shipped_without(&["[permission_settings", "operators", "default = []"]);
+    assert!(config.permission_settings.operators.is_empty());
+    assert!(config.permission_settings.default.is_empty());
+    assert!(config.permission_settings.players.is_empty());
+  }
+
+  #[test]
+  fn plugin_settings_default() {
+    let config = shipped_without(&["[plugin_settings]", "wasm_"]);
+    assert_eq!(config.plugin_settings.wasm_fuel, 10_000_000);
+    assert_eq!(config.plugin_settings.wasm_memory, 16);
+  }
+}
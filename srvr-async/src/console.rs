@@ -24,91 +24,272 @@
 
 use std::{
   thread,
-  time::Duration, io::stdin
+  io::{stdin, BufRead, IsTerminal},
+  sync::Arc
 };
 
-use log::warn;
+use log::{info, warn};
+use rustyline::{
+  Editor, Helper, Context,
+  completion::Completer,
+  config::Configurer,
+  error::ReadlineError,
+  highlight::Highlighter,
+  hint::Hinter,
+  history::FileHistory,
+  validate::Validator
+};
+use srvr_sysplugin::EventBus;
 use tokio::sync::mpsc;
 
-use crate::messages::client_request::{ClientRequest, CReqMsg, CReqRsp, CReqDenied};
+use crate::{
+  messages::client_request::{ClientRequest, CReqMsg, CReqRsp, CReqDenied, CommandSource},
+  commands::{CommandRegistry, CommandError, CompletionContext, Invocation, describe_response, dispatch_command},
+  logger
+};
 
-const CONSOLE_TICK: Duration = Duration::from_millis(100);
+const PROMPT: &str = "> ";
+const MAX_HISTORY: usize = 1000;
 
 pub struct Console {
   server_handle: mpsc::Sender<ClientRequest>,
-  stdin_buf: String,
-  buf_parser: fn(&'_ mut String)
+  commands: Arc<CommandRegistry>,
+  events: Arc<EventBus>
 }
 
 impl Console {
 
-  pub fn init(server_handle: mpsc::Sender<ClientRequest>) -> Self {
-    //(1) Get the OS that we are running on
-    let parser = match std::env::consts::OS {
-      "linux" => stdin_parse_linux,
-      "windows" => stdin_parse_windows,
-      _ => stdin_parse_linux
-    };
-
-    //(2) Make a console
-    Console {
-      server_handle: server_handle,
-      stdin_buf: String::new(),
-      buf_parser: parser
-    }
+  pub fn init(
+    server_handle: mpsc::Sender<ClientRequest>,
+    commands: Arc<CommandRegistry>,
+    events: Arc<EventBus>
+  ) -> Self {
+    Console {server_handle, commands, events}
   }
 
-  pub fn run(mut self) {
+  pub fn run(self) {
     /*
       Sadly, stdin is always blocking on most platforms.
       Therefore, we have to run the console on a separate thread from the tokio
       runtime. It is responsible for shutting itself down.
     */
-    use CReqMsg::*;
-
     thread::spawn(move || {
-      'console_tick: loop {
-        //(1) Read from stdin
-        if let Ok(_) = stdin().read_line(&mut self.stdin_buf) {
-          //Parse the buffer before matching
-          (self.buf_parser)(&mut self.stdin_buf);
-
-          //Try to execute the command
-          match self.stdin_buf.as_str() {
-            "stop" => {
-              //Tell the server to stop
-              self.send_msg(ConsoleKill).unwrap();
-              //Then stop ourselves
-              break 'console_tick;
-            },
-            other => warn!("Unknown command \"{other}\"")
-          }
-        };
-        //Clean the buffer lol
-        self.stdin_buf = String::new();
-        thread::sleep(CONSOLE_TICK);
+      //Line editing only makes sense when someone is typing at a terminal,
+      //under systemd (or with a pipe) we just read lines
+      if stdin().is_terminal() {
+        match self.run_interactive() {
+          Ok(()) => return,
+          Err(err) => warn!("Could not start the interactive console (reason: \"{err}\"), falling back to plain input")
+        }
       }
+      self.run_plain(stdin().lock());
     });
   }
 
-  fn send_msg(&mut self, msg: CReqMsg) -> Result<CReqRsp, CReqDenied>{
-    tokio::runtime::Builder::new_current_thread()
-      .build()
-      .unwrap()
-      .block_on(
-        ClientRequest::send(msg, self.server_handle.clone())
-      )
+  fn run_interactive(&self) -> Result<(), ReadlineError> {
+    //(1) Set up the line editor with the history of earlier sessions
+    let mut editor: Editor<ConsoleHelper, FileHistory> = Editor::new()?;
+    editor.set_max_history_size(MAX_HISTORY)?;
+    editor.set_auto_add_history(false);
+    editor.set_helper(Some(ConsoleHelper {
+      commands: self.commands.clone(),
+      server_handle: self.server_handle.clone()
+    }));
+    if let Err(err) = editor.load_history(crate::CONSOLE_HISTORY_FILE) {
+      //There is no history the very first time
+      if !matches!(&err, ReadlineError::Io(io_err) if io_err.kind() == std::io::ErrorKind::NotFound) {
+        warn!("Could not load console history (reason: \"{err}\")");
+      }
+    }
+
+    //(2) From now on log lines are printed above the prompt
+    logger::set_prompt_printer(Some(Box::new(editor.create_external_printer()?)));
+
+    //(3) Read and execute commands until the server stops
+    let result = loop {
+      let line = match editor.readline(PROMPT) {
+        Ok(line) => line,
+        //Ctrl+C doesn't reach the signal handler while we are editing
+        Err(ReadlineError::Interrupted) => "stop".to_string(),
+        Err(ReadlineError::Eof) => {
+          info!("Type \"stop\" to stop the server");
+          continue;
+        },
+        Err(err) => break Err(err)
+      };
+      if !line.trim().is_empty() {
+        let _ = editor.add_history_entry(line.as_str());
+        if let Err(err) = editor.append_history(crate::CONSOLE_HISTORY_FILE) {
+          warn!("Could not save console history (reason: \"{err}\")");
+        }
+      }
+      if !self.execute(&line) {
+        break Ok(());
+      }
+    };
+
+    //(R) The prompt is gone, so log lines go straight to stdout again
+    logger::set_prompt_printer(None);
+    result
+  }
+
+  fn run_plain(&self, mut input: impl BufRead) {
+    /*(Note to future self)
+      There is no completion without a terminal. Lines come from scripts or
+      systemd here, and a line ending in a tab is still a command.
+    */
+    let mut stdin_buf = String::new();
+    loop {
+      //(1) Read a line from stdin, without its line ending (\n or \r\n)
+      stdin_buf.clear();
+      match input.read_line(&mut stdin_buf) {
+        Ok(0) => {
+          info!("Console input was closed, the console is disabled");
+          return;
+        },
+        Ok(_) => {},
+        Err(err) => {
+          warn!("Could not read console input (reason: \"{err}\"), the console is disabled");
+          return;
+        }
+      }
+      let line = stdin_buf.trim_end_matches(['\n', '\r']);
+
+      //(2) Try to execute the command
+      if !self.execute(line) {
+        return;
+      }
+    }
+  }
+
+  fn execute(&self, line: &str) -> bool {
+    //Returns false once the console should stop
+    if dispatch_command(&self.events, "Console", line) {
+      return true;
+    }
+    match self.commands.parse(line) {
+      Ok(Invocation::Help(lines)) => lines.iter().for_each(|line| info!("{line}")),
+      Ok(Invocation::Request(msg)) => {
+        let stop = matches!(msg, CReqMsg::ConsoleKill);
+        match send_msg(&self.server_handle, msg) {
+          Ok(rsp) => describe_response(rsp).iter().for_each(|line| info!("{line}")),
+          Err(denied) => warn!("{}", denied.reason())
+        }
+        //When the server stops we stop ourselves
+        if stop {
+          return false;
+        }
+      },
+      //Nothing to do for empty lines
+      Err(CommandError::Empty) => {},
+      Err(CommandError::Unknown(_)) => {
+        //Maybe a plugin knows it
+        let msg = CReqMsg::PluginCommand{source: CommandSource::Console, line: line.to_string()};
+        match send_msg(&self.server_handle, msg) {
+          Ok(rsp) => describe_response(rsp).iter().for_each(|line| info!("{line}")),
+          Err(denied) => warn!("{}", denied.reason())
+        }
+      },
+      Err(err) => warn!("{err}")
+    }
+    true
+  }
+
+}
+
+//Tab completion for the line editor
+struct ConsoleHelper {
+  commands: Arc<CommandRegistry>,
+  server_handle: mpsc::Sender<ClientRequest>
+}
+
+impl Completer for ConsoleHelper {
+  type Candidate = String;
+
+  fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>)
+    -> rustyline::Result<(usize, Vec<String>)>
+  {
+    let context = completion_context(&self.server_handle);
+    Ok(complete_word(&self.commands, line, pos, &context))
   }
+}
+
+fn complete_word(commands: &CommandRegistry, line: &str, pos: usize, context: &CompletionContext)
+  -> (usize, Vec<String>)
+{
+  //We replace the word in front of the cursor, whatever comes after it stays
+  let line = &line[..pos];
+  let start = line.rfind(char::is_whitespace).map_or(0, |space| space + 1);
+  (start, commands.complete(line, context))
+}
 
+impl Hinter for ConsoleHelper {
+  type Hint = String;
 }
+impl Highlighter for ConsoleHelper {}
+impl Validator for ConsoleHelper {}
+impl Helper for ConsoleHelper {}
 
-fn stdin_parse_linux(stdin_buf: &mut String) {
-  //Linux stdin includes newline which we should pop
-  stdin_buf.pop();
+fn completion_context(server_handle: &mpsc::Sender<ClientRequest>) -> CompletionContext {
+  //Ask the server who and what there is to complete
+  let mut context = CompletionContext::default();
+  if let Ok(CReqRsp::Players(players)) = send_msg(server_handle, CReqMsg::ListPlayers) {
+    context.players = players.into_iter().map(|player| player.name).collect();
+  }
+  if let Ok(CReqRsp::Worlds(worlds)) = send_msg(server_handle, CReqMsg::ListWorlds) {
+    context.worlds = worlds;
+  }
+  context
 }
 
-fn stdin_parse_windows(stdin_buf: &mut String) {
-  //Windows stdin includes newline AND carriage return
-  stdin_buf.pop();
-  stdin_buf.pop();
-}
\ No newline at end of file
+fn send_msg(server_handle: &mpsc::Sender<ClientRequest>, msg: CReqMsg) -> Result<CReqRsp, CReqDenied> {
+  tokio::runtime::Builder::new_current_thread()
+    .build()
+    .unwrap()
+    .block_on(
+      ClientRequest::send(msg, server_handle.clone())
+    )
+}
+
+#[cfg(test)]
+mod console_test {
+  use std::io::Cursor;
+
+  use super::*;
+
+  #[test]
+  fn completion() {
+    let registry = CommandRegistry::with_builtins();
+    let context = CompletionContext {
+      players: vec!["Notch".to_string(), "jeb_".to_string()],
+      worlds: vec!["lobby".to_string(), "nether".to_string()]
+    };
+    assert_eq!(complete_word(&registry, "sa", 2, &context), (0, vec!["save-all".to_string(), "say".to_string()]));
+    assert_eq!(complete_word(&registry, "kick  No", 8, &context), (6, vec!["Notch".to_string()]));
+
+    //Only what's in front of the cursor counts
+    assert_eq!(complete_word(&registry, "tp Notch lo 1 2 3", 11, &context), (9, vec!["lobby".to_string()]));
+    assert_eq!(complete_word(&registry, "tp Notch ", 9, &context).1, vec!["lobby", "nether"]);
+  }
+
+  #[test]
+  fn piped_lines_run() {
+    //A server that writes down what it is asked
+    let (server_handle, mut requests) = mpsc::channel::<ClientRequest>(16);
+    let server = thread::spawn(move || {
+      let mut asked = Vec::new();
+      while let Some(request) = requests.blocking_recv() {
+        let (msg, tx) = request.open();
+        asked.push(format!("{msg:?}"));
+        let _ = tx.send(Ok(CReqRsp::Done));
+      }
+      asked
+    });
+
+    //A trailing tab is part of the line, not a request for completion
+    let console = Console::init(server_handle, Arc::new(CommandRegistry::with_builtins()), Arc::new(EventBus::new()));
+    console.run_plain(Cursor::new("save-all\t\nlist\n"));
+    drop(console);
+    assert_eq!(server.join().unwrap(), vec!["SaveAll", "ListPlayers"]);
+  }
+}
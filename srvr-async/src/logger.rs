@@ -24,23 +24,37 @@
 
 use std::{
   fs,
-  path::PathBuf
+  io::{stdout, IsTerminal, Write},
+  path::PathBuf,
+  sync::Mutex
 };
 
 use chrono::Local;
-use log::{LevelFilter, info};
+use log::{LevelFilter, Record, info};
 use log4rs::{
-  append::{console::ConsoleAppender, file::FileAppender},
-  encode::pattern::PatternEncoder,
+  append::{Append, file::FileAppender},
+  encode::{
+    Encode,
+    pattern::PatternEncoder,
+    writer::{ansi::AnsiWriter, simple::SimpleWriter}
+  },
   Config,
   config::{Appender, Logger, Root}
 };
+use rustyline::ExternalPrinter;
+use srvr_sysplugin::logger::LOG_TARGET;
 
 //Logging constants
 const SERVER_LOG_PATTERN: &'static str =
   "(({d(%Y-%m-%d %H:%M:%S)})) server: [{h({l})}] {m}{n}";
 const CHAT_LOG_PATTERN: &'static str= 
   "(({d(%Y-%m-%d %H:%M:%S)})) {m}{n}";
+//Plugins log under "plugin::<name>"
+const PLUGIN_LOG_PATTERN: &'static str =
+  "(({d(%Y-%m-%d %H:%M:%S)})) {t}: [{h({l})}] {m}{n}";
+
+//While the console is editing a line, log lines have to go through it
+static PROMPT_PRINTER: Mutex<Option<Box<dyn ExternalPrinter + Send>>> = Mutex::new(None);
 
 //Panic messages
 const PANIC_DIR_MISSING: &'static str =
@@ -52,15 +66,13 @@ pub fn start_logger() {
     of two parts; all messages are logged to *both*:
       (1) Stdout - basic console logger
       (2) Logfile - file that is appended during operation
+    Server, chat and plugin messages each have their own pattern.
   */
 
   //(1) First we need to configure stdout
-  let server_stdout = ConsoleAppender::builder()
-    .encoder(Box::new(PatternEncoder::new(SERVER_LOG_PATTERN)))
-    .build();
-  let chat_stdout = ConsoleAppender::builder()
-    .encoder(Box::new(PatternEncoder::new(CHAT_LOG_PATTERN)))
-    .build();
+  let server_stdout = StdoutAppender::new(PatternEncoder::new(SERVER_LOG_PATTERN));
+  let chat_stdout = StdoutAppender::new(PatternEncoder::new(CHAT_LOG_PATTERN));
+  let plugin_stdout = StdoutAppender::new(PatternEncoder::new(PLUGIN_LOG_PATTERN));
 
   //(2a) Next we setup the logfile. We must first make sure the log dir exists
   let log_dir = PathBuf::from(super::LOG_FOLDER);
@@ -78,6 +90,10 @@ pub fn start_logger() {
     .expect("[FATAL STARTUP PANIC] - could not instantiate logger: ");
   let chat_logfile_out = FileAppender::builder()
     .encoder(Box::new(PatternEncoder::new(CHAT_LOG_PATTERN)))
+    .build(log_file_path.clone())
+    .expect("[FATAL STARTUP PANIC] - could not instantiate logger: ");
+  let plugin_logfile_out = FileAppender::builder()
+    .encoder(Box::new(PatternEncoder::new(PLUGIN_LOG_PATTERN)))
     .build(log_file_path)
     .expect("[FATAL STARTUP PANIC] - could not instantiate logger: ");
 
@@ -87,12 +103,22 @@ pub fn start_logger() {
     .appender(Appender::builder().build("server_logfile", Box::new(server_logfile_out)))
     .appender(Appender::builder().build("chat_stdout", Box::new(chat_stdout)))
     .appender(Appender::builder().build("chat_logfile", Box::new(chat_logfile_out)))
+    .appender(Appender::builder().build("plugin_stdout", Box::new(plugin_stdout)))
+    .appender(Appender::builder().build("plugin_logfile", Box::new(plugin_logfile_out)))
     .logger(Logger::builder()
       .appender("chat_stdout")
       .appender("chat_logfile")
       .additive(false)
       .build("chat", LevelFilter::Trace)
     )
+    .logger(Logger::builder()
+      .appender("plugin_stdout")
+      .appender("plugin_logfile")
+      .additive(false)
+      .build(LOG_TARGET, LevelFilter::Info)
+    )
+    //rustyline logs from inside the console printer, which would deadlock
+    .logger(Logger::builder().build("rustyline", LevelFilter::Warn))
     .build(
       Root::builder()
       .appender("server_stdout")
@@ -104,4 +130,51 @@ pub fn start_logger() {
 
   info!("Starting srvr v{}...", super::VERSION);
   info!("Finished configuring logger");
-}
\ No newline at end of file
+}
+
+pub fn set_prompt_printer(printer: Option<Box<dyn ExternalPrinter + Send>>) {
+  //A poisoned lock only means some log line panicked, the printer is fine
+  let mut prompt_printer = PROMPT_PRINTER.lock().unwrap_or_else(|err| err.into_inner());
+  *prompt_printer = printer;
+}
+
+/*(Note to future self)
+  This does what log4rs' ConsoleAppender does, except that while the console
+  is showing a prompt we hand the line to the console instead. It prints the
+  line above the prompt and redraws whatever the operator was typing.
+*/
+#[derive(Debug)]
+struct StdoutAppender {
+  encoder: PatternEncoder,
+  colored: bool
+}
+
+impl StdoutAppender {
+  fn new(encoder: PatternEncoder) -> Self {
+    //Don't write colour codes into files or pipes
+    StdoutAppender {encoder, colored: stdout().is_terminal()}
+  }
+}
+
+impl Append for StdoutAppender {
+  fn append(&self, record: &Record) -> anyhow::Result<()> {
+    //(1) Format the line
+    let mut line = Vec::new();
+    match self.colored {
+      true => self.encoder.encode(&mut AnsiWriter(&mut line), record)?,
+      false => self.encoder.encode(&mut SimpleWriter(&mut line), record)?
+    }
+
+    //(2) Print it through the console if it has a prompt, or directly if not
+    let mut prompt_printer = PROMPT_PRINTER.lock().unwrap_or_else(|err| err.into_inner());
+    match prompt_printer.as_mut() {
+      Some(printer) => printer.print(String::from_utf8_lossy(&line).into_owned())?,
+      None => stdout().lock().write_all(&line)?
+    }
+    Ok(())
+  }
+
+  fn flush(&self) {
+    let _ = stdout().flush();
+  }
+}
@@ -29,6 +29,7 @@ use std::{
 //Internal deps
 use config::Config;
 
+use srvr_sysplugin::{PluginManager, Event, WasmLimits, ffi::FfiStr};
 use srvr_sysworld::{
   worldgen::generator_manager::WorldGeneratorManager,
   world_builder::WorldBuilder,
@@ -45,6 +46,9 @@ mod logger;
 mod config;
 mod client;
 mod console;
+mod commands;
+mod rcon;
+mod query;
 mod srvr_manager;
 
 //Public modules
@@ -57,6 +61,7 @@ pub const VERSION: Version = Version::new(0,0,1);
 pub const LOG_FOLDER: &'static str = "./logs";
 pub const PLUGIN_FOLDER: &'static str = "./plugins";
 pub const CONFIG_FILE: &'static str = "./config.toml";
+pub const CONSOLE_HISTORY_FILE: &'static str = "./console_history.txt";
 pub const WORLD_FOLDER: &'static str = "./world";
 pub const WORLD_GEN_FOLDER: &'static str = "./world/generators";
 
@@ -100,7 +105,7 @@ fn main() {
   */
   info!("Loading saved worlds...");
 
-  let mut worlds: Vec<World> = config.world_settings.worlds.iter()
+  let worlds: Vec<World> = config.world_settings.worlds.iter()
     .map(|world_config| -> Option<World> {
       //(4a) First we must check if the generator specified in the world-config
       // is actually loaded
@@ -151,10 +156,26 @@ fn main() {
   };
 
   //(4) Load plugins (oof!)
+  info!("Loading plugins...");
+  let wasm_limits = WasmLimits {
+    fuel: config.plugin_settings.wasm_fuel,
+    memory: config.plugin_settings.wasm_memory * 1024 * 1024
+  };
+  let mut plugins = match PluginManager::load_folder(Path::new(PLUGIN_FOLDER), &VERSION, wasm_limits) {
+    Ok(plugins) => plugins,
+    Err(err) => {
+      error!("Could not load plugins (reason: \"{err}\"), continuing without them");
+      PluginManager::from_plugins(Path::new(PLUGIN_FOLDER), &VERSION, Vec::new())
+    }
+  };
+  plugins.start_all();
+  for world in &worlds {
+    plugins.events().dispatch(Event::WorldLoad{world: FfiStr::new(world.name())});
+  }
 
   //(5) Start Runtime
   runtime.block_on( async {
-    match srvr_manager::Main::init().await {
+    match srvr_manager::Main::init(worlds, plugins).await {
       Ok(mut srvr) => {
         //(6) Initialise the Console
         srvr.connect_console().run();
@@ -22,8 +22,20 @@
   text of the license in any official language of the European Union.
 */
 
+use std::time::Duration;
+
+use srvr_sysproto::mc_dtypes::MCChat;
+
 #[derive(Debug, Clone)]
 pub enum BroadcastMsg {
-  //Instruct all clients to disconnect
-  Die
+  //Instruct all clients to disconnect, telling the player why
+  Die{reason: String},
+  //Latest (smoothed) latency of a player, for the player list
+  PlayerLatency{uuid: u128, latency: Duration},
+  //A message for every player's chat
+  Chat{message: MCChat},
+  //Only the client of this player disconnects
+  Kick{uuid: u128, reason: String},
+  //Only the client of this player moves its player
+  Teleport{uuid: u128, x: f64, y: f64, z: f64}
 }
\ No newline at end of file
@@ -24,11 +24,15 @@
 
 use std::{
   error::Error,
-  fmt::{Display, Formatter}
+  fmt::{Display, Formatter},
+  net::SocketAddr,
+  time::Duration
 };
 
 use tokio::sync::{oneshot, mpsc};
 
+use crate::srvr_manager::player_registry::PlayerEntry;
+
 /*(Note to future self)
   This request is issued by clients and follows a strict Request-Response
   pattern:
@@ -75,21 +79,81 @@ impl ClientRequest {
 
 #[derive(Debug)]
 pub enum CReqMsg {
-  ConsoleKill
+  ConsoleKill,
+  //A client finished logging in and wants to enter the game
+  Join{name: String, uuid: u128, addr: SocketAddr},
+  //A player left the game
+  Leave{uuid: u128},
+  //Get a copy of every player that is online
+  ListPlayers,
+  //Stop the server once the delay has passed
+  StopIn{delay: Duration},
+  //Disconnect a player, telling them why
+  Kick{name: String, reason: Option<String>},
+  //Send a message to every player
+  Say{message: String},
+  //A player chatted, and no plugin stopped it
+  Chat{name: String, message: String},
+  //Run a command that a plugin added
+  PluginCommand{source: CommandSource, line: String},
+  //Move a player to a world, and to a block in that world if one is given
+  Teleport{name: String, world: String, position: Option<(i32, i32, i32)>},
+  SaveAll,
+  ListWorlds,
+  ListPlugins,
+  //Swap a plugin for the new build of its library
+  ReloadPlugin{name: String},
+  //Re-read the config file
+  Reload,
+  //Everything the query protocol reports about the server
+  ServerInfo,
+  //A client measured a new latency through the keep-alive exchange
+  UpdateLatency{uuid: u128, latency: Duration}
 }
 
 #[derive(Debug)]
 pub enum CReqRsp {
   //Do nothing
   Done,
+  //The players that are online, sorted by name
+  Players(Vec<PlayerEntry>),
+  //Names of the loaded worlds
+  Worlds(Vec<String>),
+  //Names and versions of the loaded plugins
+  Plugins(Vec<String>),
+  //Text to show whoever asked, one entry per line
+  Lines(Vec<String>),
+  ServerInfo(ServerInfo),
   //Client must switch communication channels to this new supervisor
   ChangeSuperior{
     new_request_queue: mpsc::Sender<ClientRequest>
   }
 }
 
+//Who ran a command, which decides the permissions it has
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandSource {
+  Console,
+  Rcon,
+  Player{name: String, uuid: u128}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+  pub motd: String,
+  //Name of the default world
+  pub map: String,
+  pub max_players: usize,
+  pub players: Vec<String>,
+  pub plugins: Vec<String>
+}
+
 #[derive(Debug)]
 pub struct CReqDenied(String);
+impl CReqDenied {
+  pub fn new(reason: impl Into<String>) -> Self {CReqDenied(reason.into())}
+  pub fn reason(&self) -> &str {&self.0}
+}
 impl Display for CReqDenied {
   fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
     write!(f, "Request denied. Reason: \"{}\"", self.0)
@@ -0,0 +1,339 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::{
+  collections::HashMap,
+  io,
+  net::SocketAddr,
+  time::{Duration, Instant}
+};
+
+use log::{warn, debug};
+use rand::Rng;
+use tokio::{
+  net::{UdpSocket, ToSocketAddrs},
+  sync::mpsc,
+  task::JoinHandle
+};
+
+use crate::messages::client_request::{ClientRequest, CReqMsg, CReqRsp, ServerInfo};
+
+/*(Note to future self)
+  The query protocol (GameSpy4) runs over UDP. Every request starts with
+    [0xFE 0xFD][type: u8][session id: i32]
+  and every answer with [type: u8][session id: i32]. A client first asks for
+  a challenge token (type 9), which it then has to send back with each stat
+  request (type 0). This way we never send the (much bigger) stat answer to
+  a spoofed address. A stat request padded with four extra bytes asks for
+  the full stat, which includes the plugins and player names.
+*/
+const MAGIC: [u8; 2] = [0xFE, 0xFD];
+const TYPE_HANDSHAKE: u8 = 9;
+const TYPE_STAT: u8 = 0;
+
+//Vanilla replaces its tokens every 30 seconds
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(30);
+//Requests are tiny, anything bigger is not meant for us
+const MAX_REQUEST_LEN: usize = 32;
+
+//Fixed parts of the full stat answer
+const FULL_STAT_PADDING: &[u8] = b"splitnum\0\x80\0";
+const PLAYER_PADDING: &[u8] = b"\x01player_\0\0";
+const GAME_TYPE: &str = "SMP";
+const GAME_ID: &str = "MINECRAFT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QueryRequest {
+  Handshake{session: i32},
+  BasicStat{session: i32, token: i32},
+  FullStat{session: i32, token: i32}
+}
+
+impl QueryRequest {
+  fn parse(packet: &[u8]) -> Option<Self> {
+    //(1) Check the magic and read the header
+    if packet.len() < 7 || packet[..2] != MAGIC {
+      return None;
+    }
+    let session = i32::from_be_bytes(packet[3..7].try_into().ok()?);
+
+    //(2) Stat requests also carry the token, and optionally the padding
+    match (packet[2], packet.len()) {
+      (TYPE_HANDSHAKE, _) => Some(QueryRequest::Handshake{session}),
+      (TYPE_STAT, 11) | (TYPE_STAT, 15) => {
+        let token = i32::from_be_bytes(packet[7..11].try_into().ok()?);
+        match packet.len() {
+          11 => Some(QueryRequest::BasicStat{session, token}),
+          _ => Some(QueryRequest::FullStat{session, token})
+        }
+      },
+      _ => None
+    }
+  }
+}
+
+pub struct QueryServer {
+  socket: UdpSocket,
+  server_handle: mpsc::Sender<ClientRequest>,
+  host_ip: String,
+  host_port: u16,
+  challenges: HashMap<SocketAddr, (i32, Instant)>
+}
+
+impl QueryServer {
+
+  pub async fn bind(
+    addr: impl ToSocketAddrs,
+    server_handle: mpsc::Sender<ClientRequest>,
+    host: SocketAddr
+  ) -> io::Result<Self> {
+    Ok(QueryServer {
+      socket: UdpSocket::bind(addr).await?,
+      server_handle,
+      host_ip: host.ip().to_string(),
+      host_port: host.port(),
+      challenges: HashMap::new()
+    })
+  }
+
+  pub fn spawn(self) -> JoinHandle<()> {
+    tokio::spawn(self.run())
+  }
+
+  async fn run(mut self) {
+    let mut buf = [0u8; MAX_REQUEST_LEN];
+    loop {
+      //(1) Wait for a request, junk is simply ignored
+      let (len, addr) = match self.socket.recv_from(&mut buf).await {
+        Ok(received) => received,
+        Err(err) => {
+          //On some platforms an unreachable client shows up here
+          debug!("could not receive query packet: \"{err}\"");
+          continue;
+        }
+      };
+      let request = match QueryRequest::parse(&buf[..len]) {
+        Some(request) => request,
+        None => continue
+      };
+
+      //(2) Answer it, if the client is allowed an answer
+      let answer = match request {
+        QueryRequest::Handshake{session} => Some(self.handshake(session, addr)),
+        QueryRequest::BasicStat{session, token} if self.check_token(addr, token) => {
+          self.server_info().await.map(|info| self.basic_stat(session, &info))
+        },
+        QueryRequest::FullStat{session, token} if self.check_token(addr, token) => {
+          self.server_info().await.map(|info| self.full_stat(session, &info))
+        },
+        _ => None
+      };
+      if let Some(answer) = answer {
+        if let Err(err) = self.socket.send_to(&answer, addr).await {
+          debug!("could not answer query from @{addr}: \"{err}\"");
+        }
+      }
+    }
+  }
+
+  fn handshake(&mut self, session: i32, addr: SocketAddr) -> Vec<u8> {
+    //(1) Forget the tokens that expired, so the map can't grow forever
+    let now = Instant::now();
+    self.challenges.retain(|_, (_, issued)| now.duration_since(*issued) < CHALLENGE_LIFETIME);
+
+    //(2) Hand out a fresh token, which is sent as a decimal string
+    let token = rand::thread_rng().gen_range(0..=i32::MAX);
+    self.challenges.insert(addr, (token, now));
+    let mut answer = header(TYPE_HANDSHAKE, session);
+    push_str(&mut answer, &token.to_string());
+    answer
+  }
+
+  fn check_token(&self, addr: SocketAddr, token: i32) -> bool {
+    match self.challenges.get(&addr) {
+      Some((expected, issued)) => *expected == token && issued.elapsed() < CHALLENGE_LIFETIME,
+      None => false
+    }
+  }
+
+  async fn server_info(&self) -> Option<ServerInfo> {
+    match ClientRequest::send(CReqMsg::ServerInfo, self.server_handle.clone()).await {
+      Ok(CReqRsp::ServerInfo(info)) => Some(info),
+      Ok(other) => {
+        warn!("Unexpected answer to a query: {other:?}");
+        None
+      },
+      Err(err) => {
+        warn!("Could not answer query: {err}");
+        None
+      }
+    }
+  }
+
+  fn basic_stat(&self, session: i32, info: &ServerInfo) -> Vec<u8> {
+    let mut answer = header(TYPE_STAT, session);
+    push_str(&mut answer, &info.motd);
+    push_str(&mut answer, GAME_TYPE);
+    push_str(&mut answer, &info.map);
+    push_str(&mut answer, &info.players.len().to_string());
+    push_str(&mut answer, &info.max_players.to_string());
+    //The only little-endian number in the protocol
+    answer.extend_from_slice(&self.host_port.to_le_bytes());
+    push_str(&mut answer, &self.host_ip);
+    answer
+  }
+
+  fn full_stat(&self, session: i32, info: &ServerInfo) -> Vec<u8> {
+    //(1) Key-value pairs, ended by an empty key
+    let plugins = format!("srvr {}: {}", crate::VERSION, info.plugins.join("; "));
+    let (num_players, max_players) = (info.players.len().to_string(), info.max_players.to_string());
+    let host_port = self.host_port.to_string();
+    let pairs = [
+      ("hostname", info.motd.as_str()),
+      ("gametype", GAME_TYPE),
+      ("game_id", GAME_ID),
+      ("version", srvr_sysproto::VERSION_NAME),
+      ("plugins", plugins.as_str()),
+      ("map", info.map.as_str()),
+      ("numplayers", num_players.as_str()),
+      ("maxplayers", max_players.as_str()),
+      ("hostport", host_port.as_str()),
+      ("hostip", self.host_ip.as_str())
+    ];
+    let mut answer = header(TYPE_STAT, session);
+    answer.extend_from_slice(FULL_STAT_PADDING);
+    for (key, value) in pairs {
+      push_str(&mut answer, key);
+      push_str(&mut answer, value);
+    }
+    answer.push(0);
+
+    //(2) Player names, ended by an empty name
+    answer.extend_from_slice(PLAYER_PADDING);
+    for player in &info.players {
+      push_str(&mut answer, player);
+    }
+    answer.push(0);
+    answer
+  }
+
+}
+
+fn header(kind: u8, session: i32) -> Vec<u8> {
+  //Only the lower four bits of every byte of the session id are used
+  let mut header = vec![kind];
+  header.extend_from_slice(&(session & 0x0F0F0F0F).to_be_bytes());
+  header
+}
+
+fn push_str(packet: &mut Vec<u8>, text: &str) {
+  packet.extend_from_slice(text.as_bytes());
+  packet.push(0);
+}
+
+#[cfg(test)]
+mod query_test {
+  use super::*;
+
+  async fn start() -> (UdpSocket, SocketAddr) {
+    //(1) A server manager with two players online
+    let (server_handle, mut requests) = mpsc::channel::<ClientRequest>(1);
+    tokio::spawn(async move {
+      while let Some(request) = requests.recv().await {
+        let (_, tx) = request.open();
+        let _ = tx.send(Ok(CReqRsp::ServerInfo(ServerInfo {
+          motd: "Hello world".to_string(),
+          map: "lobby".to_string(),
+          max_players: 20,
+          players: vec!["Notch".to_string(), "jeb_".to_string()],
+          plugins: vec!["sample-plugin v0.1.0".to_string()]
+        })));
+      }
+    });
+
+    //(2) And a query server in front of it
+    let host = "127.0.0.1:25565".parse().unwrap();
+    let query = QueryServer::bind("127.0.0.1:0", server_handle, host).await.unwrap();
+    let addr = query.socket.local_addr().unwrap();
+    query.spawn();
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    (client, addr)
+  }
+
+  async fn request(client: &UdpSocket, addr: SocketAddr, packet: &[u8]) -> Option<Vec<u8>> {
+    client.send_to(packet, addr).await.unwrap();
+    let mut buf = [0u8; 1024];
+    let received = tokio::time::timeout(Duration::from_millis(500), client.recv(&mut buf)).await;
+    received.ok().map(|len| buf[..len.unwrap()].to_vec())
+  }
+
+  async fn handshake(client: &UdpSocket, addr: SocketAddr) -> i32 {
+    let answer = request(client, addr, &[0xFE, 0xFD, 9, 0x1F, 0x2F, 0x3F, 0x4F]).await.unwrap();
+    //The session id comes back with its upper bits cleared
+    assert_eq!(answer[..5], [9, 0x0F, 0x0F, 0x0F, 0x0F]);
+    std::str::from_utf8(&answer[5..answer.len() - 1]).unwrap().parse().unwrap()
+  }
+
+  fn stat_request(token: i32, full: bool) -> Vec<u8> {
+    let mut packet = vec![0xFE, 0xFD, 0, 0, 0, 0, 1];
+    packet.extend_from_slice(&token.to_be_bytes());
+    if full {
+      packet.extend_from_slice(&[0; 4]);
+    }
+    packet
+  }
+
+  #[tokio::test]
+  async fn basic_stat() {
+    let (client, addr) = start().await;
+    let token = handshake(&client, addr).await;
+    let answer = request(&client, addr, &stat_request(token, false)).await.unwrap();
+
+    let mut expected = vec![0, 0, 0, 0, 1];
+    expected.extend_from_slice(b"Hello world\0SMP\0lobby\x002\x0020\0");
+    expected.extend_from_slice(&25565u16.to_le_bytes());
+    expected.extend_from_slice(b"127.0.0.1\0");
+    assert_eq!(answer, expected);
+  }
+
+  #[tokio::test]
+  async fn full_stat() {
+    let (client, addr) = start().await;
+    let token = handshake(&client, addr).await;
+    let answer = request(&client, addr, &stat_request(token, true)).await.unwrap();
+
+    assert!(answer.starts_with(b"\0\0\0\0\x01splitnum\0\x80\0hostname\0Hello world\0"));
+    let plugins = format!("plugins\0srvr {}: sample-plugin v0.1.0\0", crate::VERSION);
+    assert!(answer.windows(plugins.len()).any(|window| window == plugins.as_bytes()));
+    assert!(answer.ends_with(b"hostip\x00127.0.0.1\0\0\x01player_\0\0Notch\0jeb_\0\0"));
+  }
+
+  #[tokio::test]
+  async fn bad_token() {
+    let (client, addr) = start().await;
+    let token = handshake(&client, addr).await;
+    assert_eq!(request(&client, addr, &stat_request(token.wrapping_add(1), false)).await, None);
+  }
+
+}
@@ -0,0 +1,323 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+use log::{info, warn};
+use srvr_sysplugin::EventBus;
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::{TcpListener, TcpStream, ToSocketAddrs},
+  sync::mpsc,
+  task::JoinHandle
+};
+
+use crate::{
+  messages::client_request::{ClientRequest, CReqMsg, CommandSource},
+  commands::{CommandRegistry, CommandError, Invocation, describe_response, dispatch_command}
+};
+
+/*(Note to future self)
+  RCON packets are little-endian, unlike the rest of the protocol:
+    [length: i32][request id: i32][type: i32][body: ASCII][0u8][0u8]
+  where the length counts everything after itself. The client logs in with
+  the password first, after which every packet is a command. A failed login
+  is answered with request id -1. Answers that don't fit into one packet are
+  split over several packets with the same request id.
+*/
+const TYPE_RESPONSE: i32 = 0;
+const TYPE_COMMAND: i32 = 2;
+const TYPE_AUTH_RESPONSE: i32 = 2;
+const TYPE_LOGIN: i32 = 3;
+const AUTH_FAILED: i32 = -1;
+
+//Id, type and the two null bytes
+const HEADER_LEN: usize = 10;
+//Vanilla doesn't accept longer requests, nor sends longer answers
+const MAX_REQUEST_BODY: usize = 1446;
+const MAX_RESPONSE_BODY: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RconPacket {
+  id: i32,
+  kind: i32,
+  body: String
+}
+
+impl RconPacket {
+
+  async fn read(stream: &mut TcpStream) -> io::Result<Option<Self>> {
+    //(1) Read the length, a closed connection is not an error here
+    let length = match stream.read_i32_le().await {
+      Ok(length) => length,
+      Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+      Err(err) => return Err(err)
+    };
+    let length = usize::try_from(length).unwrap_or(0);
+    if !(HEADER_LEN..=HEADER_LEN + MAX_REQUEST_BODY).contains(&length) {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad packet length {length}")));
+    }
+
+    //(2) Read the rest, and drop the null bytes at the end of the body
+    let mut packet = vec![0u8; length];
+    stream.read_exact(&mut packet).await?;
+    let id = i32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]);
+    let kind = i32::from_le_bytes([packet[4], packet[5], packet[6], packet[7]]);
+    let body = &packet[8..];
+    let end = body.iter().position(|&byte| byte == 0).unwrap_or(body.len());
+
+    //(R) Non-ASCII bytes shouldn't appear, but there's no reason to fail on them
+    Ok(Some(RconPacket {id, kind, body: String::from_utf8_lossy(&body[..end]).into_owned()}))
+  }
+
+  async fn write(&self, stream: &mut TcpStream) -> io::Result<()> {
+    let mut packet = Vec::with_capacity(4 + HEADER_LEN + self.body.len());
+    packet.extend_from_slice(&((HEADER_LEN + self.body.len()) as i32).to_le_bytes());
+    packet.extend_from_slice(&self.id.to_le_bytes());
+    packet.extend_from_slice(&self.kind.to_le_bytes());
+    packet.extend_from_slice(self.body.as_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    stream.write_all(&packet).await
+  }
+
+}
+
+pub struct RconServer {
+  listener: TcpListener,
+  password: Arc<str>,
+  commands: Arc<CommandRegistry>,
+  events: Arc<EventBus>,
+  server_handle: mpsc::Sender<ClientRequest>
+}
+
+impl RconServer {
+
+  pub async fn bind(
+    addr: impl ToSocketAddrs,
+    password: &str,
+    commands: Arc<CommandRegistry>,
+    events: Arc<EventBus>,
+    server_handle: mpsc::Sender<ClientRequest>
+  ) -> io::Result<Self> {
+    //Anyone could log in with an empty password
+    if password.is_empty() {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "rcon_password must not be empty"));
+    }
+    Ok(RconServer {
+      listener: TcpListener::bind(addr).await?,
+      password: password.into(),
+      commands,
+      events,
+      server_handle
+    })
+  }
+
+  pub fn spawn(self) -> JoinHandle<()> {
+    tokio::spawn(self.run())
+  }
+
+  async fn run(self) {
+    //Every connection gets its own session, so slow operators don't block
+    //each other
+    loop {
+      let (stream, addr) = match self.listener.accept().await {
+        Ok(accepted) => accepted,
+        Err(err) => {
+          warn!("could not accept RCON connection: \"{err}\"");
+          continue;
+        }
+      };
+      let session = RconSession {
+        stream,
+        addr,
+        password: self.password.clone(),
+        commands: self.commands.clone(),
+        events: self.events.clone(),
+        server_handle: self.server_handle.clone()
+      };
+      tokio::spawn(async move {
+        let addr = session.addr;
+        if let Err(err) = session.run().await {
+          warn!("Closed RCON connection @{addr}: \"{err}\"");
+        }
+      });
+    }
+  }
+
+}
+
+struct RconSession {
+  stream: TcpStream,
+  addr: SocketAddr,
+  password: Arc<str>,
+  commands: Arc<CommandRegistry>,
+  events: Arc<EventBus>,
+  server_handle: mpsc::Sender<ClientRequest>
+}
+
+impl RconSession {
+
+  async fn run(mut self) -> io::Result<()> {
+    //(1) The first packet must be a login with the right password
+    let login = match RconPacket::read(&mut self.stream).await? {
+      Some(packet) => packet,
+      None => return Ok(())
+    };
+    let authenticated = login.kind == TYPE_LOGIN && login.body == *self.password;
+    let id = if authenticated {login.id} else {AUTH_FAILED};
+    RconPacket {id, kind: TYPE_AUTH_RESPONSE, body: String::new()}.write(&mut self.stream).await?;
+    if !authenticated {
+      warn!("Failed RCON login from @{}", self.addr);
+      return Ok(());
+    }
+    info!("RCON client logged in @{}", self.addr);
+
+    //(2) After that every packet is a command
+    while let Some(request) = RconPacket::read(&mut self.stream).await? {
+      let answer = match request.kind {
+        TYPE_COMMAND => {
+          info!("RCON @{} issued command: {}", self.addr, request.body);
+          self.execute(&request.body).await
+        },
+        //This is what vanilla answers
+        other => format!("Unknown request {other:x}")
+      };
+      for body in split_body(&answer) {
+        RconPacket {id: request.id, kind: TYPE_RESPONSE, body}.write(&mut self.stream).await?;
+      }
+    }
+    info!("RCON client disconnected @{}", self.addr);
+    Ok(())
+  }
+
+  async fn execute(&self, line: &str) -> String {
+    //The same commands as the console, but the answer goes back over RCON
+    if dispatch_command(&self.events, "RCON", line) {
+      return String::new();
+    }
+    let msg = match self.commands.parse(line) {
+      Ok(Invocation::Help(lines)) => return lines.join("\n"),
+      Ok(Invocation::Request(msg)) => msg,
+      //Maybe a plugin knows it
+      Err(CommandError::Unknown(_)) => CReqMsg::PluginCommand{source: CommandSource::Rcon, line: line.to_string()},
+      Err(err) => return err.to_string()
+    };
+    let lines = match ClientRequest::send(msg, self.server_handle.clone()).await {
+      Ok(rsp) => describe_response(rsp),
+      Err(denied) => vec![denied.reason().to_string()]
+    };
+    lines.join("\n")
+  }
+
+}
+
+fn split_body(text: &str) -> Vec<String> {
+  //Split into packet-sized pieces without cutting characters in half. An
+  //empty answer still gets a (single, empty) packet.
+  let mut bodies = Vec::new();
+  let mut rest = text;
+  while rest.len() > MAX_RESPONSE_BODY {
+    let mut end = MAX_RESPONSE_BODY;
+    while !rest.is_char_boundary(end) {
+      end -= 1;
+    }
+    let (body, tail) = rest.split_at(end);
+    bodies.push(body.to_string());
+    rest = tail;
+  }
+  bodies.push(rest.to_string());
+  bodies
+}
+
+#[cfg(test)]
+mod rcon_test {
+  use super::*;
+  use srvr_sysplugin::commands::CommandError as PluginCommandError;
+
+  use crate::messages::client_request::{CReqMsg, CReqRsp, CReqDenied};
+
+  async fn start() -> SocketAddr {
+    //(1) A server manager that only knows there is nobody online
+    let (server_handle, mut requests) = mpsc::channel::<ClientRequest>(1);
+    tokio::spawn(async move {
+      while let Some(request) = requests.recv().await {
+        let (msg, tx) = request.open();
+        let _ = tx.send(match msg {
+          CReqMsg::ListPlayers => Ok(CReqRsp::Players(Vec::new())),
+          //And has no plugins
+          CReqMsg::PluginCommand{line, ..} => Err(CReqDenied::new(PluginCommandError::Unknown(line).to_string())),
+          _ => Ok(CReqRsp::Done)
+        });
+      }
+    });
+
+    //(2) And an RCON server in front of it
+    let commands = Arc::new(CommandRegistry::with_builtins());
+    let events = Arc::new(EventBus::new());
+    let rcon = RconServer::bind("127.0.0.1:0", "hunter2", commands, events, server_handle).await.unwrap();
+    let addr = rcon.listener.local_addr().unwrap();
+    rcon.spawn();
+    addr
+  }
+
+  async fn request(stream: &mut TcpStream, id: i32, kind: i32, body: &str) -> RconPacket {
+    RconPacket {id, kind, body: body.to_string()}.write(stream).await.unwrap();
+    RconPacket::read(stream).await.unwrap().unwrap()
+  }
+
+  #[tokio::test]
+  async fn login_and_command() {
+    let mut client = TcpStream::connect(start().await).await.unwrap();
+    let login = request(&mut client, 7, TYPE_LOGIN, "hunter2").await;
+    assert_eq!(login, RconPacket {id: 7, kind: TYPE_AUTH_RESPONSE, body: String::new()});
+
+    let list = request(&mut client, 8, TYPE_COMMAND, "list").await;
+    assert_eq!(list.id, 8);
+    assert_eq!(list.kind, TYPE_RESPONSE);
+    assert_eq!(list.body, "There are 0 players online: ");
+
+    let unknown = request(&mut client, 9, TYPE_COMMAND, "fly").await;
+    assert_eq!(unknown.body, "Unknown command \"fly\"");
+  }
+
+  #[tokio::test]
+  async fn wrong_password() {
+    let mut client = TcpStream::connect(start().await).await.unwrap();
+    let login = request(&mut client, 7, TYPE_LOGIN, "hunter3").await;
+    assert_eq!(login.id, AUTH_FAILED);
+    //The server hangs up afterwards
+    assert_eq!(RconPacket::read(&mut client).await.unwrap(), None);
+  }
+
+  #[test]
+  fn split_long_answers() {
+    assert_eq!(split_body(""), vec![""]);
+    let long = "é".repeat(MAX_RESPONSE_BODY);
+    let bodies = split_body(&long);
+    assert_eq!(bodies.len(), 2);
+    assert_eq!(bodies[0].len(), MAX_RESPONSE_BODY);
+    assert_eq!(bodies.concat(), long);
+  }
+
+}
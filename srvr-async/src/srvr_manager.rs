@@ -24,42 +24,78 @@
 
 use std::{
   error::Error,
+  fs,
   net::{SocketAddr, Ipv4Addr, IpAddr},
+  sync::Arc,
   time::Duration
 };
 
-use log::{info, warn};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use log::{info, warn, debug, error};
+use srvr_sysproto::{
+  packets::{ServerStatus, StatusPlayerSample},
+  mc_dtypes::{MCUuid, MCChat}
+};
+use serde_json::json;
+use srvr_sysplugin::{PluginManager, PluginState, Event, CommandSender, OnlinePlayer, events::Player, ffi::FfiStr};
+use srvr_sysworld::world::World;
 use tokio::{
   net::TcpListener,
-  sync::{broadcast, mpsc}, time::timeout
+  sync::{broadcast, mpsc, watch},
+  task::{self, JoinHandle, JoinSet},
+  time::{self, timeout, MissedTickBehavior}
 };
 
 use crate::{
   messages::{
     broadcast::BroadcastMsg,
-    client_request::{ClientRequest, CReqMsg, CReqRsp}
+    client_request::{ClientRequest, CReqMsg, CReqRsp, CReqDenied, CommandSource}
   },
   config::Config,
-  client::Client,
+  client::{TICK_DURATION, auth::{OnlineMode, MojangAuthenticator}},
   console::Console,
+  commands::CommandRegistry,
+  rcon::RconServer,
+  query::QueryServer
 };
 
+mod acceptor;
+use acceptor::{Acceptor, LoggedIn};
+
+pub mod player_registry;
+use player_registry::{PlayerRegistry, PlayerEntry, STATUS_SAMPLE_SIZE};
+
+mod permissions;
+
 const MAX_QUEUE_LEN: usize = 100;
-const TCP_TIMEOUT: Duration = Duration::from_millis(10);
-const TASK_TIMEOUT: Duration = Duration::from_millis(1);
+
+//Favicons must be 64x64 PNG files
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const FAVICON_SIZE: u32 = 64;
 
 #[derive(Debug)]
 pub struct Main {
   config: Config,
-  socket: TcpListener,
+  acceptor: JoinHandle<()>,
+  rcon: Option<JoinHandle<()>>,
+  query: Option<JoinHandle<()>>,
+  commands: Arc<CommandRegistry>,
+  logged_in: mpsc::Receiver<LoggedIn>,
+  clients: JoinSet<()>,
   broadcast: broadcast::Sender<BroadcastMsg>,
   request_queue: mpsc::Receiver<ClientRequest>,
-  request_queue_tx: mpsc::Sender<ClientRequest>
+  request_queue_tx: mpsc::Sender<ClientRequest>,
+  favicon: Option<String>,
+  status: watch::Sender<ServerStatus>,
+  players: PlayerRegistry,
+  worlds: Vec<World>,
+  plugins: PluginManager,
+  tick: u64
 }
 
 impl Main {
 
-  pub async fn init() -> Result<Self, Box<dyn Error>> {
+  pub async fn init(worlds: Vec<World>, plugins: PluginManager) -> Result<Self, Box<dyn Error>> {
     //(1) Get global config
     let config = crate::config::copy_config();
 
@@ -71,76 +107,518 @@ impl Main {
     //(3) Set up the connections to and from the client
     let (broadcast, _) = broadcast::channel(MAX_QUEUE_LEN);
     let (tx, request_queue) = mpsc::channel(MAX_QUEUE_LEN);
+
+    //(4) In online mode we need a keypair to encrypt the login with
+    let online_mode = match config.network_settings.online_mode {
+      true => {
+        info!("Generating keypair for online mode...");
+        Some(Arc::new(OnlineMode::new(Box::new(MojangAuthenticator::new()))?))
+      },
+      false => {
+        warn!("Server is running in offline mode, players will not be authenticated!");
+        None
+      }
+    };
+
+    //(5) Clients answer status requests with the latest status we published
+    let favicon = match &config.server_settings.favicon {
+      Some(path) => match load_favicon(path) {
+        Ok(favicon) => Some(favicon),
+        Err(err) => {
+          warn!("Could not load favicon \"{path}\" (reason: \"{err}\"), server list will show the default icon");
+          None
+        }
+      },
+      None => None
+    };
+    let (status, _) = watch::channel(build_status(&config, &favicon, 0, Vec::new()));
+
+    //(6) Connections are accepted on their own task, which hands us the
+    //clients once they have logged in
+    let (logged_in_tx, logged_in) = mpsc::channel(MAX_QUEUE_LEN);
+    let acceptor = Acceptor::new(
+      socket,
+      tx.clone(),
+      online_mode,
+      status.subscribe(),
+      logged_in_tx,
+      Duration::from_secs(config.network_settings.handshake_timeout),
+      config.network_settings.max_pending_connections
+    ).spawn();
   
+    //(7) The console and RCON share the commands
+    let commands = Arc::new(CommandRegistry::with_builtins());
+    let rcon = match config.network_settings.rcon_enabled {
+      true => {
+        let rcon_addr = SocketAddr::new(ip, config.network_settings.rcon_port);
+        let password = &config.network_settings.rcon_password;
+        match RconServer::bind(rcon_addr, password, commands.clone(), plugins.events().clone(), tx.clone()).await {
+          Ok(rcon) => {
+            info!("RCON listening @{}", rcon_addr);
+            Some(rcon.spawn())
+          },
+          Err(err) => {
+            error!("Could not start RCON (reason: \"{err}\"), it will be disabled");
+            None
+          }
+        }
+      },
+      false => None
+    };
+
+    //(8) Server lists and monitoring can query us over UDP
+    let query = match config.network_settings.query_enabled {
+      true => {
+        let query_addr = SocketAddr::new(ip, config.network_settings.query_port);
+        match QueryServer::bind(query_addr, tx.clone(), socket_addr).await {
+          Ok(query) => {
+            info!("Query listening @{}", query_addr);
+            Some(query.spawn())
+          },
+          Err(err) => {
+            error!("Could not start query (reason: \"{err}\"), it will be disabled");
+            None
+          }
+        }
+      },
+      false => None
+    };
+
     //(R) before we return, say hi to the console
     info!("Server listening @{}", socket_addr);
     Ok(Main {
       config: config,
-      socket: socket,
+      acceptor,
+      rcon,
+      query,
+      commands,
+      logged_in,
+      clients: JoinSet::new(),
       broadcast: broadcast,
       request_queue: request_queue,
-      request_queue_tx: tx
+      request_queue_tx: tx,
+      favicon,
+      status,
+      players: PlayerRegistry::new(),
+      worlds,
+      plugins,
+      tick: 0
     })
   }
 
   pub fn connect_console(&mut self) -> Console {
-    Console::init(self.request_queue_tx.clone())
+    Console::init(self.request_queue_tx.clone(), self.commands.clone(), self.plugins.events().clone())
   }
 
   pub async fn run(&mut self) {
-    'server_tick: loop {
-      /*(1)
-        Look for clients. If no client connects within the time-out period,
-        we continue to the next step in executing the server-tick.
-      */
-      if let Ok(fut) = timeout(TCP_TIMEOUT, self.socket.accept()).await {
-        //(1a) A client connected! Let's unwrap:
-        let (connection, addr) = match fut {
-          Ok((connection, addr)) => (connection, addr),
+    /*(Note to future self)
+      The server tick no longer waits on the socket: the acceptor does the
+      handshakes and we only hear from it once a client has logged in. So we
+      simply wait for whichever comes first: a new player, a request, a client
+      task that finished, the next tick or a signal telling us to stop.
+    */
+    let stop_signal = stop_signal();
+    tokio::pin!(stop_signal);
+    let mut ticker = time::interval(TICK_DURATION);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+      tokio::select! {
+        Some((client, profile)) = self.logged_in.recv() => {
+          //(1) A client has logged in, so it can start playing
+          let events = self.plugins.events().clone();
+          self.clients.spawn(client.play(profile, self.broadcast.subscribe(), events));
+        },
+        Some(request) = self.request_queue.recv() => {
+          //(2) Answer requests until one of them tells us to stop
+          if !self.handle_request(request).await {
+            break;
+          }
+        },
+        Some(joined) = self.clients.join_next() => {
+          //(3) Clean up after clients that disconnected
+          if let Err(err) = joined {
+            warn!("Client task stopped unexpectedly: \"{err}\"");
+          }
+        },
+        _ = ticker.tick() => {
+          //(4) Plugins keep time through the ticks
+          self.tick += 1;
+          self.worlds.iter_mut().for_each(World::tick);
+          self.plugins.events().dispatch(Event::ServerTick{tick: self.tick});
+          self.plugins.tick();
+
+          //Like vanilla, the server list shows other players every 5 seconds
+          if self.tick.is_multiple_of(100) && self.players.len() > STATUS_SAMPLE_SIZE {
+            self.publish_status();
+          }
+        },
+        _ = &mut stop_signal => {
+          info!("Received stop signal");
+          break;
+        },
+        else => break
+      }
+    }
+    self.shutdown().await;
+  }
+
+  async fn handle_request(&mut self, request: ClientRequest) -> bool {
+    //Returns false once the server should stop
+    use CReqMsg::*;
+    //(1) open the request packet
+    let (msg, tx) = request.open();
+
+    //(2) match the message and answer
+    match msg {
+      ConsoleKill => {
+        //User has killed the server with a console command
+        let _ = tx.send(Ok(CReqRsp::Done));
+        return false;
+      },
+      Join{name, uuid, addr} => {
+        //A client that logged in wants to enter the game
+        let _ = tx.send(self.join(name, uuid, addr));
+      },
+      Leave{uuid} => {
+        //A player disconnected, so it should no longer be listed
+        if let Some(player) = self.players.leave(uuid) {
+          self.share_players();
+          self.plugins.events().dispatch(Event::PlayerQuit{
+            player: Player{name: FfiStr::new(&player.name), uuid}
+          });
+          self.publish_status();
+        }
+        let _ = tx.send(Ok(CReqRsp::Done));
+      },
+      ListPlayers => {
+        let _ = tx.send(Ok(CReqRsp::Players(self.players.list())));
+      },
+      StopIn{delay} => {
+        //Warn the players, then send ourselves the stop request when it's time
+        let seconds = delay.as_secs();
+        info!("Stopping the server in {seconds}s");
+        self.broadcast_chat(format!("The server will stop in {seconds} seconds"));
+        let server_handle = self.request_queue_tx.clone();
+        tokio::spawn(async move {
+          tokio::time::sleep(delay).await;
+          let _ = ClientRequest::send(ConsoleKill, server_handle).await;
+        });
+        let _ = tx.send(Ok(CReqRsp::Done));
+      },
+      Kick{name, reason} => {
+        //The player's own client does the disconnecting
+        let _ = tx.send(self.players.find(&name)
+          .map(|player| {
+            let reason = reason.unwrap_or_else(|| "Kicked by an operator".to_string());
+            info!("Kicking player \"{}\": \"{reason}\"", player.name);
+            let _ = self.broadcast.send(BroadcastMsg::Kick{uuid: player.uuid, reason});
+            CReqRsp::Done
+          })
+          .ok_or_else(|| CReqDenied::new(format!("No player named \"{name}\" is online")))
+        );
+      },
+      Say{message} => {
+        info!("[Server] {message}");
+        self.broadcast_chat(format!("[Server] {message}"));
+        let _ = tx.send(Ok(CReqRsp::Done));
+      },
+      Chat{name, message} => {
+        info!("<{name}> {message}");
+        self.broadcast_chat(format!("<{name}> {message}"));
+        let _ = tx.send(Ok(CReqRsp::Done));
+      },
+      PluginCommand{source, line} => {
+        let _ = tx.send(self.plugin_command(&source, &line));
+      },
+      Teleport{name, world, position} => {
+        let _ = tx.send(self.teleport(&name, world, position));
+      },
+      SaveAll => {
+        self.save_worlds().await;
+        let _ = tx.send(Ok(CReqRsp::Done));
+      },
+      ListWorlds => {
+        let worlds = self.worlds.iter().map(|world| world.name().to_string()).collect();
+        let _ = tx.send(Ok(CReqRsp::Worlds(worlds)));
+      },
+      ListPlugins => {
+        let _ = tx.send(Ok(CReqRsp::Plugins(self.plugin_names())));
+      },
+      ReloadPlugin{name} => {
+        let rsp = match self.plugins.reload(&name) {
+          Ok(()) => Ok(CReqRsp::Lines(vec![format!("Reloaded plugin \"{name}\"")])),
           Err(err) => {
-            warn!("could not accept client connection: \"{err}\"");
-            return;
+            error!("Could not reload plugin \"{name}\" (reason: \"{err}\")");
+            Err(CReqDenied::new(err.to_string()))
           }
         };
+        let _ = tx.send(rsp);
+      },
+      ServerInfo => {
+        let info = crate::messages::client_request::ServerInfo {
+          motd: self.config.server_settings.motd.clone(),
+          map: self.config.world_settings.default.clone(),
+          max_players: self.config.server_settings.max_players,
+          players: self.players.list().into_iter().map(|player| player.name).collect(),
+          plugins: self.plugin_names()
+        };
+        let _ = tx.send(Ok(CReqRsp::ServerInfo(info)));
+      },
+      Reload => {
+        let _ = tx.send(self.reload());
+      },
+      UpdateLatency{uuid, latency} => {
+        //Store the latency and pass it on to every client's player list
+        debug!("Player {} has a latency of {}ms", MCUuid::from(uuid), latency.as_millis());
+        self.players.update_ping(uuid, latency);
+        self.share_players();
+        let _ = self.broadcast.send(BroadcastMsg::PlayerLatency{uuid, latency});
+        let _ = tx.send(Ok(CReqRsp::Done));
+      }
+    }
+    true
+  }
+
+  fn join(&mut self, name: String, uuid: u128, addr: SocketAddr) -> Result<CReqRsp, CReqDenied> {
+    //(1) Refuse the player if the server is full
+    if self.players.len() >= self.config.server_settings.max_players {
+      return Err(CReqDenied::new("The server is full!"));
+    }
+
+    //(2) New players always start out in the default world
+    let entry = PlayerEntry {
+      name: name.clone(),
+      uuid,
+      addr,
+      ping: Duration::ZERO,
+      world: self.config.world_settings.default.clone()
+    };
+    self.players.join(entry).map_err(CReqDenied::new)?;
+    self.share_players();
+
+    //(3) Plugins only hear about players that could actually join, and may
+    //still refuse them
+    let cancelled = self.plugins.events().dispatch(Event::PlayerJoin{
+      player: Player{name: FfiStr::new(&name), uuid}
+    });
+    if cancelled {
+      self.players.leave(uuid);
+      self.share_players();
+      return Err(CReqDenied::new("You are not allowed to join this server"));
+    }
+
+    //(R) The server list should show the new player
+    self.publish_status();
+    Ok(CReqRsp::Done)
+  }
 
-        //(1b) Initialise the client (or drop it if it does not want to login)
-        if let Some(client) = Client::init(
-          connection,
-          addr,
-          self.broadcast.subscribe(),
-          self.request_queue_tx.clone()
-        ).await {
-          //(1c) Client has successfully passed the init (handshake) phase
-          //next, we must tell it to log-in (we LOSE ownership of the client)
-          client.login();
+  fn teleport(&mut self, name: &str, world: String, position: Option<(i32, i32, i32)>)
+    -> Result<CReqRsp, CReqDenied>
+  {
+    //(1) Both the player and the world have to exist
+    let uuid = match self.players.find(name) {
+      Some(player) => player.uuid,
+      None => return Err(CReqDenied::new(format!("No player named \"{name}\" is online")))
+    };
+    if !self.worlds.iter().any(|loaded| loaded.name() == world) {
+      return Err(CReqDenied::new(format!("There is no world named \"{world}\"")));
+    }
+
+    //(2) Move the player, to the middle of the block if one was given
+    info!("Teleporting player \"{name}\" to world \"{world}\"");
+    self.players.set_world(uuid, world);
+    self.share_players();
+    if let Some((x, y, z)) = position {
+      let (x, y, z) = (x as f64 + 0.5, y as f64, z as f64 + 0.5);
+      let _ = self.broadcast.send(BroadcastMsg::Teleport{uuid, x, y, z});
+    }
+    Ok(CReqRsp::Done)
+  }
+
+  fn plugin_command(&self, source: &CommandSource, line: &str) -> Result<CReqRsp, CReqDenied> {
+    //Plugin commands run here, on the same task as the plugins' other tasks
+    let sender = match source {
+      CommandSource::Console => CommandSender::Console,
+      CommandSource::Rcon => CommandSender::Rcon,
+      CommandSource::Player{name, uuid} => CommandSender::Player(Player{name: FfiStr::new(name), uuid: *uuid})
+    };
+    let allowed = |node: &str| match source {
+      CommandSource::Player{name, ..} => permissions::allows(&self.config.permission_settings, name, node),
+      _ => true
+    };
+    self.plugins.execute_command(sender, line, allowed)
+      .map(CReqRsp::Lines)
+      .map_err(|err| CReqDenied::new(err.to_string()))
+  }
+
+  fn reload(&mut self) -> Result<CReqRsp, CReqDenied> {
+    /*(Note to future self)
+      Only the settings we read while running (like the motd and the player
+      limit) change right away. Anything we use at startup, like the port and
+      the worlds, still needs a restart.
+    */
+    let config = crate::config::load_config()
+      .map_err(|err| CReqDenied::new(format!("Could not reload config: {err}")))?;
+    self.config = config;
+    self.publish_status();
+    info!("Reloaded the config file, network and world settings apply after a restart");
+    Ok(CReqRsp::Done)
+  }
+
+  async fn save_worlds(&mut self) {
+    //Saving waits for the disk, so the worlds go to a blocking thread and
+    //come back once they're written
+    let mut worlds = std::mem::take(&mut self.worlds);
+    let saving = task::spawn_blocking(move || {
+      for world in worlds.iter_mut() {
+        if let Err(err) = world.save() {
+          error!("Could not save world \"{}\" (reason: \"{err}\")", world.name());
         }
       }
+      worlds
+    });
+    match saving.await {
+      Ok(worlds) => self.worlds = worlds,
+      Err(err) => error!("Saving the worlds stopped unexpectedly (reason: \"{err}\")")
+    }
+  }
+
+  fn plugin_names(&self) -> Vec<String> {
+    //Plugins that aren't running get their state behind their name
+    self.plugins.plugins()
+      .map(|(name, version, state)| match state {
+        PluginState::Started => format!("{name} {version}"),
+        other => format!("{name} {version} ({other:?})")
+      })
+      .collect()
+  }
+
+  fn broadcast_chat(&self, text: String) {
+    let message = MCChat::from(json!({"text": text}));
+    let _ = self.broadcast.send(BroadcastMsg::Chat{message});
+  }
+
+  fn publish_status(&self) {
+    let sample = self.players.status_sample();
+    self.status.send_replace(build_status(&self.config, &self.favicon, self.players.len(), sample));
+  }
+
+  fn share_players(&self) {
+    //Plugins run while we're busy calling them, so they can't ask us. They
+    //get a copy of the registry every time it changes instead
+    let players = self.players.list().into_iter()
+      .map(|player| OnlinePlayer{name: player.name, uuid: player.uuid, ping: player.ping, world: player.world})
+      .collect();
+    self.plugins.players().set(players);
+  }
+
+  async fn shutdown(&mut self) {
+    info!("Shutting down...");
+    //(1) Stop accepting new connections (players, RCON and queries), and drop
+    //the players that are still logging in
+    self.acceptor.abort();
+    self.logged_in.close();
+    for listener in [&self.rcon, &self.query].into_iter().flatten() {
+      listener.abort();
+    }
+
+    //(2) Tell every client to disconnect its player
+    let reason = self.config.server_settings.shutdown_message.clone();
+    let _ = self.broadcast.send(BroadcastMsg::Die{reason});
 
-      /*(2)
-        Next we execute the server tick.
-      */
-      while let Ok(maybe) = timeout(TASK_TIMEOUT, self.request_queue.recv()).await {
-        if let Some(request) = maybe {
-          use CReqMsg::*;
-          //(2a) open the request packet
-          let (msg, tx) = request.open();
-
-          //(2b) match the message and answer
-          match msg {
-            ConsoleKill => {
-              //User has killed the server with a console command
-              tx.send(Ok(CReqRsp::Done)).unwrap();
-              self.shutdown().await;
-              break 'server_tick;
-            }
+    /*(3)
+      Wait for the clients to finish. They still send us requests while they
+      disconnect (to leave the registry), so we have to keep answering them or
+      we would be waiting on each other.
+    */
+    let mut clients = std::mem::take(&mut self.clients);
+    let grace_period = Duration::from_secs(self.config.server_settings.shutdown_timeout);
+    let drained = timeout(grace_period, async {
+      loop {
+        tokio::select! {
+          joined = clients.join_next() => match joined {
+            Some(Err(err)) => warn!("Client task stopped unexpectedly: \"{err}\""),
+            Some(Ok(())) => {},
+            None => break
+          },
+          Some(request) = self.request_queue.recv() => {
+            self.handle_request(request).await;
           }
         }
       }
+    }).await;
+    if drained.is_err() {
+      warn!("{} clients did not disconnect in time, stopping them anyway", clients.len());
+      clients.shutdown().await;
     }
+
+    //(4) Plugins may still want to save something to the worlds
+    self.plugins.stop_all();
+
+    //(5) Write the worlds to disk
+    self.save_worlds().await;
+    info!("Shutdown complete");
   }
 
-  pub async fn shutdown(&mut self) {
-    info!("Shutting down...");
+}
+
+async fn stop_signal() {
+  //(1) Ctrl+C works everywhere, if we can't listen for it we just never stop
+  let ctrl_c = async {
+    if let Err(err) = tokio::signal::ctrl_c().await {
+      warn!("Could not listen for Ctrl+C (reason: \"{err}\")");
+      std::future::pending::<()>().await;
+    }
+  };
+
+  //(2) Service managers (like systemd) stop us with SIGTERM instead
+  #[cfg(unix)]
+  let terminate = async {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+      Ok(mut sigterm) => {sigterm.recv().await;},
+      Err(err) => {
+        warn!("Could not listen for SIGTERM (reason: \"{err}\")");
+        std::future::pending::<()>().await;
+      }
+    }
+  };
+  #[cfg(not(unix))]
+  let terminate = std::future::pending::<()>();
+
+  //(R) Whichever comes first
+  tokio::select! {
+    _ = ctrl_c => {},
+    _ = terminate => {}
+  }
+}
+
+fn build_status(config: &Config, favicon: &Option<String>, online: usize, sample: Vec<StatusPlayerSample>)
+  -> ServerStatus
+{
+  ServerStatus::builder()
+    .motd(&config.server_settings.motd)
+    .max_players(config.server_settings.max_players)
+    .players(online, sample)
+    .favicon(favicon.clone())
+    .build()
+}
+
+fn load_favicon(path: &str) -> Result<String, Box<dyn Error>> {
+  //(1) Read the file and check that it is a PNG
+  let png = fs::read(path)?;
+  if png.len() < 24 || &png[..8] != PNG_SIGNATURE {
+    return Err("file is not a PNG image".into());
+  }
+
+  //(2) The IHDR chunk always comes first and starts with the width and height
+  let width = u32::from_be_bytes([png[16], png[17], png[18], png[19]]);
+  let height = u32::from_be_bytes([png[20], png[21], png[22], png[23]]);
+  if width != FAVICON_SIZE || height != FAVICON_SIZE {
+    return Err(format!("image must be {FAVICON_SIZE}x{FAVICON_SIZE} pixels, not {width}x{height}").into());
   }
 
+  //(R) The status response wants the raw file in base64
+  Ok(BASE64.encode(png))
 }
\ No newline at end of file
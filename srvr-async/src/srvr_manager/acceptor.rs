@@ -0,0 +1,227 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use log::{info, warn, debug};
+use srvr_sysproto::packets::ServerStatus;
+use tokio::{
+  net::{TcpListener, TcpStream},
+  sync::{mpsc, watch, Semaphore, OwnedSemaphorePermit},
+  task::JoinHandle,
+  time::{sleep, timeout_at, Instant}
+};
+
+use crate::{
+  messages::client_request::ClientRequest,
+  client::{
+    Client,
+    auth::{OnlineMode, GameProfile}
+  }
+};
+
+//Accept errors are usually a lack of file descriptors, so give them some time
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+//A client that has finished the login, ready to be handed to the manager
+pub type LoggedIn = (Client, GameProfile);
+
+#[derive(Debug)]
+pub struct Acceptor {
+  socket: TcpListener,
+  request_queue_tx: mpsc::Sender<ClientRequest>,
+  online_mode: Option<Arc<OnlineMode>>,
+  status: watch::Receiver<ServerStatus>,
+  logged_in: mpsc::Sender<LoggedIn>,
+  handshake_timeout: Duration,
+  pending: Arc<Semaphore>
+}
+
+impl Acceptor {
+
+  pub fn new(
+    socket: TcpListener,
+    request_queue_tx: mpsc::Sender<ClientRequest>,
+    online_mode: Option<Arc<OnlineMode>>,
+    status: watch::Receiver<ServerStatus>,
+    logged_in: mpsc::Sender<LoggedIn>,
+    handshake_timeout: Duration,
+    max_pending_connections: usize
+  ) -> Self {
+    Acceptor {
+      socket,
+      request_queue_tx,
+      online_mode,
+      status,
+      logged_in,
+      handshake_timeout,
+      pending: Arc::new(Semaphore::new(max_pending_connections))
+    }
+  }
+
+  pub fn spawn(self) -> JoinHandle<()> {
+    tokio::spawn(self.run())
+  }
+
+  async fn run(self) {
+    /*(Note to future self)
+      The acceptor only accepts connections and never waits on a client itself:
+      every connection gets its own task for the handshake and login. This way
+      a slow (or malicious) client can't hold up the other connections, nor
+      the server tick. We stop once the manager stops listening for clients.
+    */
+    loop {
+      //(1) Wait for a client, or for the manager to go away
+      let accepted = tokio::select! {
+        accepted = self.socket.accept() => accepted,
+        _ = self.logged_in.closed() => return
+      };
+      let (connection, addr) = match accepted {
+        Ok((connection, addr)) => (connection, addr),
+        Err(err) => {
+          //A failed accept only affects that one connection
+          warn!("could not accept client connection: \"{err}\"");
+          sleep(ACCEPT_BACKOFF).await;
+          continue;
+        }
+      };
+
+      //(2) Refuse the connection if too many clients are still logging in
+      let permit = match self.pending.clone().try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+          warn!("Too many pending connections, refusing client @{}", addr);
+          continue;
+        }
+      };
+
+      //(3) Do the handshake and login on a task of its own
+      let handshake = Handshake {
+        connection,
+        addr,
+        request_queue_tx: self.request_queue_tx.clone(),
+        online_mode: self.online_mode.clone(),
+        status: self.status.clone(),
+        logged_in: self.logged_in.clone(),
+        deadline: Instant::now() + self.handshake_timeout,
+        _permit: permit
+      };
+      tokio::spawn(handshake.run());
+    }
+  }
+
+}
+
+struct Handshake {
+  connection: TcpStream,
+  addr: SocketAddr,
+  request_queue_tx: mpsc::Sender<ClientRequest>,
+  online_mode: Option<Arc<OnlineMode>>,
+  status: watch::Receiver<ServerStatus>,
+  logged_in: mpsc::Sender<LoggedIn>,
+  deadline: Instant,
+  //Held until the connection is handed over or dropped
+  _permit: OwnedSemaphorePermit
+}
+
+impl Handshake {
+
+  async fn run(self) {
+    //(1) Handshake, and answer the server-list ping if that's all it wants
+    let mut client = match timeout_at(self.deadline, Client::init(
+      self.connection,
+      self.addr,
+      self.request_queue_tx,
+      self.online_mode,
+      self.status
+    )).await {
+      Ok(Some(client)) => client,
+      Ok(None) => return,
+      Err(_) => {
+        info!("Client @{} took too long to finish the handshake", self.addr);
+        return;
+      }
+    };
+
+    //(2) Log the client in before the same deadline
+    let profile = match timeout_at(self.deadline, client.login()).await {
+      Ok(Some(profile)) => profile,
+      Ok(None) => return,
+      Err(_) => {
+        info!("Client @{} took too long to log in", self.addr);
+        client.disconnect_login("Took too long to log in").await;
+        return;
+      }
+    };
+
+    //(3) Only fully logged-in clients are handed to the manager
+    if self.logged_in.send((client, profile)).await.is_err() {
+      debug!("Server is shutting down, dropping client @{}", self.addr);
+    }
+  }
+
+}
+
+#[cfg(test)]
+mod acceptor_test {
+  use super::*;
+  use srvr_sysproto::packets::ServerStatus;
+  use tokio::io::AsyncReadExt;
+
+  async fn start(handshake_timeout: Duration, max_pending: usize)
+    -> (SocketAddr, mpsc::Receiver<LoggedIn>)
+  {
+    let socket = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let (request_queue_tx, _) = mpsc::channel(1);
+    let (_, status) = watch::channel(ServerStatus::builder().build());
+    let (logged_in, logged_in_rx) = mpsc::channel(1);
+    Acceptor::new(
+      socket, request_queue_tx, None, status, logged_in, handshake_timeout, max_pending
+    ).spawn();
+    (addr, logged_in_rx)
+  }
+
+  #[tokio::test]
+  async fn handshake_deadline() {
+    //A client that never says anything is dropped after the deadline
+    let (addr, _logged_in) = start(Duration::from_millis(50), 1).await;
+    let mut idle = TcpStream::connect(addr).await.unwrap();
+    let mut buf = [0u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(5), idle.read(&mut buf)).await;
+    assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))));
+  }
+
+  #[tokio::test]
+  async fn connection_limit() {
+    //The second connection is refused while the first is still pending
+    let (addr, _logged_in) = start(Duration::from_secs(60), 1).await;
+    let _pending = TcpStream::connect(addr).await.unwrap();
+    let mut refused = TcpStream::connect(addr).await.unwrap();
+    let mut buf = [0u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(5), refused.read(&mut buf)).await;
+    assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))));
+  }
+
+}
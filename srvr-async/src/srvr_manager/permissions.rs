@@ -0,0 +1,81 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use crate::config::PermissionSettings;
+
+/*(Note to future self)
+  Permission nodes are dot-separated names like "homes.set". Granting
+  "homes.*" grants every node under "homes.", and "*" grants everything.
+  Only players are checked, the console and RCON may do anything.
+*/
+
+pub fn allows(settings: &PermissionSettings, player: &str, node: &str) -> bool {
+  //(1) Operators may do anything
+  if settings.operators.iter().any(|operator| operator.eq_ignore_ascii_case(player)) {
+    return true;
+  }
+
+  //(2) Otherwise they need a node that matches, from the defaults or their own
+  let own = settings.players.iter()
+    .filter(|(name, _)| name.eq_ignore_ascii_case(player))
+    .flat_map(|(_, nodes)| nodes);
+  settings.default.iter().chain(own).any(|granted| grants(granted, node))
+}
+
+fn grants(granted: &str, node: &str) -> bool {
+  match granted.strip_suffix('*') {
+    Some(prefix) => (prefix.is_empty() || prefix.ends_with('.')) && node.starts_with(prefix),
+    None => granted == node
+  }
+}
+
+#[cfg(test)]
+mod permissions_test {
+  use std::collections::HashMap;
+
+  use super::*;
+
+  #[test]
+  fn nodes() {
+    let settings = PermissionSettings {
+      operators: vec!["Notch".to_string()],
+      default: vec!["homes.home".to_string()],
+      players: HashMap::from([
+        ("jeb_".to_string(), vec!["homes.*".to_string(), "warps.list".to_string()])
+      ])
+    };
+
+    //Everyone gets the defaults, operators get everything
+    assert!(allows(&settings, "Dinnerbone", "homes.home"));
+    assert!(!allows(&settings, "Dinnerbone", "homes.set"));
+    assert!(allows(&settings, "notch", "anything.at.all"));
+
+    //Wildcards only match whole parts
+    assert!(allows(&settings, "JEB_", "homes.set"));
+    assert!(allows(&settings, "jeb_", "homes.set.other"));
+    assert!(!allows(&settings, "jeb_", "homesick"));
+    assert!(!allows(&settings, "jeb_", "warps.set"));
+    assert!(grants("*", "warps.set"));
+  }
+}
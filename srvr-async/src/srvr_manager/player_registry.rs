@@ -0,0 +1,170 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use rand::seq::IteratorRandom;
+use srvr_sysproto::packets::StatusPlayerSample;
+
+//Vanilla never lists more players than this in the server list
+pub const STATUS_SAMPLE_SIZE: usize = 12;
+
+/*(Note to future self)
+  The registry is owned by the server manager, which is the only one that may
+  change it. The console and the status response ask the manager for a copy
+  of the entries through a [CReqMsg]. Plugins are called by the manager, so
+  it hands them a new copy whenever the registry changes.
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerEntry {
+  pub name: String,
+  pub uuid: u128,
+  pub addr: SocketAddr,
+  //Smoothed keep-alive latency, zero until the first keep-alive returns
+  pub ping: Duration,
+  //Name of the world the player is in
+  pub world: String
+}
+
+#[derive(Debug, Default)]
+pub struct PlayerRegistry {
+  players: HashMap<u128, PlayerEntry>
+}
+
+impl PlayerRegistry {
+
+  pub fn new() -> Self {Self::default()}
+
+  pub fn len(&self) -> usize {self.players.len()}
+
+  pub fn join(&mut self, entry: PlayerEntry) -> Result<(), String> {
+    //The same account can't be online twice
+    if self.players.contains_key(&entry.uuid) {
+      return Err(format!("Player \"{}\" is already online", entry.name));
+    }
+    self.players.insert(entry.uuid, entry);
+    Ok(())
+  }
+
+  pub fn leave(&mut self, uuid: u128) -> Option<PlayerEntry> {
+    self.players.remove(&uuid)
+  }
+
+  pub fn update_ping(&mut self, uuid: u128, ping: Duration) {
+    if let Some(player) = self.players.get_mut(&uuid) {
+      player.ping = ping;
+    }
+  }
+
+  pub fn set_world(&mut self, uuid: u128, world: String) {
+    if let Some(player) = self.players.get_mut(&uuid) {
+      player.world = world;
+    }
+  }
+
+  pub fn find(&self, name: &str) -> Option<&PlayerEntry> {
+    //Minecraft usernames are case-insensitive
+    self.players.values().find(|player| player.name.eq_ignore_ascii_case(name))
+  }
+
+  pub fn list(&self) -> Vec<PlayerEntry> {
+    //Sorted by name, so listings don't change order between calls
+    let mut players: Vec<PlayerEntry> = self.players.values().cloned().collect();
+    players.sort_by_key(|player| player.name.to_lowercase());
+    players
+  }
+
+  pub fn status_sample(&self) -> Vec<StatusPlayerSample> {
+    //A random few, like vanilla, so the status doesn't grow with the server
+    self.players.values()
+      .choose_multiple(&mut rand::thread_rng(), STATUS_SAMPLE_SIZE)
+      .into_iter()
+      .map(|player| StatusPlayerSample::new(player.name.clone(), player.uuid))
+      .collect()
+  }
+
+}
+
+#[cfg(test)]
+mod player_registry_test {
+  use super::*;
+
+  fn entry(name: &str, uuid: u128) -> PlayerEntry {
+    PlayerEntry {
+      name: name.to_string(),
+      uuid,
+      addr: "127.0.0.1:25565".parse().unwrap(),
+      ping: Duration::ZERO,
+      world: "lobby".to_string()
+    }
+  }
+
+  #[test]
+  fn join_leave() {
+    let mut registry = PlayerRegistry::new();
+    registry.join(entry("Notch", 1)).unwrap();
+    registry.join(entry("jeb_", 2)).unwrap();
+    assert!(registry.join(entry("Notch", 1)).is_err());
+    assert_eq!(registry.len(), 2);
+
+    assert_eq!(registry.leave(1).map(|player| player.name), Some("Notch".to_string()));
+    assert_eq!(registry.leave(1), None);
+    assert_eq!(registry.len(), 1);
+  }
+
+  #[test]
+  fn queries() {
+    let mut registry = PlayerRegistry::new();
+    registry.join(entry("jeb_", 2)).unwrap();
+    registry.join(entry("Notch", 1)).unwrap();
+    registry.update_ping(1, Duration::from_millis(42));
+
+    registry.update_ping(3, Duration::from_millis(42));
+
+    registry.set_world(2, "nether".to_string());
+    assert_eq!(registry.find("JEB_").map(|player| player.world.as_str()), Some("nether"));
+    assert!(registry.find("Dinnerbone").is_none());
+
+    let players = registry.list();
+    let names: Vec<&str> = players.iter().map(|player| player.name.as_str()).collect();
+    assert_eq!(names, vec!["jeb_", "Notch"]);
+    assert_eq!(players[1].ping, Duration::from_millis(42));
+    assert!(registry.status_sample().contains(&StatusPlayerSample::new("Notch".to_string(), 1)));
+  }
+
+  #[test]
+  fn status_sample_is_capped() {
+    let mut registry = PlayerRegistry::new();
+    for uuid in 0..100 {
+      registry.join(entry(&format!("player{uuid}"), uuid)).unwrap();
+    }
+    let sample = registry.status_sample();
+    assert_eq!(sample.len(), STATUS_SAMPLE_SIZE);
+    for (index, player) in sample.iter().enumerate() {
+      assert!(!sample[..index].contains(player));
+    }
+  }
+
+}
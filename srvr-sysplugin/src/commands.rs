@@ -0,0 +1,292 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+//! Commands that plugins add to the server.
+//!
+//! The server runs them for the console, RCON and players alike, after
+//! checking the sender has the command's permission node.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    ffi::c_void,
+    fmt::{self, Debug, Display, Formatter},
+    panic::{self, AssertUnwindSafe},
+    sync::{Mutex, MutexGuard, PoisonError}
+};
+
+use log::{error, warn};
+
+use crate::{events::Player, ffi::FfiStr};
+
+#[repr(C, u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum CommandSender<'a> {
+    Console,
+    Rcon,
+    Player(Player<'a>)
+}
+
+impl<'a> CommandSender<'a> {
+    pub fn name(&self) -> &'a str {
+        match self {
+            CommandSender::Console => "Console",
+            CommandSender::Rcon => "RCON",
+            CommandSender::Player(player) => player.name.as_str()
+        }
+    }
+}
+
+/// A command being run, passed to its handler
+#[repr(C)]
+pub struct CommandCall<'a> {
+    sender: CommandSender<'a>,
+    args: FfiStr<'a>,
+    replies: *mut c_void,
+    reply: unsafe extern "C" fn(*mut c_void, FfiStr)
+}
+
+impl<'a> CommandCall<'a> {
+    pub fn sender(&self) -> &CommandSender<'a> {&self.sender}
+
+    /// Everything after the command's name
+    pub fn args(&self) -> &'a str {self.args.as_str()}
+
+    pub fn arg(&self, index: usize) -> Option<&'a str> {
+        self.args().split_whitespace().nth(index)
+    }
+
+    /// Sends a line back to whoever ran the command
+    pub fn reply(&self, line: &str) {
+        unsafe { (self.reply)(self.replies, FfiStr::new(line)) }
+    }
+}
+
+unsafe extern "C" fn collect_reply(replies: *mut c_void, line: FfiStr) {
+    (*(replies as *mut Vec<String>)).push(line.to_string());
+}
+
+/// A command handler behind a C function table, like a
+/// [`RawListener`](crate::events::RawListener)
+#[repr(C)]
+pub struct RawCommand {
+    data: *mut c_void,
+    call: unsafe extern "C" fn(*mut c_void, *const CommandCall) -> bool,
+    drop: unsafe extern "C" fn(*mut c_void)
+}
+
+//The closures are required to be Send
+unsafe impl Send for RawCommand {}
+
+impl RawCommand {
+    pub fn new<F>(handler: F) -> Self
+        where F: FnMut(&CommandCall) + Send + 'static
+    {
+        RawCommand {
+            data: Box::into_raw(Box::new(handler)) as *mut c_void,
+            call: call_shim::<F>,
+            drop: drop_shim::<F>
+        }
+    }
+}
+
+unsafe extern "C" fn call_shim<F: FnMut(&CommandCall)>(data: *mut c_void, call: *const CommandCall) -> bool {
+    let handler = &mut *(data as *mut F);
+    panic::catch_unwind(AssertUnwindSafe(|| handler(&*call))).is_ok()
+}
+
+unsafe extern "C" fn drop_shim<F>(data: *mut c_void) {
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(data as *mut F))));
+}
+
+impl Drop for RawCommand {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.data) }
+    }
+}
+
+struct Registered {
+    owner: String,
+    description: String,
+    permission: Option<String>,
+    handler: RawCommand
+}
+
+/// Every plugin command, by name
+#[derive(Default)]
+pub struct CommandMap {
+    commands: Mutex<BTreeMap<String, Registered>>
+}
+
+impl CommandMap {
+
+    pub fn new() -> Self {Self::default()}
+
+    fn commands(&self) -> MutexGuard<'_, BTreeMap<String, Registered>> {
+        //Handlers can't panic while we hold the lock
+        self.commands.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The first plugin to claim a name gets it, names are case-insensitive
+    pub fn register(&self, owner: &str, name: &str, description: &str, permission: Option<&str>, handler: RawCommand) {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            error!("Plugin \"{owner}\" tried to add a command named \"{name}\", names can't be empty or contain spaces");
+            return;
+        }
+        let name = name.to_lowercase();
+        let mut commands = self.commands();
+        if let Some(existing) = commands.get(&name) {
+            warn!("Plugin \"{owner}\" can't add command \"{name}\", plugin \"{}\" already did", existing.owner);
+            return;
+        }
+        commands.insert(name, Registered {
+            owner: owner.to_string(),
+            description: description.to_string(),
+            permission: permission.map(str::to_string),
+            handler
+        });
+    }
+
+    pub fn unregister(&self, owner: &str) {
+        self.commands().retain(|_, registered| registered.owner != owner);
+    }
+
+    /// Runs a line like "home set bed", and returns the lines to reply with.
+    /// `allowed` tells whether the sender has a permission node
+    pub fn execute(&self, sender: CommandSender, line: &str, allowed: impl Fn(&str) -> bool)
+        -> Result<Vec<String>, CommandError>
+    {
+        //(1) Find the command
+        let line = line.trim();
+        let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mut commands = self.commands();
+        let registered = commands.get_mut(&name.to_lowercase())
+            .ok_or_else(|| CommandError::Unknown(name.to_string()))?;
+
+        //(2) Check that the sender may use it
+        if let Some(permission) = &registered.permission {
+            if !allowed(permission) {
+                return Err(CommandError::NotPermitted);
+            }
+        }
+
+        //(3) Run it, collecting its replies
+        let mut replies: Vec<String> = Vec::new();
+        let call = CommandCall {
+            sender,
+            args: FfiStr::new(args.trim()),
+            replies: &mut replies as *mut Vec<String> as *mut c_void,
+            reply: collect_reply
+        };
+        let handler = &mut registered.handler;
+        if !unsafe { (handler.call)(handler.data, &call) } {
+            error!("Command \"{name}\" of plugin \"{}\" panicked", registered.owner);
+            return Err(CommandError::Failed);
+        }
+        Ok(replies)
+    }
+
+    /// Name and description of every command, sorted by name
+    pub fn list(&self) -> Vec<(String, String)> {
+        self.commands().iter()
+            .map(|(name, registered)| (name.clone(), registered.description.clone()))
+            .collect()
+    }
+
+    /// How many commands this plugin still has registered
+    pub fn owned_by(&self, owner: &str) -> usize {
+        self.commands().values().filter(|registered| registered.owner == owner).count()
+    }
+
+}
+
+impl Debug for CommandMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.commands().keys()).finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    Unknown(String),
+    NotPermitted,
+    Failed
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Unknown(name) => write!(f, "Unknown command \"{name}\""),
+            CommandError::NotPermitted => write!(f, "You don't have permission to use this command"),
+            CommandError::Failed => write!(f, "An error occurred while running this command")
+        }
+    }
+}
+impl Error for CommandError {}
+
+#[cfg(test)]
+mod commands_test {
+    use super::*;
+
+    fn greet() -> RawCommand {
+        RawCommand::new(|call: &CommandCall| {
+            let name = call.arg(0).unwrap_or(call.sender().name());
+            call.reply(&format!("Hello, {name}!"));
+        })
+    }
+
+    #[test]
+    fn execute() {
+        let commands = CommandMap::new();
+        commands.register("greeter", "greet", "Says hello", Some("greeter.greet"), greet());
+        commands.register("other", "greet", "Also says hello", None, greet());
+        commands.register("other", "say hi", "Not a valid name", None, greet());
+        assert_eq!(commands.list(), vec![("greet".to_string(), "Says hello".to_string())]);
+
+        //Arguments, and who sent it
+        let result = commands.execute(CommandSender::Console, "GREET  jeb_ ", |_| true);
+        assert_eq!(result, Ok(vec!["Hello, jeb_!".to_string()]));
+        let result = commands.execute(CommandSender::Rcon, "greet", |_| true);
+        assert_eq!(result, Ok(vec!["Hello, RCON!".to_string()]));
+
+        //Permissions and unknown commands
+        let result = commands.execute(CommandSender::Console, "greet", |node| node != "greeter.greet");
+        assert_eq!(result, Err(CommandError::NotPermitted));
+        let result = commands.execute(CommandSender::Console, "wave", |_| true);
+        assert_eq!(result, Err(CommandError::Unknown("wave".to_string())));
+
+        //Gone with their plugin
+        commands.unregister("greeter");
+        assert!(commands.list().is_empty());
+    }
+
+    #[test]
+    fn panicking_command() {
+        let commands = CommandMap::new();
+        commands.register("broken", "boom", "", None, RawCommand::new(|_: &CommandCall| panic!("boom")));
+        let result = commands.execute(CommandSender::Console, "boom", |_| true);
+        assert_eq!(result, Err(CommandError::Failed));
+    }
+
+}
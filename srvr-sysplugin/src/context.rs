@@ -0,0 +1,238 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::{ffi::c_void, fs, io::ErrorKind, path::PathBuf, sync::Arc};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    commands::{CommandCall, CommandMap, RawCommand},
+    events::{Dispatch, EventBus, EventKind, Priority, RawListener},
+    ffi::{FfiStr, HostApi},
+    logger::{self, PluginLogger},
+    players::{PlayerList, Players, PlayersApi},
+    scheduler::{Scheduler, SchedulerApi, TaskOwner, TaskScheduler},
+    PluginError
+};
+
+/// Read by [`PluginContext::config`], from the plugin's data folder
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// Handed to [`Plugin::start`](crate::Plugin::start), this is how a plugin
+/// hooks into the server
+pub struct PluginContext<'a> {
+    api: &'a HostApi
+}
+
+impl<'a> PluginContext<'a> {
+    pub(crate) fn new(api: &'a HostApi) -> Self {
+        PluginContext {api}
+    }
+
+    pub(crate) fn api(&self) -> *const HostApi {self.api}
+
+    /// Call `listener` for every event of this kind, until the plugin stops
+    pub fn listen<F>(&mut self, kind: EventKind, priority: Priority, listener: F)
+        where F: FnMut(&mut Dispatch) + Send + 'static
+    {
+        unsafe { (self.api.listen)(self.api.host, kind, priority, RawListener::new(listener)) }
+    }
+
+    /// Adds a command for the console, RCON and players. Players need the
+    /// permission node, if there is one.
+    pub fn command<F>(&mut self, name: &str, description: &str, permission: Option<&str>, handler: F)
+        where F: FnMut(&CommandCall) + Send + 'static
+    {
+        let permission = FfiStr::new(permission.unwrap_or(""));
+        let handler = RawCommand::new(handler);
+        unsafe { (self.api.command)(self.api.host, FfiStr::new(name), FfiStr::new(description), permission, handler) }
+    }
+
+    /// Schedules tasks on the server tick, for as long as the plugin runs
+    pub fn scheduler(&mut self) -> Scheduler {
+        Scheduler::new(unsafe { (self.api.scheduler)(self.api.host) })
+    }
+
+    /// Looks up who is online, at any time and from any thread
+    pub fn players(&self) -> Players {
+        Players::new(unsafe { (self.api.players)(self.api.host) })
+    }
+
+    /// Logs through the server, tagged with the plugin's name
+    pub fn logger(&self) -> PluginLogger {
+        let name = unsafe { (self.api.name)(self.api.host) };
+        PluginLogger::new(name.to_string(), self.api.log)
+    }
+
+    /// `plugins/<name>/`, which the server creates before starting the plugin
+    pub fn data_folder(&self) -> PathBuf {
+        PathBuf::from(unsafe { (self.api.data_folder)(self.api.host) }.as_str())
+    }
+
+    /// Reads [`CONFIG_FILE`] from the data folder. If there is none yet, the
+    /// defaults are written there first, for the operator to edit.
+    pub fn config<T: Serialize + DeserializeOwned + Default>(&self) -> Result<T, PluginError> {
+        //(1) Read the config, or write the defaults if there is none
+        let path = self.data_folder().join(CONFIG_FILE);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                let defaults = T::default();
+                let contents = toml::to_string_pretty(&defaults)
+                    .map_err(|err| format!("could not serialize default config: \"{err}\""))?;
+                fs::write(&path, contents)
+                    .map_err(|err| format!("could not write default config to {}: \"{err}\"", path.display()))?;
+                return Ok(defaults);
+            },
+            Err(err) => return Err(format!("could not read config {}: \"{err}\"", path.display()).into())
+        };
+
+        //(R) A broken config is the operator's to fix, we don't overwrite it
+        toml::from_str(&contents)
+            .map_err(|err| format!("invalid config {}: \"{err}\"", path.display()).into())
+    }
+}
+
+/// The server's side of a [`PluginContext`], for one plugin
+pub(crate) struct Host<'a> {
+    events: &'a EventBus,
+    commands: &'a CommandMap,
+    scheduler: &'a Arc<TaskScheduler>,
+    players: &'a Arc<PlayerList>,
+    owner: &'a str,
+    data_folder: &'a str
+}
+
+impl<'a> Host<'a> {
+    pub(crate) fn new(
+        events: &'a EventBus,
+        commands: &'a CommandMap,
+        scheduler: &'a Arc<TaskScheduler>,
+        players: &'a Arc<PlayerList>,
+        owner: &'a str,
+        data_folder: &'a str
+    ) -> Self {
+        Host {events, commands, scheduler, players, owner, data_folder}
+    }
+
+    /// Only valid while the host is
+    pub(crate) fn api(&self) -> HostApi {
+        HostApi {
+            host: self as *const Host as *mut c_void,
+            name: host_name,
+            data_folder: host_data_folder,
+            log: logger::host_log,
+            listen: host_listen,
+            command: host_command,
+            scheduler: host_scheduler,
+            players: host_players
+        }
+    }
+}
+
+unsafe extern "C" fn host_name(host: *mut c_void) -> FfiStr<'static> {
+    let host = &*(host as *const Host<'static>);
+    FfiStr::new(host.owner)
+}
+
+unsafe extern "C" fn host_data_folder(host: *mut c_void) -> FfiStr<'static> {
+    let host = &*(host as *const Host<'static>);
+    FfiStr::new(host.data_folder)
+}
+
+unsafe extern "C" fn host_listen(host: *mut c_void, kind: EventKind, priority: Priority, listener: RawListener) {
+    let host = &*(host as *const Host);
+    host.events.register(host.owner, kind, priority, listener);
+}
+
+unsafe extern "C" fn host_command(
+    host: *mut c_void,
+    name: FfiStr,
+    description: FfiStr,
+    permission: FfiStr,
+    handler: RawCommand
+) {
+    let host = &*(host as *const Host);
+    let permission = Some(permission.as_str()).filter(|node| !node.is_empty());
+    host.commands.register(host.owner, &name, &description, permission, handler);
+}
+
+unsafe extern "C" fn host_scheduler(host: *mut c_void) -> SchedulerApi {
+    let host = &*(host as *const Host);
+    TaskOwner::api(host.scheduler.clone(), host.owner)
+}
+
+unsafe extern "C" fn host_players(host: *mut c_void) -> PlayersApi {
+    let host = &*(host as *const Host);
+    PlayerList::api(host.players.clone())
+}
+
+#[cfg(test)]
+mod context_test {
+    use std::env;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Settings {
+        greeting: String,
+        delay: u64
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Settings {greeting: "Hello".to_string(), delay: 20}
+        }
+    }
+
+    #[test]
+    fn config() {
+        let folder = env::temp_dir().join("srvr-context-test");
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        let (events, commands, scheduler) = (EventBus::new(), CommandMap::new(), Arc::new(TaskScheduler::new()));
+        let players = Arc::new(PlayerList::new());
+        let data_folder = folder.to_string_lossy();
+        let host = Host::new(&events, &commands, &scheduler, &players, "context-test", &data_folder);
+        let api = host.api();
+        let ctx = PluginContext::new(&api);
+        assert_eq!(ctx.data_folder(), folder);
+
+        //(1) The first time, the defaults are written
+        assert_eq!(ctx.config::<Settings>().unwrap(), Settings::default());
+        assert!(folder.join(CONFIG_FILE).exists());
+
+        //(2) After that the operator's changes are read
+        fs::write(folder.join(CONFIG_FILE), "greeting = \"Hi\"\ndelay = 5\n").unwrap();
+        assert_eq!(ctx.config::<Settings>().unwrap(), Settings {greeting: "Hi".to_string(), delay: 5});
+
+        //(3) And mistakes are reported, not overwritten
+        fs::write(folder.join(CONFIG_FILE), "delay = \"soon\"\n").unwrap();
+        assert!(ctx.config::<Settings>().is_err());
+        assert_eq!(fs::read_to_string(folder.join(CONFIG_FILE)).unwrap(), "delay = \"soon\"\n");
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+//! Events the server dispatches to plugins.
+//!
+//! Everything here crosses the plugin ABI, so events only borrow `#[repr(C)]`
+//! data from the server and listeners are passed around as [`RawListener`]s.
+
+use std::{
+    ffi::c_void,
+    fmt::{self, Debug, Formatter},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError
+    }
+};
+
+use log::error;
+
+use crate::{ffi::FfiStr, handles::{Handle, Handles}};
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    PlayerJoin,
+    PlayerQuit,
+    Chat,
+    Command,
+    BlockPlace,
+    BlockBreak,
+    PlayerMove,
+    WorldLoad,
+    ServerTick
+}
+
+/// Listeners run from lowest to highest priority, so the highest priority has
+/// the final say. Monitor listeners run last and can't change the outcome.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Lowest,
+    Low,
+    Normal,
+    High,
+    Highest,
+    Monitor
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Player<'a> {
+    pub name: FfiStr<'a>,
+    pub uuid: u128
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockPosition {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32
+}
+
+#[repr(C, u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    /// Cancelling refuses the player
+    PlayerJoin {player: Player<'a>},
+    PlayerQuit {player: Player<'a>},
+    /// Cancelling keeps the message from the other players
+    Chat {player: Player<'a>, message: FfiStr<'a>},
+    /// The line without its slash. Cancelling means the command was handled,
+    /// or shouldn't run at all
+    Command {sender: FfiStr<'a>, line: FfiStr<'a>},
+    /// The block that was clicked, and the face (0-5: bottom, top, north,
+    /// south, west, east) the new block was placed against
+    BlockPlace {player: Player<'a>, position: BlockPosition, face: i32},
+    BlockBreak {player: Player<'a>, position: BlockPosition},
+    /// Cancelling puts the player back where it came from
+    PlayerMove {player: Player<'a>, from: Position, to: Position},
+    WorldLoad {world: FfiStr<'a>},
+    /// Twenty times per second
+    ServerTick {tick: u64}
+}
+
+impl Event<'_> {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::PlayerJoin{..} => EventKind::PlayerJoin,
+            Event::PlayerQuit{..} => EventKind::PlayerQuit,
+            Event::Chat{..} => EventKind::Chat,
+            Event::Command{..} => EventKind::Command,
+            Event::BlockPlace{..} => EventKind::BlockPlace,
+            Event::BlockBreak{..} => EventKind::BlockBreak,
+            Event::PlayerMove{..} => EventKind::PlayerMove,
+            Event::WorldLoad{..} => EventKind::WorldLoad,
+            Event::ServerTick{..} => EventKind::ServerTick
+        }
+    }
+
+    /// Some events have already happened by the time they are dispatched
+    pub fn is_cancellable(&self) -> bool {
+        !matches!(self, Event::PlayerQuit{..} | Event::WorldLoad{..} | Event::ServerTick{..})
+    }
+}
+
+/// An event on its way past the listeners
+#[repr(C)]
+pub struct Dispatch<'a> {
+    event: Event<'a>,
+    cancelled: bool
+}
+
+impl<'a> Dispatch<'a> {
+    pub fn event(&self) -> &Event<'a> {&self.event}
+    pub fn is_cancelled(&self) -> bool {self.cancelled}
+
+    /// Does nothing for events that can't be cancelled
+    pub fn set_cancelled(&mut self, cancelled: bool) {
+        self.cancelled = cancelled && self.event.is_cancellable();
+    }
+    pub fn cancel(&mut self) {self.set_cancelled(true)}
+}
+
+/// A listener closure behind a C function table, so the plugin that created
+/// it is also the one that calls and drops it
+#[repr(C)]
+pub struct RawListener {
+    data: *mut c_void,
+    call: unsafe extern "C" fn(*mut c_void, *mut Dispatch) -> bool,
+    drop: unsafe extern "C" fn(*mut c_void)
+}
+
+//The closures are required to be Send
+unsafe impl Send for RawListener {}
+
+impl RawListener {
+    pub fn new<F>(listener: F) -> Self
+        where F: FnMut(&mut Dispatch) + Send + 'static
+    {
+        RawListener {
+            data: Box::into_raw(Box::new(listener)) as *mut c_void,
+            call: call_shim::<F>,
+            drop: drop_shim::<F>
+        }
+    }
+
+    /// False if the listener panicked
+    fn call(&mut self, dispatch: &mut Dispatch) -> bool {
+        unsafe { (self.call)(self.data, dispatch) }
+    }
+}
+
+unsafe extern "C" fn call_shim<F: FnMut(&mut Dispatch)>(data: *mut c_void, dispatch: *mut Dispatch) -> bool {
+    let listener = &mut *(data as *mut F);
+    panic::catch_unwind(AssertUnwindSafe(|| listener(&mut *dispatch))).is_ok()
+}
+
+unsafe extern "C" fn drop_shim<F>(data: *mut c_void) {
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(data as *mut F))));
+}
+
+impl Drop for RawListener {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.data) }
+    }
+}
+
+struct Registration {
+    owner: String,
+    kind: EventKind,
+    priority: Priority,
+    /// Set once unregistered, for dispatches that already took it
+    removed: AtomicBool,
+    listener: Mutex<RawListener>,
+    _handle: Handle
+}
+
+/// Every listener, shared between the manager and the clients
+#[derive(Default)]
+pub struct EventBus {
+    /// Sorted by priority, and by registration within a priority
+    listeners: Mutex<Vec<Arc<Registration>>>,
+    /// Listeners, including the ones a dispatch is still calling
+    handles: Handles
+}
+
+impl EventBus {
+
+    pub fn new() -> Self {Self::default()}
+
+    fn listeners(&self) -> MutexGuard<'_, Vec<Arc<Registration>>> {
+        //Listeners can't panic while we hold the lock, so the list is never broken
+        self.listeners.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn register(&self, owner: &str, kind: EventKind, priority: Priority, listener: RawListener) {
+        let mut listeners = self.listeners();
+        let index = listeners.partition_point(|registered| registered.priority <= priority);
+        listeners.insert(index, Arc::new(Registration {
+            owner: owner.to_string(),
+            kind,
+            priority,
+            removed: AtomicBool::new(false),
+            listener: Mutex::new(listener),
+            _handle: self.handles.handle(owner)
+        }));
+    }
+
+    /// Drops every listener of a plugin, which must happen before its library
+    /// is unloaded
+    pub fn unregister(&self, owner: &str) {
+        //Dropping a listener runs plugin code, so that waits for the lock to go
+        let mut removed = Vec::new();
+        self.listeners().retain(|registered| {
+            if registered.owner != owner {
+                return true;
+            }
+            registered.removed.store(true, Ordering::Release);
+            removed.push(registered.clone());
+            false
+        });
+        drop(removed);
+    }
+
+    /// Returns whether the event was cancelled
+    pub fn dispatch(&self, event: Event) -> bool {
+        /*(Note to future self)
+          Clients dispatch from their own tasks, so the listeners run without
+          the bus locked: a slow listener only holds up its own event. Every
+          listener has a lock of its own, a closure can only run once at a time.
+        */
+        //(1) Take the listeners for this kind of event
+        let kind = event.kind();
+        let listeners: Vec<Arc<Registration>> = self.listeners().iter()
+            .filter(|registered| registered.kind == kind)
+            .cloned()
+            .collect();
+
+        //(2) Call them in order, skipping the ones removed in the meantime
+        let mut dispatch = Dispatch {event, cancelled: false};
+        let mut panicked = Vec::new();
+        for registered in listeners {
+            let mut listener = registered.listener.lock().unwrap_or_else(PoisonError::into_inner);
+            if registered.removed.load(Ordering::Acquire) {
+                continue;
+            }
+            let cancelled = dispatch.cancelled;
+            let ok = listener.call(&mut dispatch);
+            if registered.priority == Priority::Monitor {
+                dispatch.cancelled = cancelled;
+            }
+            if !ok {
+                error!("A {kind:?} listener of plugin \"{}\" panicked and has been removed", registered.owner);
+                registered.removed.store(true, Ordering::Release);
+                drop(listener);
+                panicked.push(registered);
+            }
+        }
+
+        //(R) Remove the ones that panicked, and drop them without the lock
+        if !panicked.is_empty() {
+            self.listeners().retain(|registered| !panicked.iter().any(|broken| Arc::ptr_eq(broken, registered)));
+        }
+        drop(panicked);
+        dispatch.cancelled
+    }
+
+    /// How many listeners of this plugin are still registered, or being
+    /// called
+    pub fn owned_by(&self, owner: &str) -> usize {
+        self.handles.count(owner)
+    }
+
+    pub fn len(&self) -> usize {self.listeners().len()}
+    pub fn is_empty(&self) -> bool {self.len() == 0}
+
+}
+
+impl Debug for EventBus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus").field("listeners", &self.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod events_test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    fn chat(message: &str) -> Event<'_> {
+        Event::Chat {
+            player: Player {name: FfiStr::new("jeb_"), uuid: 1},
+            message: FfiStr::new(message)
+        }
+    }
+
+    #[test]
+    fn priorities_and_cancelling() {
+        let bus = EventBus::new();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+
+        //Registered out of order, and the monitor tries to undo the cancel
+        let log = |calls: &Arc<Mutex<Vec<&'static str>>>, name: &'static str, cancel: Option<bool>| {
+            let calls = calls.clone();
+            RawListener::new(move |dispatch: &mut Dispatch| {
+                calls.lock().unwrap().push(name);
+                if let Some(cancel) = cancel {
+                    dispatch.set_cancelled(cancel);
+                }
+            })
+        };
+        bus.register("a", EventKind::Chat, Priority::Monitor, log(&calls, "monitor", Some(false)));
+        bus.register("a", EventKind::Chat, Priority::High, log(&calls, "high", Some(true)));
+        bus.register("b", EventKind::Chat, Priority::Low, log(&calls, "low", None));
+        bus.register("b", EventKind::ServerTick, Priority::Lowest, log(&calls, "tick", Some(true)));
+
+        assert!(bus.dispatch(chat("hi")));
+        assert_eq!(*calls.lock().unwrap(), vec!["low", "high", "monitor"]);
+
+        //Ticks can't be cancelled
+        assert!(!bus.dispatch(Event::ServerTick{tick: 1}));
+
+        //Gone with its owner
+        bus.unregister("a");
+        assert!(!bus.dispatch(chat("hi")));
+        assert_eq!(bus.len(), 2);
+    }
+
+    #[test]
+    fn panicking_listeners_are_removed() {
+        let bus = EventBus::new();
+        bus.register("broken", EventKind::Chat, Priority::Normal, RawListener::new(|dispatch: &mut Dispatch| {
+            if let Event::Chat{message, ..} = dispatch.event() {
+                panic!("can't handle \"{message}\"");
+            }
+        }));
+        bus.register("fine", EventKind::Chat, Priority::High, RawListener::new(|dispatch: &mut Dispatch| {
+            dispatch.cancel();
+        }));
+
+        assert!(bus.dispatch(chat("boom")));
+        assert_eq!(bus.len(), 1);
+    }
+
+    #[test]
+    fn listeners_run_without_the_lock() {
+        //A listener that takes the bus itself would deadlock if it was locked
+        let bus = Arc::new(EventBus::new());
+        let inner = bus.clone();
+        bus.register("a", EventKind::Chat, Priority::Normal, RawListener::new(move |_: &mut Dispatch| {
+            inner.register("b", EventKind::ServerTick, Priority::Normal, RawListener::new(|_: &mut Dispatch| {}));
+            assert_eq!(inner.owned_by("a"), 1);
+        }));
+        assert!(!bus.dispatch(chat("hi")));
+        assert_eq!(bus.len(), 2);
+
+        //Unregistered listeners a dispatch already took are skipped
+        let inner = bus.clone();
+        bus.register("c", EventKind::Chat, Priority::Lowest, RawListener::new(move |_: &mut Dispatch| inner.unregister("d")));
+        bus.register("d", EventKind::Chat, Priority::High, RawListener::new(|dispatch: &mut Dispatch| dispatch.cancel()));
+        assert!(!bus.dispatch(chat("hi")));
+        assert_eq!(bus.owned_by("d"), 0);
+    }
+
+}
@@ -0,0 +1,458 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+//! The stable boundary between the server and plugin libraries.
+//!
+//! Rust has no stable ABI, so a plugin built with a different compiler, or a
+//! different version of this crate, can't safely hand us a `Box<dyn Plugin>`.
+//! Instead every library exports two `extern "C"` functions (see
+//! [`declare_plugin!`](crate::declare_plugin)): one returning the ABI version it
+//! was built against, and one creating the plugin behind a [`PluginDescriptor`].
+//! Only `#[repr(C)]` types cross the boundary, and panics never do.
+
+use std::{
+    ffi::c_void,
+    fmt::{self, Debug, Display, Formatter},
+    marker::PhantomData,
+    ops::Deref,
+    panic::{self, AssertUnwindSafe},
+    slice,
+    str
+};
+
+use crate::{
+    commands::RawCommand,
+    events::{EventKind, Priority, RawListener},
+    players::PlayersApi,
+    scheduler::SchedulerApi,
+    Dependency, Plugin, PluginContext, PluginVersion
+};
+
+/// Bumped whenever anything in this module changes layout or meaning
+pub const PLUGIN_ABI_VERSION: u32 = 6;
+
+/// `extern "C" fn() -> u32`, returning the library's [`PLUGIN_ABI_VERSION`]
+pub const ABI_VERSION_SYMBOL: &[u8] = b"srvr_plugin_abi_version\0";
+/// `extern "C" fn() -> PluginDescriptor`, only called once the ABI matches
+pub const CREATE_SYMBOL: &[u8] = b"srvr_plugin_create\0";
+
+/// A `&str` that can cross the boundary
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FfiStr<'a> {
+    ptr: *const u8,
+    len: usize,
+    _str: PhantomData<&'a str>
+}
+
+//It is just a &str
+unsafe impl Send for FfiStr<'_> {}
+unsafe impl Sync for FfiStr<'_> {}
+
+/// The functions the server calls on a plugin instance. None of them unwind,
+/// `start` and `stop` return false if the plugin panicked.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginVTable {
+    pub name: unsafe extern "C" fn(*const c_void) -> FfiStr<'static>,
+    pub version: unsafe extern "C" fn(*const c_void) -> PluginVersion,
+    pub dependency_count: unsafe extern "C" fn(*const c_void) -> usize,
+    pub dependency: unsafe extern "C" fn(*const c_void, usize) -> Dependency<'static>,
+    pub min_server_version: unsafe extern "C" fn(*const c_void) -> PluginVersion,
+    pub start: unsafe extern "C" fn(*mut c_void, *const HostApi) -> bool,
+    pub stop: unsafe extern "C" fn(*mut c_void) -> bool,
+    pub destroy: unsafe extern "C" fn(*mut c_void)
+}
+
+/// What a plugin library hands the server. `instance` is null if creating
+/// the plugin panicked.
+#[repr(C)]
+pub struct PluginDescriptor {
+    pub abi_version: u32,
+    pub instance: *mut c_void,
+    pub vtable: PluginVTable
+}
+
+/// What the server offers a plugin while it starts, see [`PluginContext`]
+#[repr(C)]
+pub struct HostApi {
+    pub host: *mut c_void,
+    /// The plugin's name and data folder, only valid while it starts
+    pub name: unsafe extern "C" fn(*mut c_void) -> FfiStr<'static>,
+    pub data_folder: unsafe extern "C" fn(*mut c_void) -> FfiStr<'static>,
+    /// Plugin name, `log::Level` and message. Valid for as long as the server
+    /// runs, so it needs no host.
+    pub log: unsafe extern "C" fn(FfiStr, usize, FfiStr),
+    pub listen: unsafe extern "C" fn(*mut c_void, EventKind, Priority, RawListener),
+    /// Name, description and permission node (empty for none)
+    pub command: unsafe extern "C" fn(*mut c_void, FfiStr, FfiStr, FfiStr, RawCommand),
+    pub scheduler: unsafe extern "C" fn(*mut c_void) -> SchedulerApi,
+    pub players: unsafe extern "C" fn(*mut c_void) -> PlayersApi
+}
+
+impl<'a> FfiStr<'a> {
+    pub fn new(s: &'a str) -> Self {
+        FfiStr {ptr: s.as_ptr(), len: s.len(), _str: PhantomData}
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        //Only ever made from a &'a str
+        unsafe { str::from_utf8_unchecked(slice::from_raw_parts(self.ptr, self.len)) }
+    }
+}
+
+impl Deref for FfiStr<'_> {
+    type Target = str;
+    fn deref(&self) -> &str {self.as_str()}
+}
+
+impl Debug for FfiStr<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Display for FfiStr<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(self.as_str(), f)
+    }
+}
+
+impl PluginDescriptor {
+    /// Used by [`declare_plugin!`](crate::declare_plugin), on the plugin's side
+    pub fn new<P: Plugin + 'static>(constructor: impl FnOnce() -> P) -> Self {
+        let instance = match panic::catch_unwind(AssertUnwindSafe(|| Instance::new(constructor()))) {
+            Ok(instance) => Box::into_raw(Box::new(instance)) as *mut c_void,
+            Err(_) => std::ptr::null_mut()
+        };
+        PluginDescriptor {
+            abi_version: PLUGIN_ABI_VERSION,
+            instance,
+            vtable: PluginVTable {
+                name: name_shim::<P>,
+                version: version_shim::<P>,
+                dependency_count: dependency_count_shim::<P>,
+                dependency: dependency_shim::<P>,
+                min_server_version: min_server_version_shim::<P>,
+                start: start_shim::<P>,
+                stop: stop_shim::<P>,
+                destroy: destroy_shim::<P>
+            }
+        }
+    }
+}
+
+//The plugin, with what it said about itself when it was created. Asking it
+//again would run plugin code for every getter, which could panic or answer
+//differently each time.
+struct Instance<P> {
+    plugin: P,
+    name: String,
+    version: PluginVersion,
+    /// Name, versions and whether it is optional
+    dependencies: Vec<(String, String, bool)>,
+    min_server_version: PluginVersion
+}
+
+impl<P: Plugin> Instance<P> {
+    fn new(plugin: P) -> Self {
+        let dependencies = plugin.dependencies().iter()
+            .map(|dependency| (dependency.name().to_string(), dependency.versions().to_string(), dependency.is_optional()))
+            .collect();
+        Instance {
+            name: plugin.name().to_string(),
+            version: *plugin.version(),
+            dependencies,
+            min_server_version: plugin.min_server_version(),
+            plugin
+        }
+    }
+}
+
+/*(Note to future self)
+  These shims are instantiated inside the plugin library, so they run on the
+  plugin's copy of std. Getters only read what Instance::new collected, under
+  catch_unwind, start and stop run arbitrary plugin code.
+*/
+unsafe extern "C" fn name_shim<P: Plugin + 'static>(instance: *const c_void) -> FfiStr<'static> {
+    let instance = &*(instance as *const Instance<P>);
+    FfiStr::new(&instance.name)
+}
+
+unsafe extern "C" fn version_shim<P: Plugin + 'static>(instance: *const c_void) -> PluginVersion {
+    (*(instance as *const Instance<P>)).version
+}
+
+unsafe extern "C" fn dependency_count_shim<P: Plugin + 'static>(instance: *const c_void) -> usize {
+    let instance = &*(instance as *const Instance<P>);
+    instance.dependencies.len()
+}
+
+//The server only asks for indices below the count, anything else gets a
+//dependency that can't be met rather than a panic
+unsafe extern "C" fn dependency_shim<P: Plugin + 'static>(instance: *const c_void, index: usize) -> Dependency<'static> {
+    let instance = &*(instance as *const Instance<P>);
+    match instance.dependencies.get(index) {
+        Some((name, versions, true)) => Dependency::optional(name, versions),
+        Some((name, versions, false)) => Dependency::required(name, versions),
+        None => Dependency::required("", "")
+    }
+}
+
+unsafe extern "C" fn min_server_version_shim<P: Plugin + 'static>(instance: *const c_void) -> PluginVersion {
+    (*(instance as *const Instance<P>)).min_server_version
+}
+
+unsafe extern "C" fn start_shim<P: Plugin + 'static>(instance: *mut c_void, api: *const HostApi) -> bool {
+    let plugin = &mut (*(instance as *mut Instance<P>)).plugin;
+    let mut ctx = PluginContext::new(&*api);
+    panic::catch_unwind(AssertUnwindSafe(|| plugin.start(&mut ctx))).is_ok()
+}
+
+unsafe extern "C" fn stop_shim<P: Plugin + 'static>(instance: *mut c_void) -> bool {
+    let plugin = &mut (*(instance as *mut Instance<P>)).plugin;
+    panic::catch_unwind(AssertUnwindSafe(|| plugin.stop())).is_ok()
+}
+
+unsafe extern "C" fn destroy_shim<P: Plugin + 'static>(instance: *mut c_void) {
+    //Dropping is plugin code too, and must not unwind into the server
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(instance as *mut Instance<P>))));
+}
+
+/// The server's side of a [`PluginDescriptor`], usable like any other plugin
+pub(crate) struct FfiPlugin {
+    instance: *mut c_void,
+    vtable: PluginVTable,
+    name: String,
+    version: PluginVersion,
+    /// Name, versions and whether it is optional
+    dependencies: Vec<(String, String, bool)>,
+    min_server_version: PluginVersion
+}
+
+impl FfiPlugin {
+    /// Safety: the descriptor must come from [`PluginDescriptor::new`], and the
+    /// code it points to must stay loaded for as long as the `FfiPlugin` lives
+    pub(crate) unsafe fn from_descriptor(descriptor: PluginDescriptor) -> Result<Self, String> {
+        //(1) The version is checked before loading, but a library could lie
+        if descriptor.abi_version != PLUGIN_ABI_VERSION {
+            return Err(abi_mismatch(descriptor.abi_version));
+        }
+        if descriptor.instance.is_null() {
+            return Err("plugin panicked while being created or describing itself".to_string());
+        }
+
+        //(2) Copy the metadata, so we don't have to cross the boundary for it again
+        let PluginDescriptor {instance, vtable, ..} = descriptor;
+        let name = (vtable.name)(instance).as_str().to_string();
+        let version = (vtable.version)(instance);
+        let dependencies = (0..(vtable.dependency_count)(instance))
+            .map(|index| {
+                let dependency = (vtable.dependency)(instance, index);
+                (dependency.name().to_string(), dependency.versions().to_string(), dependency.is_optional())
+            })
+            .collect();
+        let min_server_version = (vtable.min_server_version)(instance);
+
+        //(R) The plugin, which destroys its instance when dropped
+        Ok(FfiPlugin {instance, vtable, name, version, dependencies, min_server_version})
+    }
+}
+
+pub(crate) fn abi_mismatch(found: u32) -> String {
+    format!("plugin was built for plugin ABI v{found}, but this server uses v{PLUGIN_ABI_VERSION}. \
+        Rebuild the plugin against the srvr-sysplugin version of this server")
+}
+
+impl Plugin for FfiPlugin {
+    fn name(&self) -> &str {&self.name}
+    fn version(&self) -> &PluginVersion {&self.version}
+    fn dependencies(&self) -> Vec<Dependency<'_>> {
+        self.dependencies.iter()
+            .map(|(name, versions, optional)| match optional {
+                true => Dependency::optional(name, versions),
+                false => Dependency::required(name, versions)
+            })
+            .collect()
+    }
+    fn min_server_version(&self) -> PluginVersion {self.min_server_version}
+
+    //The plugin already caught its panic, re-raise it on our side so the
+    //manager disables the plugin like any other
+    fn start(&mut self, ctx: &mut PluginContext) {
+        if !unsafe { (self.vtable.start)(self.instance, ctx.api()) } {
+            panic!("plugin \"{}\" panicked while starting", self.name);
+        }
+    }
+    fn stop(&mut self) {
+        if !unsafe { (self.vtable.stop)(self.instance) } {
+            panic!("plugin \"{}\" panicked while stopping", self.name);
+        }
+    }
+}
+
+impl Drop for FfiPlugin {
+    fn drop(&mut self) {
+        unsafe { (self.vtable.destroy)(self.instance) }
+    }
+}
+
+/// Exports a plugin from a library, through the stable plugin ABI.
+/// The constructor is called once, when the server loads the library.
+/// ```ignore
+/// declare_plugin!(MyPlugin, MyPlugin::new);
+/// ```
+#[macro_export]
+macro_rules! declare_plugin {
+    ($plugin:ty, $constructor:path) => {
+        #[no_mangle]
+        pub extern "C" fn srvr_plugin_abi_version() -> u32 {
+            $crate::ffi::PLUGIN_ABI_VERSION
+        }
+
+        #[no_mangle]
+        pub extern "C" fn srvr_plugin_create() -> $crate::ffi::PluginDescriptor {
+            $crate::ffi::PluginDescriptor::new::<$plugin>($constructor)
+        }
+    };
+}
+
+#[cfg(test)]
+mod ffi_test {
+    use std::sync::Arc;
+
+    use crate::{
+        context::Host,
+        events::Player,
+        CommandMap, CommandSender, Event, EventBus, PlayerList, TaskScheduler
+    };
+
+    use super::*;
+
+    const VERSION: PluginVersion = PluginVersion::new((2, 1, 0));
+
+    struct TestPlugin {
+        panics: bool
+    }
+
+    impl Plugin for TestPlugin {
+        fn name(&self) -> &str {"ffi-test"}
+        fn version(&self) -> &PluginVersion {&VERSION}
+        fn dependencies(&self) -> Vec<Dependency<'_>> {
+            vec![Dependency::required("database", "^1.2"), Dependency::optional("economy", "*")]
+        }
+        fn min_server_version(&self) -> PluginVersion {PluginVersion::new((0, 0, 1))}
+
+        fn start(&mut self, ctx: &mut PluginContext) {
+            if self.panics {
+                panic!("does not want to start");
+            }
+            //Nobody may say "creeper"
+            ctx.listen(EventKind::Chat, Priority::Normal, |dispatch| {
+                if let Event::Chat{message, ..} = dispatch.event() {
+                    if message.contains("creeper") {
+                        dispatch.cancel();
+                    }
+                }
+            });
+            ctx.command("ping", "Answers", None, |call| call.reply("pong"));
+            ctx.scheduler().run_repeating(1, 20, || {});
+        }
+        fn stop(&mut self) {}
+    }
+
+    struct BrokenDependencies;
+
+    impl Plugin for BrokenDependencies {
+        fn name(&self) -> &str {"broken"}
+        fn version(&self) -> &PluginVersion {&VERSION}
+        fn dependencies(&self) -> Vec<Dependency<'_>> {
+            panic!("no idea")
+        }
+        fn start(&mut self, _: &mut PluginContext) {}
+        fn stop(&mut self) {}
+    }
+
+    fn chat(message: &str) -> Event<'_> {
+        Event::Chat {player: Player {name: FfiStr::new("jeb_"), uuid: 1}, message: FfiStr::new(message)}
+    }
+
+    #[test]
+    fn round_trip() {
+        let descriptor = PluginDescriptor::new(|| TestPlugin {panics: false});
+        let mut plugin = unsafe { FfiPlugin::from_descriptor(descriptor) }.unwrap();
+        assert_eq!(plugin.name(), "ffi-test");
+        assert_eq!(plugin.version(), &VERSION);
+        assert_eq!(format!("{:?}", plugin.dependencies()), "[database ^1.2, economy * (optional)]");
+        assert_eq!(plugin.min_server_version(), PluginVersion::new((0, 0, 1)));
+
+        //Everything it registers crosses the boundary twice: to the server, and
+        //back to be called
+        let (events, commands, scheduler) = (EventBus::new(), CommandMap::new(), Arc::new(TaskScheduler::new()));
+        let players = Arc::new(PlayerList::new());
+        scheduler.activate("ffi-test");
+        let host = Host::new(&events, &commands, &scheduler, &players, "ffi-test", "plugins/ffi-test");
+        let api = host.api();
+        plugin.start(&mut PluginContext::new(&api));
+        assert!(events.dispatch(chat("aw man, a creeper")));
+        assert!(!events.dispatch(chat("hello")));
+        assert_eq!(commands.execute(CommandSender::Console, "ping", |_| true), Ok(vec!["pong".to_string()]));
+        assert_eq!(scheduler.len(), 1);
+
+        plugin.stop();
+        events.unregister("ffi-test");
+        commands.unregister("ffi-test");
+        scheduler.deactivate("ffi-test");
+    }
+
+    #[test]
+    fn rejected_descriptors() {
+        //A library built against another version of this module
+        let mut descriptor = PluginDescriptor::new(|| TestPlugin {panics: false});
+        descriptor.abi_version = PLUGIN_ABI_VERSION + 1;
+        let err = unsafe { FfiPlugin::from_descriptor(descriptor) }.err().unwrap();
+        assert!(err.contains(&format!("ABI v{}", PLUGIN_ABI_VERSION + 1)));
+
+        //A constructor that panicked
+        let descriptor = PluginDescriptor::new::<TestPlugin>(|| panic!("no"));
+        assert!(unsafe { FfiPlugin::from_descriptor(descriptor) }.is_err());
+
+        //Or a getter, which is plugin code too
+        let descriptor = PluginDescriptor::new(|| BrokenDependencies);
+        assert!(unsafe { FfiPlugin::from_descriptor(descriptor) }.is_err());
+    }
+
+    #[test]
+    fn panics_stay_in_the_plugin() {
+        let descriptor = PluginDescriptor::new(|| TestPlugin {panics: true});
+        let mut plugin = unsafe { FfiPlugin::from_descriptor(descriptor) }.unwrap();
+        let (events, commands, scheduler) = (EventBus::new(), CommandMap::new(), Arc::new(TaskScheduler::new()));
+        let players = Arc::new(PlayerList::new());
+        let host = Host::new(&events, &commands, &scheduler, &players, "ffi-test", "plugins/ffi-test");
+        let api = host.api();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| plugin.start(&mut PluginContext::new(&api))));
+        assert!(result.is_err());
+    }
+
+}
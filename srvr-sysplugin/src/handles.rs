@@ -0,0 +1,88 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+//! Keeping count of what the server holds of each plugin.
+//!
+//! Unregistering drops what a plugin left in the registries, but not what is
+//! somewhere else at that moment: a task in the middle of running, or a
+//! scheduler the plugin kept. Each of those holds a token of its plugin, and
+//! the library can't be unloaded while any are out.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, Weak}
+};
+
+/// Held by anything that can still call into a plugin, dropped along with it
+pub(crate) type Handle = Arc<()>;
+
+#[derive(Default)]
+pub(crate) struct Handles {
+    owners: Mutex<HashMap<String, Weak<()>>>
+}
+
+impl Handles {
+
+    fn owners(&self) -> MutexGuard<'_, HashMap<String, Weak<()>>> {
+        self.owners.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn handle(&self, owner: &str) -> Handle {
+        let mut owners = self.owners();
+        match owners.get(owner).and_then(Weak::upgrade) {
+            Some(handle) => handle,
+            None => {
+                let handle = Arc::new(());
+                owners.insert(owner.to_string(), Arc::downgrade(&handle));
+                handle
+            }
+        }
+    }
+
+    /// How many handles of this plugin are still out
+    pub(crate) fn count(&self, owner: &str) -> usize {
+        self.owners().get(owner).map_or(0, Weak::strong_count)
+    }
+
+}
+
+#[cfg(test)]
+mod handles_test {
+    use super::*;
+
+    #[test]
+    fn counting() {
+        let handles = Handles::default();
+        let first = handles.handle("a");
+        let second = handles.handle("a");
+        let other = handles.handle("b");
+        assert_eq!((handles.count("a"), handles.count("b"), handles.count("c")), (2, 1, 0));
+        drop((first, second, other));
+        assert_eq!((handles.count("a"), handles.count("b")), (0, 0));
+
+        //A plugin that comes back starts over
+        let _again = handles.handle("a");
+        assert_eq!(handles.count("a"), 1);
+    }
+}
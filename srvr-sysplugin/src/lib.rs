@@ -22,17 +22,54 @@
   text of the license in any official language of the European Union.
 */
 
-use std::fmt::{Display, Formatter};
+use std::fmt::{self, Debug, Display, Formatter};
+
+use semver::Version;
+
+use ffi::FfiStr;
+
+pub mod ffi;
+pub mod events;
+pub mod commands;
+pub mod scheduler;
+pub mod logger;
+pub mod players;
+pub mod wasm;
+mod context;
+mod handles;
+mod manager;
+mod resolver;
+pub use context::{PluginContext, CONFIG_FILE};
+pub use logger::PluginLogger;
+pub use events::{EventBus, Event, EventKind, Priority, Dispatch};
+pub use commands::{CommandMap, CommandCall, CommandSender};
+pub use scheduler::{TaskScheduler, Scheduler, TaskId};
+pub use players::{PlayerList, Players, OnlinePlayer};
+pub use manager::{PluginManager, PluginError, PluginState};
+pub use wasm::{WasmPlugin, WasmLimits};
 
 pub trait Plugin {
     fn name(&self) -> &str;
     fn version(&self) -> &PluginVersion;
 
-    fn start(&mut self);
+    /// Plugins this one needs, or can make use of. They are started first.
+    fn dependencies(&self) -> Vec<Dependency<'_>> {Vec::new()}
+
+    /// The oldest srvr this plugin works with
+    fn min_server_version(&self) -> PluginVersion {PluginVersion::new((0, 0, 0))}
+
+    /// Listeners, commands and tasks added through the context stay until the
+    /// plugin stops
+    fn start(&mut self, ctx: &mut PluginContext);
+
+    /// Drop any [`Scheduler`] the plugin kept, a plugin that still holds one
+    /// can't be reloaded. Threads the plugin started itself have to be
+    /// stopped here too, the server can't see them.
     fn stop(&mut self);
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PluginVersion(usize, usize, usize);
 
 impl PluginVersion {
@@ -45,4 +82,45 @@ impl Display for PluginVersion {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "v{}.{}.{}", self.0, self.1, self.2)
     }
+}
+
+impl From<PluginVersion> for Version {
+    fn from(version: PluginVersion) -> Self {
+        Version::new(version.0 as u64, version.1 as u64, version.2 as u64)
+    }
+}
+
+/// Another plugin, and the versions of it that will do. `versions` is a semver
+/// range, like "^1.2", ">=0.3, <0.5" or "*" for any.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Dependency<'a> {
+    name: FfiStr<'a>,
+    versions: FfiStr<'a>,
+    optional: bool
+}
+
+impl<'a> Dependency<'a> {
+    /// The plugin isn't loaded without it
+    pub fn required(name: &'a str, versions: &'a str) -> Self {
+        Dependency {name: FfiStr::new(name), versions: FfiStr::new(versions), optional: false}
+    }
+
+    /// Started first if it is there, but the plugin loads without it
+    pub fn optional(name: &'a str, versions: &'a str) -> Self {
+        Dependency {name: FfiStr::new(name), versions: FfiStr::new(versions), optional: true}
+    }
+
+    pub fn name(&self) -> &'a str {self.name.as_str()}
+    pub fn versions(&self) -> &'a str {self.versions.as_str()}
+    pub fn is_optional(&self) -> bool {self.optional}
+}
+
+impl Debug for Dependency<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.optional {
+            true => write!(f, "{} {} (optional)", self.name, self.versions),
+            false => write!(f, "{} {}", self.name, self.versions)
+        }
+    }
 }
\ No newline at end of file
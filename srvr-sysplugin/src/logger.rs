@@ -0,0 +1,96 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::fmt::Display;
+
+use log::{Level, Log, Metadata, Record};
+
+use crate::ffi::FfiStr;
+
+/// Everything a plugin logs ends up under this target, followed by its name
+pub const LOG_TARGET: &str = "plugin";
+
+/// Logs through the server's logger, under `plugin::<name>`.
+///
+/// A plugin library has its own copy of the `log` crate, which the server
+/// never configures. Use this directly, or install it with
+/// `log::set_boxed_logger` to make the `log` macros work too.
+#[derive(Debug, Clone)]
+pub struct PluginLogger {
+    name: String,
+    log: unsafe extern "C" fn(FfiStr, usize, FfiStr)
+}
+
+impl PluginLogger {
+    pub(crate) fn new(name: String, log: unsafe extern "C" fn(FfiStr, usize, FfiStr)) -> Self {
+        PluginLogger {name, log}
+    }
+
+    pub fn log(&self, level: Level, message: impl Display) {
+        let message = message.to_string();
+        unsafe { (self.log)(FfiStr::new(&self.name), level as usize, FfiStr::new(&message)) }
+    }
+
+    pub fn error(&self, message: impl Display) {self.log(Level::Error, message)}
+    pub fn warn(&self, message: impl Display) {self.log(Level::Warn, message)}
+    pub fn info(&self, message: impl Display) {self.log(Level::Info, message)}
+    pub fn debug(&self, message: impl Display) {self.log(Level::Debug, message)}
+    pub fn trace(&self, message: impl Display) {self.log(Level::Trace, message)}
+}
+
+impl Log for PluginLogger {
+    //The server filters, it knows the levels
+    fn enabled(&self, _: &Metadata) -> bool {true}
+
+    fn log(&self, record: &Record) {
+        PluginLogger::log(self, record.level(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+/*(Note to future self)
+  log::Level is only a Rust enum, so it crosses as its discriminant. Those
+  have been 1 (error) to 5 (trace) for as long as the crate has existed.
+*/
+pub(crate) unsafe extern "C" fn host_log(plugin: FfiStr, level: usize, message: FfiStr) {
+    let level = match level {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace
+    };
+    if level > log::max_level() {
+        return;
+    }
+    let target = format!("{LOG_TARGET}::{plugin}");
+    log::logger().log(&Record::builder()
+        .level(level)
+        .target(&target)
+        .args(format_args!("{}", message.as_str()))
+        .build()
+    );
+}
+
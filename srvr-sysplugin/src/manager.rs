@@ -0,0 +1,663 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Arc
+};
+
+use libloading::{Library, Symbol};
+use log::{info, warn, error};
+use semver::Version;
+
+use crate::{
+    commands::{CommandError, CommandMap, CommandSender},
+    context::Host,
+    resolver,
+    scheduler::TaskScheduler,
+    players::PlayerList,
+    ffi::{self, FfiPlugin, PluginDescriptor, ABI_VERSION_SYMBOL, CREATE_SYMBOL, PLUGIN_ABI_VERSION},
+    wasm::{WasmLimits, WasmPlugin, WASM_EXTENSION},
+    EventBus, Plugin, PluginContext, PluginVersion
+};
+
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+type CreateFn = unsafe extern "C" fn() -> PluginDescriptor;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluginState {
+    Loaded,
+    Started,
+    Stopped,
+    /// The plugin panicked, we won't call it again
+    Failed
+}
+
+struct LoadedPlugin {
+    /*(Note to future self)
+      Fields are dropped in order, and the plugin's vtable and code live in
+      the library. So the plugin MUST come before the library.
+    */
+    plugin: Box<dyn Plugin>,
+    state: PluginState,
+    /// Where the library came from, so it can be reloaded
+    path: Option<PathBuf>,
+    _library: Option<Library>
+}
+
+pub struct PluginManager {
+    /// Sorted so that every plugin comes after its dependencies
+    plugins: Vec<LoadedPlugin>,
+    /*(Note to future self)
+      These only hold what running plugins registered, which points into
+      their libraries. So everything a plugin registered MUST be released
+      when it stops, before its library can be unloaded.
+    */
+    events: Arc<EventBus>,
+    commands: CommandMap,
+    scheduler: Arc<TaskScheduler>,
+    /// Kept up to date by the server, for plugins to look up
+    players: Arc<PlayerList>,
+    /// Every plugin gets a data folder in here
+    folder: PathBuf,
+    /// Libraries of plugins whose reload failed, to try again once fixed
+    unloaded: BTreeMap<String, PathBuf>,
+    /// Plugins say which srvr versions they work with
+    server: Version,
+    /// For WebAssembly plugins, including the ones loaded by a reload
+    wasm: WasmLimits
+}
+
+impl PluginManager {
+
+    /// Loads every plugin library and WebAssembly module in `folder` that
+    /// works with this `server`. The modules get `wasm` to run on.
+    pub fn load_folder(folder: &Path, server: &Version, wasm: WasmLimits) -> Result<Self, PluginError> {
+        //(1) Make sure the folder exists, there just aren't any plugins yet
+        if !folder.exists() {
+            fs::create_dir_all(folder)
+                .map_err(|err| format!("could not create plugin folder {}: \"{err}\"", folder.display()))?;
+        }
+        let entries = fs::read_dir(folder)
+            .map_err(|err| format!("could not read plugin folder {}: \"{err}\"", folder.display()))?;
+
+        //(2) Load every plugin in the folder, skipping the ones that fail
+        let mut plugins = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_plugin = path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.ends_with(env::consts::DLL_SUFFIX) || name.ends_with(&format!(".{WASM_EXTENSION}")));
+            if !is_plugin {
+                continue;
+            }
+            match Self::load(&path, wasm) {
+                Ok(plugin) => plugins.push(plugin),
+                Err(err) => error!("Could not load plugin {} (reason: \"{err}\")", path.display())
+            }
+        }
+
+        //(R) Order the plugins by their dependencies
+        Ok(Self::sorted(plugins, folder, server, wasm))
+    }
+
+    /// Manage plugins that are already in memory, like the server's own. Their
+    /// data folders go in `folder`.
+    pub fn from_plugins(folder: &Path, server: &Version, plugins: Vec<Box<dyn Plugin>>) -> Self {
+        let plugins = plugins.into_iter()
+            .map(|plugin| LoadedPlugin {plugin, state: PluginState::Loaded, path: None, _library: None})
+            .collect();
+        Self::sorted(plugins, folder, server, WasmLimits::default())
+    }
+
+    //Native libraries and WebAssembly modules, told apart by their extension
+    fn load(path: &Path, wasm: WasmLimits) -> Result<LoadedPlugin, PluginError> {
+        if path.extension().is_some_and(|extension| extension == WASM_EXTENSION) {
+            let plugin = WasmPlugin::load(path, wasm)?;
+            info!("Loaded WebAssembly plugin {} {}", plugin.name(), plugin.version());
+            let plugin: Box<dyn Plugin> = Box::new(plugin);
+            return Ok(LoadedPlugin {plugin, state: PluginState::Loaded, path: Some(path.to_path_buf()), _library: None});
+        }
+        Self::load_library(path)
+    }
+
+    fn load_library(path: &Path) -> Result<LoadedPlugin, PluginError> {
+        //(1) Open the library and check which ABI it speaks before calling
+        //anything else, a mismatched descriptor would be undefined behaviour
+        let library = unsafe { Library::new(path) }
+            .map_err(|err| format!("could not open library: \"{err}\""))?;
+        let abi_version: Symbol<AbiVersionFn> = unsafe { library.get(ABI_VERSION_SYMBOL) }
+            .map_err(|_| "not a srvr plugin, or built for an ABI older than v1 (use declare_plugin!)".to_string())?;
+        let abi_version = unsafe { abi_version() };
+        if abi_version != PLUGIN_ABI_VERSION {
+            return Err(ffi::abi_mismatch(abi_version).into());
+        }
+
+        //(2) Create the plugin, which catches its own panics
+        let create: Symbol<CreateFn> = unsafe { library.get(CREATE_SYMBOL) }
+            .map_err(|err| format!("could not find plugin constructor: \"{err}\""))?;
+        let plugin = unsafe { FfiPlugin::from_descriptor(create()) }?;
+        info!("Loaded plugin {} {}", plugin.name(), plugin.version());
+        let plugin: Box<dyn Plugin> = Box::new(plugin);
+
+        //(R) The plugin, which keeps its library loaded
+        Ok(LoadedPlugin {plugin, state: PluginState::Loaded, path: Some(path.to_path_buf()), _library: Some(library)})
+    }
+
+    fn sorted(mut unsorted: Vec<LoadedPlugin>, folder: &Path, server: &Version, wasm: WasmLimits) -> Self {
+        //(1) Sort by name first so the order doesn't depend on the file system,
+        //and drop plugins that share a name with one we already have
+        unsorted.sort_by(|a, b| a.plugin.name().cmp(b.plugin.name()));
+        unsorted.dedup_by(|duplicate, kept| {
+            let is_duplicate = duplicate.plugin.name() == kept.plugin.name();
+            if is_duplicate {
+                error!("There are two plugins named \"{}\", ignoring one of them", kept.plugin.name());
+            }
+            is_duplicate
+        });
+
+        //(2) Work out which plugins can be loaded, and in which order
+        let resolution = {
+            let plugins: Vec<&dyn Plugin> = unsorted.iter().map(|loaded| &*loaded.plugin).collect();
+            resolver::resolve(&plugins, server)
+        };
+        for (_, warning) in &resolution.warnings {
+            warn!("{warning}");
+        }
+        for (index, reason) in &resolution.refused {
+            error!("Plugin \"{}\" will not be loaded, {reason}", unsorted[*index].plugin.name());
+        }
+
+        //(R) Take the plugins out in that order, unloading the rest
+        let mut unsorted: Vec<Option<LoadedPlugin>> = unsorted.into_iter().map(Some).collect();
+        PluginManager {
+            plugins: resolution.order.iter().filter_map(|&index| unsorted[index].take()).collect(),
+            events: Arc::new(EventBus::new()),
+            commands: CommandMap::new(),
+            scheduler: Arc::new(TaskScheduler::new()),
+            players: Arc::new(PlayerList::new()),
+            folder: folder.to_path_buf(),
+            unloaded: BTreeMap::new(),
+            server: server.clone(),
+            wasm
+        }
+    }
+
+    pub fn start_all(&mut self) {
+        //Dependencies first, so every plugin finds its dependencies running
+        for index in 0..self.plugins.len() {
+            self.start(index);
+        }
+    }
+
+    pub fn stop_all(&mut self) {
+        //Dependents first, so nothing runs without its dependencies
+        for index in (0..self.plugins.len()).rev() {
+            self.stop(index);
+        }
+    }
+
+    fn start(&mut self, index: usize) {
+        //(1) Never start a plugin whose dependencies aren't running
+        let (placed, rest) = self.plugins.split_at_mut(index);
+        let loaded = &mut rest[0];
+        if loaded.state != PluginState::Loaded && loaded.state != PluginState::Stopped {
+            return;
+        }
+        let failed_dependency = loaded.plugin.dependencies().into_iter()
+            .filter(|dependency| !dependency.is_optional())
+            .find(|dependency| {
+                !placed.iter().any(|dep| dep.plugin.name() == dependency.name() && dep.state == PluginState::Started)
+            })
+            .map(|dependency| dependency.name().to_string());
+        if let Some(dependency) = failed_dependency {
+            error!("Not starting plugin \"{}\", its dependency \"{dependency}\" isn't running", loaded.plugin.name());
+            return;
+        }
+
+        //(2) Give it a data folder, and start it
+        info!("Starting plugin {} {}", loaded.plugin.name(), loaded.plugin.version());
+        let name = loaded.plugin.name().to_string();
+        let data_folder = self.folder.join(folder_name(&name));
+        if let Err(err) = fs::create_dir_all(&data_folder) {
+            warn!("Could not create data folder {} for plugin \"{name}\" (reason: \"{err}\")", data_folder.display());
+        }
+        let data_folder = data_folder.to_string_lossy();
+        self.scheduler.activate(&name);
+        let host = Host::new(&self.events, &self.commands, &self.scheduler, &self.players, &name, &data_folder);
+        let api = host.api();
+        loaded.call(PluginState::Started, |plugin| plugin.start(&mut PluginContext::new(&api)));
+
+        //(R) A plugin that failed halfway may have registered some things
+        if loaded.state != PluginState::Started {
+            Self::release(&self.events, &self.commands, &self.scheduler, &name);
+        }
+    }
+
+    fn stop(&mut self, index: usize) {
+        let loaded = &mut self.plugins[index];
+        if loaded.state == PluginState::Started {
+            info!("Stopping plugin {} {}", loaded.plugin.name(), loaded.plugin.version());
+            loaded.call(PluginState::Stopped, |plugin| plugin.stop());
+            Self::release(&self.events, &self.commands, &self.scheduler, loaded.plugin.name());
+        }
+    }
+
+    /// Stops a plugin, unloads its library and starts the library's new build
+    /// in its place
+    /*(Note to future self)
+      Replace the library by renaming a new file over it (cargo does). Writing
+      into the old file changes the code of the running plugin under our feet.
+    */
+    pub fn reload(&mut self, name: &str) -> Result<(), PluginError> {
+        //(1) Find the library, which is only gone if the last reload failed
+        let index = self.plugins.iter().position(|loaded| loaded.plugin.name() == name);
+        let path = match index {
+            Some(index) => self.plugins[index].path.clone()
+                .ok_or_else(|| format!("plugin \"{name}\" is built into the server, it has no library to reload"))?,
+            None => self.unloaded.get(name).cloned()
+                .ok_or_else(|| format!("there is no plugin named \"{name}\""))?
+        };
+
+        if let Some(index) = index {
+            //(2) Plugins that depend on this one may hold on to what it gave
+            //them, and they'd have to stop too
+            let dependents: Vec<&str> = self.plugins.iter()
+                .filter(|loaded| loaded.state == PluginState::Started)
+                .filter(|loaded| loaded.plugin.dependencies().iter().any(|dependency| dependency.name() == name))
+                .map(|loaded| loaded.plugin.name())
+                .collect();
+            if !dependents.is_empty() {
+                return Err(format!("refusing to reload \"{name}\", running plugins depend on it: {}", dependents.join(", ")).into());
+            }
+
+            //(3) Stopping releases everything it registered. What's left is
+            //running right now, or kept by the plugin past stop, and points
+            //into the library so we can't unload it
+            self.stop(index);
+            let leftovers = self.events.owned_by(name) + self.commands.owned_by(name) + self.scheduler.owned_by(name);
+            if leftovers > 0 {
+                self.start(index);
+                return Err(format!("refusing to unload \"{name}\", the server still holds {leftovers} of its listeners, commands, tasks or schedulers").into());
+            }
+
+            //(4) Unload it. The plugin goes first, its code is in the library
+            drop(self.plugins.remove(index));
+            info!("Unloaded plugin \"{name}\"");
+        }
+
+        //(5) Load the new build, remembering the library if it doesn't work
+        self.unloaded.insert(name.to_string(), path.clone());
+        let loaded = Self::load(&path, self.wasm)
+            .map_err(|err| format!("could not load the new build of \"{name}\" (reason: \"{err}\"), fix it and reload again"))?;
+        if loaded.plugin.name() != name {
+            return Err(format!("the new build of \"{name}\" is named \"{}\", restart the server to load it", loaded.plugin.name()).into());
+        }
+
+        //(6) The new build may want other dependencies or another server
+        let resolution = {
+            let mut plugins: Vec<&dyn Plugin> = self.plugins.iter().map(|loaded| &*loaded.plugin).collect();
+            plugins.push(&*loaded.plugin);
+            resolver::resolve(&plugins, &self.server)
+        };
+        let new = self.plugins.len();
+        for (_, warning) in resolution.warnings.iter().filter(|(index, _)| *index == new) {
+            warn!("{warning}");
+        }
+        if let Some((_, reason)) = resolution.refused.iter().find(|(index, _)| *index == new) {
+            return Err(format!("the new build of \"{name}\" will not be loaded, {reason}").into());
+        }
+        self.unloaded.remove(name);
+
+        //(R) Start it last, which is after its dependencies, and nothing that
+        //runs depends on it
+        self.plugins.push(loaded);
+        self.start(new);
+        match self.plugins[new].state {
+            PluginState::Started => Ok(()),
+            _ => Err(format!("the new build of \"{name}\" is loaded, but did not start").into())
+        }
+    }
+
+    /// Where a plugin keeps its config and data, named after the plugin
+    pub fn data_folder(&self, name: &str) -> PathBuf {
+        self.folder.join(folder_name(name))
+    }
+
+    //Takes the fields apart, so it can be used while a plugin is borrowed
+    fn release(events: &EventBus, commands: &CommandMap, scheduler: &TaskScheduler, name: &str) {
+        events.unregister(name);
+        commands.unregister(name);
+        scheduler.deactivate(name);
+    }
+
+    /// Dispatches events to the plugins' listeners
+    pub fn events(&self) -> &Arc<EventBus> {&self.events}
+
+    /// Who is online, for the server to keep up to date
+    pub fn players(&self) -> &Arc<PlayerList> {&self.players}
+
+    /// Runs the plugins' tasks, once per server tick
+    pub fn tick(&self) {
+        self.scheduler.tick();
+    }
+
+    /// Runs a plugin command, `allowed` tells whether the sender has a
+    /// permission node
+    pub fn execute_command(&self, sender: CommandSender, line: &str, allowed: impl Fn(&str) -> bool)
+        -> Result<Vec<String>, CommandError>
+    {
+        self.commands.execute(sender, line, allowed)
+    }
+
+    /// Name and description of every plugin command
+    pub fn commands(&self) -> Vec<(String, String)> {
+        self.commands.list()
+    }
+
+    /// Name, version and state of every plugin, in start order
+    pub fn plugins(&self) -> impl Iterator<Item = (&str, &PluginVersion, PluginState)> {
+        self.plugins.iter()
+            .map(|loaded| (loaded.plugin.name(), loaded.plugin.version(), loaded.state))
+    }
+
+}
+
+//Plugin names can be anything, folder names can't
+fn folder_name(name: &str) -> String {
+    let mut folder: String = name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c
+        })
+        .collect();
+    if folder.trim_matches('.').is_empty() {
+        folder.insert(0, '_');
+    }
+    folder
+}
+
+impl LoadedPlugin {
+    fn call(&mut self, next_state: PluginState, f: impl FnOnce(&mut dyn Plugin)) {
+        //A panicking plugin is disabled instead of taking the server with it
+        let plugin = &mut *self.plugin;
+        match panic::catch_unwind(AssertUnwindSafe(|| f(plugin))) {
+            Ok(()) => self.state = next_state,
+            Err(_) => {
+                error!("Plugin \"{}\" panicked and has been disabled", self.plugin.name());
+                self.state = PluginState::Failed;
+            }
+        }
+    }
+}
+
+impl fmt::Debug for PluginManager {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.plugins().map(|(name, version, state)| format!("{name} {version} ({state:?})")))
+            .finish()
+    }
+}
+
+impl Drop for PluginManager {
+    fn drop(&mut self) {
+        //Plugins should always get the chance to clean up
+        if self.plugins.iter().any(|loaded| loaded.state == PluginState::Started) {
+            warn!("Plugin manager dropped while plugins were running, stopping them");
+            self.stop_all();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PluginError(String);
+
+impl From<String> for PluginError {
+    fn from(msg: String) -> Self { Self(msg) }
+}
+impl From<&str> for PluginError {
+    fn from(msg: &str) -> Self { Self(msg.to_string()) }
+}
+
+impl Error for PluginError {}
+impl Display for PluginError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod manager_test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{Dependency, EventKind, Priority, Scheduler};
+
+    use super::*;
+
+    const VERSION: PluginVersion = PluginVersion::new((1, 0, 0));
+
+    //Writes down every call, so we can check the order
+    struct TestPlugin {
+        name: &'static str,
+        dependencies: &'static [&'static str],
+        panics: bool,
+        calls: Arc<Mutex<Vec<String>>>
+    }
+
+    impl Plugin for TestPlugin {
+        fn name(&self) -> &str {self.name}
+        fn version(&self) -> &PluginVersion {&VERSION}
+        fn dependencies(&self) -> Vec<Dependency<'_>> {
+            self.dependencies.iter().map(|name| Dependency::required(name, "*")).collect()
+        }
+
+        fn start(&mut self, ctx: &mut PluginContext) {
+            self.calls.lock().unwrap().push(format!("start {}", self.name));
+            ctx.listen(EventKind::ServerTick, Priority::Normal, |_| {});
+            ctx.command(self.name, "", None, |_| {});
+            if self.panics {
+                panic!("{} does not want to start", self.name);
+            }
+        }
+        fn stop(&mut self) {
+            self.calls.lock().unwrap().push(format!("stop {}", self.name));
+        }
+    }
+
+    fn manager(plugins: &[(&'static str, &'static [&'static str], bool)]) -> (PluginManager, Arc<Mutex<Vec<String>>>) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let plugins = plugins.iter()
+            .map(|&(name, dependencies, panics)| -> Box<dyn Plugin> {
+                Box::new(TestPlugin {name, dependencies, panics, calls: calls.clone()})
+            })
+            .collect();
+        let folder = env::temp_dir().join("srvr-manager-test");
+        (PluginManager::from_plugins(&folder, &Version::new(0, 1, 0), plugins), calls)
+    }
+
+    #[test]
+    fn dependency_order() {
+        let (mut manager, calls) = manager(&[
+            ("economy", &["database"], false),
+            ("shops", &["economy", "database"], false),
+            ("database", &[], false)
+        ]);
+        manager.start_all();
+        manager.stop_all();
+        assert_eq!(*calls.lock().unwrap(), vec![
+            "start database", "start economy", "start shops",
+            "stop shops", "stop economy", "stop database"
+        ]);
+    }
+
+    #[test]
+    fn missing_and_cyclic() {
+        let (manager, _) = manager(&[
+            ("chicken", &["egg"], false),
+            ("egg", &["chicken"], false),
+            ("lonely", &["friend"], false),
+            ("needs-lonely", &["lonely"], false),
+            ("fine", &[], false)
+        ]);
+        let names: Vec<&str> = manager.plugins().map(|(name, _, _)| name).collect();
+        assert_eq!(names, vec!["fine"]);
+    }
+
+    #[test]
+    fn panics_are_isolated() {
+        let (mut manager, calls) = manager(&[
+            ("broken", &[], true),
+            ("needs-broken", &["broken"], false),
+            ("fine", &[], false)
+        ]);
+        manager.start_all();
+        assert_eq!(manager.events().len(), 1);
+        assert_eq!(manager.commands().len(), 1);
+        let states: Vec<(&str, PluginState)> = manager.plugins().map(|(name, _, state)| (name, state)).collect();
+        assert_eq!(states, vec![
+            ("broken", PluginState::Failed),
+            ("fine", PluginState::Started),
+            ("needs-broken", PluginState::Loaded)
+        ]);
+
+        //Failed plugins aren't stopped, as they never really started
+        manager.stop_all();
+        assert_eq!(*calls.lock().unwrap(), vec!["start broken", "start fine", "stop fine"]);
+        assert!(manager.events().is_empty());
+        assert!(manager.commands().is_empty());
+    }
+
+    #[test]
+    fn reload_refused() {
+        let (mut manager, calls) = manager(&[("built-in", &[], false)]);
+        manager.start_all();
+        assert!(manager.reload("built-in").unwrap_err().to_string().contains("built into the server"));
+        assert!(manager.reload("elsewhere").unwrap_err().to_string().contains("no plugin named"));
+
+        //Refusing leaves it running
+        assert_eq!(*calls.lock().unwrap(), vec!["start built-in"]);
+        assert_eq!(manager.events().len(), 1);
+    }
+
+    //Keeps its scheduler when it stops, like a plugin that handed it to a
+    //thread of its own
+    struct KeepsScheduler {
+        scheduler: Option<Scheduler>
+    }
+
+    impl Plugin for KeepsScheduler {
+        fn name(&self) -> &str {"keeper"}
+        fn version(&self) -> &PluginVersion {&VERSION}
+        fn start(&mut self, ctx: &mut PluginContext) {
+            self.scheduler = Some(ctx.scheduler());
+        }
+        fn stop(&mut self) {}
+    }
+
+    #[test]
+    fn reload_refused_with_leftovers() {
+        let folder = env::temp_dir().join("srvr-manager-test");
+        let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(KeepsScheduler {scheduler: None})];
+        let mut manager = PluginManager::from_plugins(&folder, &Version::new(0, 1, 0), plugins);
+        manager.plugins[0].path = Some(folder.join("keeper.so"));
+        manager.start_all();
+
+        //It's refused before the library would be unloaded, and started again
+        let err = manager.reload("keeper").unwrap_err().to_string();
+        assert!(err.contains("still holds 1 of its"), "{err}");
+        assert_eq!(manager.plugins[0].state, PluginState::Started);
+        manager.stop_all();
+    }
+
+    #[test]
+    fn data_folders() {
+        let (manager, _) = manager(&[]);
+        let folder = |name| manager.data_folder(name).file_name().unwrap().to_string_lossy().into_owned();
+        assert_eq!(folder("Sample Plugin"), "Sample Plugin");
+        assert_eq!(folder("../../etc"), ".._.._etc");
+        assert_eq!(folder(".."), "_..");
+        assert_eq!(folder(""), "_");
+    }
+
+    #[test]
+    fn wasm_plugins() {
+        //Loaded from the folder next to native plugins, and reloadable like them
+        let folder = env::temp_dir().join("srvr-manager-wasm-test");
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        let module = |version| format!(r#"(module
+            (@custom "srvr-plugin" "name = \"guest\"\nversion = \"{version}\"")
+            (memory (export "memory") 1)
+            (func (export "srvr_alloc") (param i32) (result i32) (i32.const 1024)))"#);
+        fs::write(folder.join("guest.wasm"), module("1.0.0")).unwrap();
+        fs::write(folder.join("notes.txt"), "not a plugin").unwrap();
+
+        let mut manager = PluginManager::load_folder(&folder, &Version::new(0, 1, 0), WasmLimits::default()).unwrap();
+        manager.start_all();
+        assert_eq!(manager.plugins().collect::<Vec<_>>(), vec![("guest", &PluginVersion::new((1, 0, 0)), PluginState::Started)]);
+
+        fs::write(folder.join("guest.wasm"), module("1.1.0")).unwrap();
+        manager.reload("guest").unwrap();
+        assert_eq!(manager.plugins().collect::<Vec<_>>(), vec![("guest", &PluginVersion::new((1, 1, 0)), PluginState::Started)]);
+        manager.stop_all();
+    }
+
+    #[test]
+    fn wasm_reload_after_failed_start() {
+        //A module that traps in srvr_start, after scheduling a task
+        let folder = env::temp_dir().join("srvr-manager-wasm-trap-test");
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("guest.wasm"), r#"(module
+            (@custom "srvr-plugin" "name = \"guest\"\nversion = \"1.0.0\"")
+            (import "srvr" "schedule" (func $schedule (param i64 i64 i32) (result i64)))
+            (memory (export "memory") 1)
+            (func (export "srvr_alloc") (param i32) (result i32) (i32.const 1024))
+            (func (export "srvr_task") (param i32))
+            (func (export "srvr_start")
+                (drop (call $schedule (i64.const 20) (i64.const 0) (i32.const 0)))
+                unreachable))"#).unwrap();
+
+        let mut manager = PluginManager::load_folder(&folder, &Version::new(0, 1, 0), WasmLimits::default()).unwrap();
+        manager.start_all();
+        assert_eq!(manager.plugins().collect::<Vec<_>>(), vec![("guest", &PluginVersion::new((1, 0, 0)), PluginState::Failed)]);
+
+        //Nothing of the failed start is left, so the fixed build loads
+        fs::write(folder.join("guest.wasm"), r#"(module
+            (@custom "srvr-plugin" "name = \"guest\"\nversion = \"1.0.1\"")
+            (memory (export "memory") 1)
+            (func (export "srvr_alloc") (param i32) (result i32) (i32.const 1024)))"#).unwrap();
+        manager.reload("guest").unwrap();
+        assert_eq!(manager.plugins().collect::<Vec<_>>(), vec![("guest", &PluginVersion::new((1, 0, 1)), PluginState::Started)]);
+        manager.stop_all();
+    }
+
+}
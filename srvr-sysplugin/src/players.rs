@@ -0,0 +1,198 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+//! Which players are online, for plugins to look up.
+//!
+//! The server owns the list and replaces it whenever someone joins, leaves,
+//! changes worlds or gets a new ping. Plugins only ever read a copy, through
+//! a [`Players`] they can keep for as long as they like.
+
+use std::{
+    ffi::c_void,
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration
+};
+
+use crate::ffi::FfiStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnlinePlayer {
+    pub name: String,
+    pub uuid: u128,
+    //Keep-alive latency, zero until the first keep-alive returns
+    pub ping: Duration,
+    //Name of the world the player is in
+    pub world: String
+}
+
+/// An [`OnlinePlayer`] as it crosses the boundary, only valid during the call
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RawOnlinePlayer<'a> {
+    pub name: FfiStr<'a>,
+    pub uuid: u128,
+    pub ping_ms: u64,
+    pub world: FfiStr<'a>
+}
+
+/// The server's list of online players
+#[derive(Debug, Default)]
+pub struct PlayerList {
+    players: Mutex<Vec<OnlinePlayer>>
+}
+
+impl PlayerList {
+
+    pub fn new() -> Self {Self::default()}
+
+    fn players(&self) -> MutexGuard<'_, Vec<OnlinePlayer>> {
+        self.players.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Replaces the list, called by the server whenever it changes
+    pub fn set(&self, players: Vec<OnlinePlayer>) {
+        *self.players() = players;
+    }
+
+    pub fn get(&self) -> Vec<OnlinePlayer> {
+        self.players().clone()
+    }
+
+    /// What a plugin's [`Players`] reads through
+    pub(crate) fn api(players: Arc<PlayerList>) -> PlayersApi {
+        PlayersApi {
+            host: Arc::into_raw(players) as *const c_void,
+            list: host_list,
+            retain: host_retain,
+            release: host_release
+        }
+    }
+
+}
+
+/// The plugin's way to see who is online. It can be cloned and kept around.
+pub struct Players {
+    api: PlayersApi
+}
+
+/// What [`Players`] calls into, owned by the server
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PlayersApi {
+    pub host: *const c_void,
+    /// Calls the function with the plugin's pointer once for every player
+    pub list: unsafe extern "C" fn(*const c_void, *mut c_void, unsafe extern "C" fn(*mut c_void, RawOnlinePlayer)),
+    pub retain: unsafe extern "C" fn(*const c_void),
+    pub release: unsafe extern "C" fn(*const c_void)
+}
+
+//The server's side is behind a mutex
+unsafe impl Send for Players {}
+unsafe impl Sync for Players {}
+
+impl Players {
+    pub(crate) fn new(api: PlayersApi) -> Self {
+        Players {api}
+    }
+
+    /// Everyone who is online right now
+    pub fn online(&self) -> Vec<OnlinePlayer> {
+        let mut players: Vec<OnlinePlayer> = Vec::new();
+        unsafe { (self.api.list)(self.api.host, &mut players as *mut Vec<OnlinePlayer> as *mut c_void, collect) }
+        players
+    }
+}
+
+unsafe extern "C" fn collect(players: *mut c_void, player: RawOnlinePlayer) {
+    let players = &mut *(players as *mut Vec<OnlinePlayer>);
+    players.push(OnlinePlayer {
+        name: player.name.to_string(),
+        uuid: player.uuid,
+        ping: Duration::from_millis(player.ping_ms),
+        world: player.world.to_string()
+    });
+}
+
+impl Clone for Players {
+    fn clone(&self) -> Self {
+        unsafe { (self.api.retain)(self.api.host) }
+        Players {api: self.api}
+    }
+}
+
+impl Drop for Players {
+    fn drop(&mut self) {
+        unsafe { (self.api.release)(self.api.host) }
+    }
+}
+
+impl Debug for Players {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Players").finish_non_exhaustive()
+    }
+}
+
+unsafe extern "C" fn host_list(host: *const c_void, out: *mut c_void, push: unsafe extern "C" fn(*mut c_void, RawOnlinePlayer)) {
+    let host = &*(host as *const PlayerList);
+    for player in host.players().iter() {
+        push(out, RawOnlinePlayer {
+            name: FfiStr::new(&player.name),
+            uuid: player.uuid,
+            ping_ms: player.ping.as_millis() as u64,
+            world: FfiStr::new(&player.world)
+        });
+    }
+}
+
+unsafe extern "C" fn host_retain(host: *const c_void) {
+    Arc::increment_strong_count(host as *const PlayerList);
+}
+
+unsafe extern "C" fn host_release(host: *const c_void) {
+    Arc::decrement_strong_count(host as *const PlayerList);
+}
+
+#[cfg(test)]
+mod players_test {
+    use super::*;
+
+    #[test]
+    fn copies() {
+        let list = Arc::new(PlayerList::new());
+        let players = Players::new(PlayerList::api(list.clone()));
+        assert!(players.online().is_empty());
+
+        let jeb = OnlinePlayer {name: "jeb_".to_string(), uuid: 2, ping: Duration::from_millis(42), world: "nether".to_string()};
+        list.set(vec![jeb.clone()]);
+        let kept = players.clone();
+        drop(players);
+        assert_eq!(kept.online(), vec![jeb]);
+
+        //Every copy lets go of the list when it's dropped
+        assert_eq!(Arc::strong_count(&list), 2);
+        drop(kept);
+        assert_eq!(Arc::strong_count(&list), 1);
+    }
+}
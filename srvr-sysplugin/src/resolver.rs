@@ -0,0 +1,309 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::collections::VecDeque;
+
+use semver::{Version, VersionReq};
+
+use crate::Plugin;
+
+/// Which plugins can be loaded, and in which order
+#[derive(Debug, Default)]
+pub(crate) struct Resolution {
+    /// Indices of the plugins that can be loaded, each after its dependencies
+    pub order: Vec<usize>,
+    /// Plugins that can't be loaded, and why
+    pub refused: Vec<(usize, String)>,
+    /// Problems that don't keep a plugin from loading
+    pub warnings: Vec<(usize, String)>
+}
+
+//A plugin with its version ranges parsed
+struct Node<'a> {
+    name: &'a str,
+    version: Version,
+    requires: Vec<(&'a str, VersionReq)>,
+    optional: Vec<(&'a str, VersionReq)>
+}
+
+/*(Note to future self)
+  Reasons are logged as "Plugin "x" will not be loaded, <reason>", so they
+  should tell the operator what to do about it, not just what is wrong.
+*/
+pub(crate) fn resolve(plugins: &[&dyn Plugin], server: &Version) -> Resolution {
+    let mut resolution = Resolution::default();
+    let mut reasons: Vec<Option<String>> = vec![None; plugins.len()];
+
+    //(1) Check what every plugin can check by itself: the server version, and
+    //whether its ranges make sense
+    let mut nodes = Vec::with_capacity(plugins.len());
+    for (index, &plugin) in plugins.iter().enumerate() {
+        let min_server_version = plugin.min_server_version();
+        if *server < Version::from(min_server_version) {
+            reasons[index].get_or_insert(format!(
+                "it needs srvr {min_server_version} or newer, but this is srvr v{server}. \
+                Update the server, or use an older build of the plugin"
+            ));
+        }
+        let mut node = Node {
+            name: plugin.name(),
+            version: Version::from(*plugin.version()),
+            requires: Vec::new(),
+            optional: Vec::new()
+        };
+        for dependency in plugin.dependencies() {
+            match VersionReq::parse(dependency.versions()) {
+                Ok(versions) if dependency.is_optional() => node.optional.push((dependency.name(), versions)),
+                Ok(versions) => node.requires.push((dependency.name(), versions)),
+                Err(err) => {
+                    reasons[index].get_or_insert(format!(
+                        "its dependency \"{}\" has an invalid version range \"{}\" ({err}). \
+                        Ask the plugin's author to fix it", dependency.name(), dependency.versions()
+                    ));
+                }
+            }
+        }
+        nodes.push(node);
+    }
+    let find = |name: &str| nodes.iter().position(|node| node.name == name);
+
+    //(2) Refuse plugins whose required dependencies are missing or don't fit.
+    //Refusing one can leave another without its dependency, so repeat until stable
+    loop {
+        let mut changed = false;
+        for index in 0..nodes.len() {
+            if reasons[index].is_some() {
+                continue;
+            }
+            let reason = nodes[index].requires.iter().find_map(|(name, versions)| match find(name) {
+                None => Some(format!(
+                    "it needs plugin \"{name}\" {versions}, which isn't installed. Add it to the plugins folder"
+                )),
+                Some(dependency) if reasons[dependency].is_some() => Some(format!(
+                    "it needs plugin \"{name}\", which can't be loaded either"
+                )),
+                Some(dependency) if !versions.matches(&nodes[dependency].version) => Some(format!(
+                    "it needs plugin \"{name}\" {versions}, but v{} is installed. Install a version that fits",
+                    nodes[dependency].version
+                )),
+                Some(_) => None
+            });
+            if reason.is_some() {
+                reasons[index] = reason;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    //(3) Optional dependencies only count if they are there and fit
+    let mut uses: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
+    for index in 0..nodes.len() {
+        if reasons[index].is_some() {
+            continue;
+        }
+        for (name, versions) in &nodes[index].optional {
+            match find(name) {
+                Some(dependency) if reasons[dependency].is_some() => {},
+                Some(dependency) if !versions.matches(&nodes[dependency].version) => resolution.warnings.push((index, format!(
+                    "Plugin \"{}\" can use plugin \"{name}\" {versions}, but v{} is installed. It will run without it",
+                    nodes[index].name, nodes[dependency].version
+                ))),
+                Some(dependency) => uses[index].push(dependency),
+                None => {}
+            }
+        }
+    }
+
+    //(4) Keep placing plugins whose dependencies are all placed. If only
+    //optional dependencies are in the way, they are what we give up on
+    let mut placed = vec![false; nodes.len()];
+    loop {
+        let ready = |index: usize, with_optional: bool| {
+            !placed[index] && reasons[index].is_none()
+                && nodes[index].requires.iter().all(|(name, _)| find(name).is_some_and(|dependency| placed[dependency]))
+                && (!with_optional || uses[index].iter().all(|&dependency| placed[dependency]))
+        };
+        let next = match (0..nodes.len()).find(|&index| ready(index, true)) {
+            Some(index) => index,
+            None => match (0..nodes.len()).find(|&index| ready(index, false)) {
+                Some(index) => {
+                    let waiting: Vec<&str> = uses[index].iter()
+                        .filter(|&&dependency| !placed[dependency])
+                        .map(|&dependency| nodes[dependency].name)
+                        .collect();
+                    resolution.warnings.push((index, format!(
+                        "Plugin \"{}\" starts before {}, their optional dependencies form a cycle",
+                        nodes[index].name, waiting.join(", ")
+                    )));
+                    index
+                },
+                None => break
+            }
+        };
+        placed[next] = true;
+        resolution.order.push(next);
+    }
+
+    //(5) Whatever is left is stuck on a cycle of required dependencies
+    let stuck: Vec<bool> = (0..nodes.len()).map(|index| !placed[index] && reasons[index].is_none()).collect();
+    for index in (0..nodes.len()).filter(|&index| stuck[index]) {
+        let required = |index: usize| -> Vec<usize> {
+            nodes[index].requires.iter().filter_map(|(name, _)| find(name)).filter(|&dependency| stuck[dependency]).collect()
+        };
+        reasons[index] = Some(match cycle(index, nodes.len(), required) {
+            Some(cycle) => {
+                let names: Vec<&str> = cycle.iter().map(|&index| nodes[index].name).collect();
+                format!("its dependencies form a cycle: {}. One of them has to drop its dependency", names.join(" -> "))
+            },
+            None => format!(
+                "it needs plugin \"{}\", which can't be loaded either", nodes[required(index)[0]].name
+            )
+        });
+    }
+
+    //(R) Everything that was refused, in the order the plugins came in
+    resolution.refused = reasons.into_iter().enumerate()
+        .filter_map(|(index, reason)| reason.map(|reason| (index, reason)))
+        .collect();
+    resolution
+}
+
+//The shortest way back to `start` through `edges`, as [start, .., start]
+fn cycle(start: usize, len: usize, edges: impl Fn(usize) -> Vec<usize>) -> Option<Vec<usize>> {
+    let mut previous: Vec<Option<usize>> = vec![None; len];
+    let mut queue = VecDeque::from([start]);
+    while let Some(current) = queue.pop_front() {
+        for next in edges(current) {
+            //Closed the loop, walk back to where we started
+            if next == start {
+                let mut path = vec![start];
+                let mut at = current;
+                while at != start {
+                    path.push(at);
+                    at = previous[at].unwrap_or(start);
+                }
+                path.push(start);
+                path.reverse();
+                return Some(path);
+            }
+            if previous[next].is_none() {
+                previous[next] = Some(current);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod resolver_test {
+    use crate::{Dependency, PluginContext, PluginVersion};
+
+    use super::*;
+
+    struct TestPlugin {
+        name: &'static str,
+        version: PluginVersion,
+        dependencies: Vec<(&'static str, &'static str, bool)>,
+        min_server_version: PluginVersion
+    }
+
+    impl Plugin for TestPlugin {
+        fn name(&self) -> &str {self.name}
+        fn version(&self) -> &PluginVersion {&self.version}
+        fn dependencies(&self) -> Vec<Dependency<'_>> {
+            self.dependencies.iter()
+                .map(|&(name, versions, optional)| match optional {
+                    true => Dependency::optional(name, versions),
+                    false => Dependency::required(name, versions)
+                })
+                .collect()
+        }
+        fn min_server_version(&self) -> PluginVersion {self.min_server_version}
+        fn start(&mut self, _: &mut PluginContext) {}
+        fn stop(&mut self) {}
+    }
+
+    fn plugin(name: &'static str, version: (usize, usize, usize), dependencies: &[(&'static str, &'static str, bool)]) -> TestPlugin {
+        TestPlugin {
+            name,
+            version: PluginVersion::new(version),
+            dependencies: dependencies.to_vec(),
+            min_server_version: PluginVersion::new((0, 0, 0))
+        }
+    }
+
+    //Names in load order, and the refused plugins with their reasons
+    fn resolved(plugins: &[TestPlugin]) -> (Vec<&'static str>, Vec<(&'static str, String)>) {
+        let resolution = resolve(&plugins.iter().map(|plugin| plugin as &dyn Plugin).collect::<Vec<_>>(), &Version::new(1, 4, 0));
+        let order = resolution.order.iter().map(|&index| plugins[index].name).collect();
+        let refused = resolution.refused.into_iter().map(|(index, reason)| (plugins[index].name, reason)).collect();
+        (order, refused)
+    }
+
+    #[test]
+    fn order() {
+        let (order, refused) = resolved(&[
+            plugin("shops", (1, 0, 0), &[("economy", "^2.1", false), ("maps", "*", true)]),
+            plugin("economy", (2, 3, 1), &[("database", ">=0.3, <0.5", false)]),
+            plugin("maps", (0, 1, 0), &[("shops", "*", true)]),
+            plugin("database", (0, 4, 0), &[])
+        ]);
+        assert!(refused.is_empty());
+        assert_eq!(order, vec!["database", "economy", "shops", "maps"]);
+    }
+
+    #[test]
+    fn refused() {
+        let mut too_new = plugin("too-new", (1, 0, 0), &[]);
+        too_new.min_server_version = PluginVersion::new((2, 0, 0));
+        let (order, refused) = resolved(&[
+            too_new,
+            plugin("economy", (1, 9, 0), &[]),
+            plugin("shops", (1, 0, 0), &[("economy", "^2", false)]),
+            plugin("auctions", (1, 0, 0), &[("shops", "*", false)]),
+            plugin("lonely", (1, 0, 0), &[("friend", "*", false)]),
+            plugin("typo", (1, 0, 0), &[("economy", "two", false)]),
+            plugin("chicken", (1, 0, 0), &[("egg", "*", false)]),
+            plugin("egg", (1, 0, 0), &[("chicken", "*", false)]),
+            plugin("omelette", (1, 0, 0), &[("egg", "*", false)]),
+            plugin("bonus", (1, 0, 0), &[("economy", "^2", true), ("friend", "*", true)])
+        ]);
+        assert_eq!(order, vec!["economy", "bonus"]);
+
+        let reason = |name: &str| refused.iter().find(|(refused, _)| *refused == name).map(|(_, reason)| reason.as_str()).unwrap();
+        assert!(reason("too-new").contains("needs srvr v2.0.0 or newer, but this is srvr v1.4.0"));
+        assert!(reason("shops").contains("needs plugin \"economy\" ^2, but v1.9.0 is installed"));
+        assert!(reason("auctions").contains("\"shops\", which can't be loaded"));
+        assert!(reason("lonely").contains("\"friend\" *, which isn't installed"));
+        assert!(reason("typo").contains("invalid version range \"two\""));
+        assert!(reason("chicken").contains("cycle: chicken -> egg -> chicken"));
+        assert!(reason("egg").contains("cycle: egg -> chicken -> egg"));
+        assert!(reason("omelette").contains("\"egg\", which can't be loaded"));
+        assert_eq!(refused.len(), 8);
+    }
+}
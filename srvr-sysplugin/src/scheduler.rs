@@ -0,0 +1,495 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+//! Work that plugins want done later, or over and over.
+//!
+//! Time is counted in server ticks (twenty per second). Tasks run on the
+//! server's own thread during the tick, unless they are async, in which case
+//! they get a thread of their own. When a plugin stops, its pending tasks are
+//! cancelled and its running async tasks are waited for.
+
+use std::{
+    collections::HashSet,
+    ffi::c_void,
+    fmt::{self, Debug, Formatter},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError
+    },
+    thread::{self, JoinHandle}
+};
+
+use log::{error, warn};
+
+use crate::handles::{Handle, Handles};
+
+/// Returned when scheduling, to cancel the task with
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskId(pub(crate) u64);
+
+/// A task closure behind a C function table, like a
+/// [`RawListener`](crate::events::RawListener)
+#[repr(C)]
+pub struct RawTask {
+    data: *mut c_void,
+    call: unsafe extern "C" fn(*mut c_void) -> bool,
+    drop: unsafe extern "C" fn(*mut c_void)
+}
+
+//The closures are required to be Send
+unsafe impl Send for RawTask {}
+
+impl RawTask {
+    pub fn new<F>(task: F) -> Self
+        where F: FnMut() + Send + 'static
+    {
+        RawTask {
+            data: Box::into_raw(Box::new(task)) as *mut c_void,
+            call: call_shim::<F>,
+            drop: drop_shim::<F>
+        }
+    }
+}
+
+unsafe extern "C" fn call_shim<F: FnMut()>(data: *mut c_void) -> bool {
+    let task = &mut *(data as *mut F);
+    panic::catch_unwind(AssertUnwindSafe(task)).is_ok()
+}
+
+unsafe extern "C" fn drop_shim<F>(data: *mut c_void) {
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(data as *mut F))));
+}
+
+impl Drop for RawTask {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.data) }
+    }
+}
+
+struct Task {
+    id: TaskId,
+    owner: String,
+    /// Zero for tasks that only run once
+    period: u64,
+    run_async: bool,
+    cancelled: AtomicBool,
+    /// Async tasks skip a period while their last run hasn't finished
+    running: AtomicBool,
+    closure: Mutex<RawTask>,
+    _handle: Handle
+}
+
+impl Task {
+    fn run(&self) {
+        let closure = self.closure.lock().unwrap_or_else(PoisonError::into_inner);
+        let ok = unsafe { (closure.call)(closure.data) };
+        if !ok {
+            error!("Task of plugin \"{}\" panicked and has been cancelled", self.owner);
+            self.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+struct Scheduled {
+    next_tick: u64,
+    task: Arc<Task>
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    tick: u64,
+    next_id: u64,
+    /// Plugins that may schedule tasks, which are the ones that are running
+    active: HashSet<String>,
+    tasks: Vec<Scheduled>,
+    threads: Vec<(String, JoinHandle<()>)>
+}
+
+#[derive(Default)]
+pub struct TaskScheduler {
+    state: Mutex<SchedulerState>,
+    /// Tasks, wherever they are, and schedulers plugins hold on to
+    handles: Handles
+}
+
+impl TaskScheduler {
+
+    pub fn new() -> Self {Self::default()}
+
+    fn state(&self) -> MutexGuard<'_, SchedulerState> {
+        //Tasks never run while we hold the lock
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Refused (returning None) once the plugin has stopped
+    pub fn schedule(&self, owner: &str, delay: u64, period: u64, run_async: bool, task: RawTask)
+        -> Option<TaskId>
+    {
+        let mut state = self.state();
+        if !state.active.contains(owner) {
+            return None;
+        }
+        state.next_id += 1;
+        let id = TaskId(state.next_id);
+        let next_tick = state.tick + delay.max(1);
+        state.tasks.push(Scheduled {
+            next_tick,
+            task: Arc::new(Task {
+                id,
+                owner: owner.to_string(),
+                period,
+                run_async,
+                cancelled: AtomicBool::new(false),
+                running: AtomicBool::new(false),
+                closure: Mutex::new(task),
+                _handle: self.handles.handle(owner)
+            })
+        });
+        Some(id)
+    }
+
+    pub fn cancel(&self, owner: &str, id: TaskId) {
+        self.remove(|task| task.owner == owner && task.id == id);
+    }
+
+    fn remove(&self, cancel: impl Fn(&Task) -> bool) {
+        //Dropping a task runs plugin code, which may schedule again, so we
+        //drop them after letting go of the lock
+        let mut removed = Vec::new();
+        self.state().tasks.retain(|scheduled| {
+            if !cancel(&scheduled.task) {
+                return true;
+            }
+            scheduled.task.cancelled.store(true, Ordering::Relaxed);
+            removed.push(scheduled.task.clone());
+            false
+        });
+        drop(removed);
+    }
+
+    pub fn activate(&self, owner: &str) {
+        self.state().active.insert(owner.to_string());
+    }
+
+    /// Cancels every task of a plugin, and waits for the ones that are running
+    pub fn deactivate(&self, owner: &str) {
+        //(1) No new tasks, and none of the pending ones
+        self.state().active.remove(owner);
+        self.remove(|task| task.owner == owner);
+
+        //(2) Async tasks still running live in the plugin's library, so we
+        //can't go on without them
+        let threads: Vec<JoinHandle<()>> = {
+            let mut state = self.state();
+            let (mine, others) = std::mem::take(&mut state.threads).into_iter()
+                .partition(|(thread_owner, _)| thread_owner == owner);
+            state.threads = others;
+            mine.into_iter().map(|(_, handle)| handle).collect()
+        };
+        if threads.iter().any(|handle| !handle.is_finished()) {
+            warn!("Waiting for async tasks of plugin \"{owner}\" to finish");
+        }
+        for handle in threads {
+            let _ = handle.join();
+        }
+    }
+
+    /// Called once per server tick
+    pub fn tick(&self) {
+        //(1) Take the tasks that are due, rescheduling the repeating ones
+        let due: Vec<Arc<Task>> = {
+            let mut state = self.state();
+            state.tick += 1;
+            let now = state.tick;
+            state.threads.retain(|(_, handle)| !handle.is_finished());
+            let mut due = Vec::new();
+            state.tasks.retain_mut(|scheduled| {
+                if scheduled.next_tick > now {
+                    return true;
+                }
+                due.push(scheduled.task.clone());
+                scheduled.next_tick = now + scheduled.task.period;
+                scheduled.task.period > 0
+            });
+            due
+        };
+
+        //(2) Run them, without the lock so they can schedule more
+        for task in due {
+            if task.cancelled.load(Ordering::Relaxed) {
+                continue;
+            }
+            if !task.run_async {
+                task.run();
+                if task.cancelled.load(Ordering::Relaxed) {
+                    self.cancel(&task.owner, task.id);
+                }
+                continue;
+            }
+            if task.running.swap(true, Ordering::AcqRel) {
+                continue;
+            }
+            let owner = task.owner.clone();
+            let spawned = thread::Builder::new()
+                .name("srvr-plugin-task".to_string())
+                .spawn({
+                    let task = task.clone();
+                    move || {
+                        task.run();
+                        task.running.store(false, Ordering::Release);
+                    }
+                });
+            match spawned {
+                Ok(handle) => self.state().threads.push((owner, handle)),
+                Err(err) => {
+                    error!("Could not start async task of plugin \"{owner}\": \"{err}\"");
+                    task.running.store(false, Ordering::Release);
+                }
+            }
+        }
+    }
+
+    /// How many tasks (pending or running) and schedulers this plugin still
+    /// has
+    pub fn owned_by(&self, owner: &str) -> usize {
+        self.handles.count(owner)
+    }
+
+    pub fn len(&self) -> usize {self.state().tasks.len()}
+    pub fn is_empty(&self) -> bool {self.len() == 0}
+
+}
+
+impl Debug for TaskScheduler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let state = self.state();
+        f.debug_struct("TaskScheduler")
+            .field("tick", &state.tick)
+            .field("tasks", &state.tasks.len())
+            .field("threads", &state.threads.len())
+            .finish()
+    }
+}
+
+/// The plugin's way to schedule tasks. It can be cloned and kept around,
+/// but stops accepting tasks once the plugin has stopped.
+pub struct Scheduler {
+    api: SchedulerApi
+}
+
+/// What a [`Scheduler`] calls into, owned by the server
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SchedulerApi {
+    pub host: *const c_void,
+    pub schedule: unsafe extern "C" fn(*const c_void, u64, u64, bool, RawTask) -> u64,
+    pub cancel: unsafe extern "C" fn(*const c_void, u64),
+    pub retain: unsafe extern "C" fn(*const c_void),
+    pub release: unsafe extern "C" fn(*const c_void)
+}
+
+//The server's side is behind a mutex
+unsafe impl Send for Scheduler {}
+unsafe impl Sync for Scheduler {}
+
+impl Scheduler {
+    pub(crate) fn new(api: SchedulerApi) -> Self {
+        Scheduler {api}
+    }
+
+    fn schedule(&self, delay: u64, period: u64, run_async: bool, task: RawTask) -> Option<TaskId> {
+        match unsafe { (self.api.schedule)(self.api.host, delay, period, run_async, task) } {
+            0 => None,
+            id => Some(TaskId(id))
+        }
+    }
+
+    /// Runs once, after `delay` ticks
+    pub fn run_later<F>(&self, delay: u64, task: F) -> Option<TaskId>
+        where F: FnOnce() + Send + 'static
+    {
+        let mut task = Some(task);
+        self.schedule(delay, 0, false, RawTask::new(move || if let Some(task) = task.take() {task()}))
+    }
+
+    /// Runs after `delay` ticks, and then every `period` ticks
+    pub fn run_repeating<F>(&self, delay: u64, period: u64, task: F) -> Option<TaskId>
+        where F: FnMut() + Send + 'static
+    {
+        self.schedule(delay, period.max(1), false, RawTask::new(task))
+    }
+
+    /// Runs once, after `delay` ticks, on a thread of its own
+    pub fn run_async<F>(&self, delay: u64, task: F) -> Option<TaskId>
+        where F: FnOnce() + Send + 'static
+    {
+        let mut task = Some(task);
+        self.schedule(delay, 0, true, RawTask::new(move || if let Some(task) = task.take() {task()}))
+    }
+
+    pub fn cancel(&self, id: TaskId) {
+        unsafe { (self.api.cancel)(self.api.host, id.0) }
+    }
+}
+
+impl Clone for Scheduler {
+    fn clone(&self) -> Self {
+        unsafe { (self.api.retain)(self.api.host) }
+        Scheduler {api: self.api}
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        unsafe { (self.api.release)(self.api.host) }
+    }
+}
+
+impl Debug for Scheduler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scheduler").finish_non_exhaustive()
+    }
+}
+
+/// The server's side of a [`Scheduler`], for one plugin
+pub(crate) struct TaskOwner {
+    scheduler: Arc<TaskScheduler>,
+    owner: String,
+    _handle: Handle
+}
+
+impl TaskOwner {
+    pub(crate) fn api(scheduler: Arc<TaskScheduler>, owner: &str) -> SchedulerApi {
+        let _handle = scheduler.handles.handle(owner);
+        let host = Arc::new(TaskOwner {scheduler, owner: owner.to_string(), _handle});
+        SchedulerApi {
+            host: Arc::into_raw(host) as *const c_void,
+            schedule: host_schedule,
+            cancel: host_cancel,
+            retain: host_retain,
+            release: host_release
+        }
+    }
+}
+
+unsafe extern "C" fn host_schedule(host: *const c_void, delay: u64, period: u64, run_async: bool, task: RawTask) -> u64 {
+    let host = &*(host as *const TaskOwner);
+    host.scheduler.schedule(&host.owner, delay, period, run_async, task).map_or(0, |id| id.0)
+}
+
+unsafe extern "C" fn host_cancel(host: *const c_void, id: u64) {
+    let host = &*(host as *const TaskOwner);
+    host.scheduler.cancel(&host.owner, TaskId(id));
+}
+
+unsafe extern "C" fn host_retain(host: *const c_void) {
+    Arc::increment_strong_count(host as *const TaskOwner);
+}
+
+unsafe extern "C" fn host_release(host: *const c_void) {
+    Arc::decrement_strong_count(host as *const TaskOwner);
+}
+
+#[cfg(test)]
+mod scheduler_test {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    fn scheduler() -> (Arc<TaskScheduler>, Scheduler) {
+        let tasks = Arc::new(TaskScheduler::new());
+        tasks.activate("test");
+        let scheduler = Scheduler::new(TaskOwner::api(tasks.clone(), "test"));
+        (tasks, scheduler)
+    }
+
+    #[test]
+    fn one_shot_and_repeating() {
+        let (tasks, scheduler) = scheduler();
+        let runs = Arc::new(Mutex::new(Vec::new()));
+
+        let log = runs.clone();
+        scheduler.run_later(2, move || log.lock().unwrap().push("later"));
+        let log = runs.clone();
+        let repeating = scheduler.run_repeating(1, 2, move || log.lock().unwrap().push("repeat")).unwrap();
+
+        //Ticks 1 to 5: repeats on 1, 3 and 5, the one-shot on 2
+        for _ in 0..5 {
+            tasks.tick();
+        }
+        assert_eq!(*runs.lock().unwrap(), vec!["repeat", "later", "repeat", "repeat"]);
+        assert_eq!(tasks.len(), 1);
+
+        scheduler.cancel(repeating);
+        tasks.tick();
+        tasks.tick();
+        assert_eq!(runs.lock().unwrap().len(), 4);
+        assert!(tasks.is_empty());
+    }
+
+    #[test]
+    fn tasks_schedule_tasks() {
+        //A task may use the scheduler itself, which must not deadlock
+        let (tasks, scheduler) = scheduler();
+        let (tx, rx) = mpsc::channel();
+        let inner = scheduler.clone();
+        scheduler.run_later(1, move || {
+            inner.run_later(1, move || tx.send("inner").unwrap());
+        });
+        tasks.tick();
+        tasks.tick();
+        assert_eq!(rx.try_recv(), Ok("inner"));
+    }
+
+    #[test]
+    fn stopped_plugins() {
+        let (tasks, scheduler) = scheduler();
+
+        //An async task that is still running when the plugin stops is waited for
+        let (tx, rx) = mpsc::channel();
+        scheduler.run_async(1, move || {
+            thread::sleep(std::time::Duration::from_millis(50));
+            tx.send("done").unwrap();
+        });
+        scheduler.run_repeating(5, 5, || {});
+        tasks.tick();
+        tasks.deactivate("test");
+        assert_eq!(rx.try_recv(), Ok("done"));
+        assert!(tasks.is_empty());
+
+        //And nothing new is accepted
+        assert_eq!(scheduler.run_later(1, || {}), None);
+    }
+
+    #[test]
+    fn panicking_tasks_are_cancelled() {
+        let (tasks, scheduler) = scheduler();
+        scheduler.run_repeating(1, 1, || panic!("every tick"));
+        tasks.tick();
+        assert!(tasks.is_empty());
+    }
+
+}
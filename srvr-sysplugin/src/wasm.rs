@@ -0,0 +1,752 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+//! Plugins compiled to WebAssembly, for the ones the operator can't vouch for.
+//!
+//! A native plugin runs in the server's process with all of its rights, so a
+//! broken one can crash the server or scribble over its memory. A `.wasm`
+//! plugin runs in an interpreter instead: it only ever sees its own linear
+//! memory, only reaches the server through the functions below, and gets a
+//! fixed amount of fuel for every call into it. Running out of fuel, or any
+//! other trap, is handled like a panic in a native plugin.
+//!
+//! # The module's side
+//! The module describes itself in a custom section called `srvr-plugin`,
+//! holding TOML:
+//! ```toml
+//! name = "greeter"
+//! version = "1.0.0"
+//! min_server_version = "0.0.1"
+//!
+//! [dependencies]
+//! economy = "^1.2"
+//!
+//! [optional_dependencies]
+//! maps = "*"
+//! ```
+//! It has to export its `memory`, and `srvr_alloc(len: i32) -> i32` to make
+//! room for the strings the server passes in. Those belong to the module
+//! afterwards. Everything else is only needed if the module uses it:
+//! - `srvr_start()` and `srvr_stop()`
+//! - `srvr_event(handler: i32, ptr: i32, len: i32) -> i32` gets the event as
+//!   JSON. Returning 1 cancels it, 2 uncancels it and anything else leaves it.
+//! - `srvr_command(handler: i32, ptr: i32, len: i32)` gets the call as JSON,
+//!   `{"sender": "jeb_", "player": "<uuid>" or null, "args": "..."}`
+//! - `srvr_task(handler: i32)`
+//!
+//! `handler` is whatever number the module passed when it registered the
+//! listener, command or task, so it can tell them apart.
+//!
+//! The server's functions are imported from the `srvr` module:
+//! - `log(level: i32, ptr: i32, len: i32)`, from 1 (error) to 5 (trace)
+//! - `listen(kind: i32, priority: i32, handler: i32)`, numbered in the order of
+//!   [`EventKind`] and [`Priority`]. Only from `srvr_start`.
+//! - `command(name_ptr: i32, name_len: i32, description_ptr: i32,
+//!   description_len: i32, permission_ptr: i32, permission_len: i32,
+//!   handler: i32)`, an empty permission means none. Only from `srvr_start`.
+//! - `schedule(delay: i64, period: i64, handler: i32) -> i64` in ticks, where
+//!   a period of 0 runs the task once. Returns the task, or -1.
+//! - `cancel(task: i64)`
+//! - `reply(ptr: i32, len: i32)`, only from `srvr_command`
+//! - `players(ptr: i32, len: i32) -> i32` writes who is online as JSON,
+//!   `[{"name": "jeb_", "uuid": "<uuid>", "ping": <ms>, "world": "lobby"}]`,
+//!   if it fits in `len` bytes. Returns its length, so the module can try
+//!   again with more room if it didn't.
+//!
+//! There is no `run_async` counterpart. An instance runs one call at a time,
+//! so a task on another thread would only make the module's events and
+//! commands wait on the main thread until it's done. And since every call
+//! runs out of fuel, no task can take long enough to need a thread anyway.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    str,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, Weak}
+};
+
+use log::Level;
+use semver::Version;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use wasmi::{
+    Caller, Config, Engine, Error, Extern, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+    TrapCode, TypedFunc, WasmParams, WasmResults
+};
+
+use crate::{
+    commands::{CommandCall, CommandSender},
+    events::{BlockPosition, Player, Position},
+    logger::{self, PluginLogger},
+    scheduler::TaskId,
+    OnlinePlayer, Players,
+    Dependency, Dispatch, Event, EventKind, Plugin, PluginContext, PluginError, PluginVersion, Priority, Scheduler
+};
+
+/// The custom section a module keeps its metadata in
+pub const METADATA_SECTION: &str = "srvr-plugin";
+/// The module the server's functions are imported from
+pub const HOST_MODULE: &str = "srvr";
+/// What the files are called
+pub const WASM_EXTENSION: &str = "wasm";
+
+//Nothing a plugin says needs to be longer than this, and everything we copy
+//out of its memory is ours to allocate
+const MAX_STRING: usize = 64 * 1024;
+const MAX_REPLIES: usize = 100;
+
+//The numbers the module uses, in declaration order
+const KINDS: [EventKind; 9] = [
+    EventKind::PlayerJoin, EventKind::PlayerQuit, EventKind::Chat, EventKind::Command, EventKind::BlockPlace,
+    EventKind::BlockBreak, EventKind::PlayerMove, EventKind::WorldLoad, EventKind::ServerTick
+];
+const PRIORITIES: [Priority; 6] = [
+    Priority::Lowest, Priority::Low, Priority::Normal, Priority::High, Priority::Highest, Priority::Monitor
+];
+
+/// What every WebAssembly plugin gets to use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasmLimits {
+    /// Fuel for every call into the plugin, be it an event, a command or a
+    /// task. Most instructions cost one.
+    pub fuel: u64,
+    /// Bytes of linear memory
+    pub memory: usize
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        WasmLimits {fuel: 10_000_000, memory: 16 * 1024 * 1024}
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Metadata {
+    name: String,
+    version: String,
+    #[serde(default)]
+    min_server_version: Option<String>,
+    #[serde(default)]
+    dependencies: BTreeMap<String, String>,
+    #[serde(default)]
+    optional_dependencies: BTreeMap<String, String>
+}
+
+//Asked for during srvr_start, and added once it returns
+enum Registration {
+    Listen {kind: EventKind, priority: Priority, handler: i32},
+    Command {name: String, description: String, permission: String, handler: i32}
+}
+
+//The plugin's side of the store, which is all the server's functions can see
+struct Guest {
+    limits: StoreLimits,
+    logger: PluginLogger,
+    /// Only once the plugin started
+    scheduler: Option<Scheduler>,
+    players: Option<Players>,
+    /// For the tasks it schedules to call back into
+    runtime: Weak<Mutex<Runtime>>,
+    /// Only while srvr_start runs
+    registrations: Option<Vec<Registration>>,
+    /// Only while srvr_command runs
+    replies: Option<Vec<String>>
+}
+
+struct Runtime {
+    name: String,
+    fuel: u64,
+    store: Store<Guest>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    start: Option<TypedFunc<(), ()>>,
+    stop: Option<TypedFunc<(), ()>>,
+    event: Option<TypedFunc<(i32, i32, i32), i32>>,
+    command: Option<TypedFunc<(i32, i32, i32), ()>>,
+    task: Option<TypedFunc<i32, ()>>
+}
+
+impl Runtime {
+    //Every call into the plugin starts with a full tank
+    fn call<P: WasmParams, R: WasmResults>(&mut self, func: TypedFunc<P, R>, params: P) -> Result<R, Error> {
+        self.store.set_fuel(self.fuel)?;
+        func.call(&mut self.store, params)
+    }
+
+    //Copies a string into the plugin's memory, wherever its allocator says
+    fn pass(&mut self, s: &str) -> Result<(i32, i32), Error> {
+        let len = i32::try_from(s.len()).map_err(|_| Error::new("string does not fit in the plugin's memory"))?;
+        let ptr = self.call(self.alloc, len)?;
+        self.memory.write(&mut self.store, ptr as u32 as usize, s.as_bytes())
+            .map_err(|_| Error::new("srvr_alloc returned memory out of bounds"))?;
+        Ok((ptr, len))
+    }
+
+    fn event(&mut self, handler: i32, event: &str) -> Result<i32, Error> {
+        let func = self.event.ok_or_else(|| Error::new("module listens to events but does not export srvr_event"))?;
+        let (ptr, len) = self.pass(event)?;
+        self.call(func, (handler, ptr, len))
+    }
+
+    fn command(&mut self, handler: i32, call: &str) -> Result<Vec<String>, Error> {
+        let func = self.command.ok_or_else(|| Error::new("module adds commands but does not export srvr_command"))?;
+        let (ptr, len) = self.pass(call)?;
+        self.store.data_mut().replies = Some(Vec::new());
+        let result = self.call(func, (handler, ptr, len));
+        let replies = self.store.data_mut().replies.take().unwrap_or_default();
+        result.map(|_| replies)
+    }
+
+    fn task(&mut self, handler: i32) -> Result<(), Error> {
+        let func = self.task.ok_or_else(|| Error::new("module schedules tasks but does not export srvr_task"))?;
+        self.call(func, handler)
+    }
+
+    //What the manager logs when we pass this on as a panic
+    fn failure(&self, err: Error) -> String {
+        match err.as_trap_code() {
+            Some(TrapCode::OutOfFuel) => format!("WebAssembly plugin \"{}\" ran out of fuel ({} per call)", self.name, self.fuel),
+            _ => format!("WebAssembly plugin \"{}\" trapped: \"{err}\"", self.name)
+        }
+    }
+}
+
+//A trap leaves the module as it was, it's up to the manager whether to call
+//it again
+fn lock(runtime: &Mutex<Runtime>) -> MutexGuard<'_, Runtime> {
+    runtime.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A plugin compiled to WebAssembly, which can't touch anything but its own
+/// memory
+pub struct WasmPlugin {
+    name: String,
+    version: PluginVersion,
+    dependencies: Vec<(String, String, bool)>,
+    min_server_version: PluginVersion,
+    runtime: Arc<Mutex<Runtime>>
+}
+
+impl WasmPlugin {
+
+    pub fn load(path: &Path, limits: WasmLimits) -> Result<Self, PluginError> {
+        let bytes = fs::read(path)
+            .map_err(|err| format!("could not read module: \"{err}\""))?;
+        Self::from_bytes(&bytes, limits)
+    }
+
+    /// Compiles and instantiates the module, running its start function if
+    /// it has one
+    pub fn from_bytes(bytes: &[u8], limits: WasmLimits) -> Result<Self, PluginError> {
+        //(1) Compile the module, which also validates it
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, bytes)
+            .map_err(|err| format!("invalid WebAssembly module: \"{err}\""))?;
+
+        //(2) Read who it is
+        let section = module.custom_sections()
+            .find(|section| section.name() == METADATA_SECTION)
+            .ok_or_else(|| format!("module has no \"{METADATA_SECTION}\" section to say which plugin it is"))?;
+        let metadata: Metadata = str::from_utf8(section.data())
+            .map_err(|err| err.to_string())
+            .and_then(|metadata| toml::from_str(metadata).map_err(|err| err.to_string()))
+            .map_err(|err| format!("invalid \"{METADATA_SECTION}\" section: \"{err}\""))?;
+        let version = plugin_version(&metadata.version)?;
+        let min_server_version = match &metadata.min_server_version {
+            Some(version) => plugin_version(version)?,
+            None => PluginVersion::new((0, 0, 0))
+        };
+        let dependencies = metadata.dependencies.into_iter().map(|(name, versions)| (name, versions, false))
+            .chain(metadata.optional_dependencies.into_iter().map(|(name, versions)| (name, versions, true)))
+            .collect();
+
+        //(3) Give it a store of its own, which holds all the memory it will
+        //ever get
+        let guest = Guest {
+            limits: StoreLimitsBuilder::new().memory_size(limits.memory).instances(1).build(),
+            logger: PluginLogger::new(metadata.name.clone(), logger::host_log),
+            scheduler: None,
+            players: None,
+            runtime: Weak::new(),
+            registrations: None,
+            replies: None
+        };
+        let mut store = Store::new(&engine, guest);
+        store.limiter(|guest| &mut guest.limits);
+        store.set_fuel(limits.fuel)
+            .map_err(|err| format!("could not fuel the module: \"{err}\""))?;
+        let instance = host_functions(&engine)
+            .and_then(|linker| linker.instantiate_and_start(&mut store, &module))
+            .map_err(|err| format!("could not instantiate module: \"{err}\""))?;
+
+        //(4) Find what the server calls into
+        let memory = instance.get_memory(&store, "memory")
+            .ok_or("module does not export its \"memory\"")?;
+        let alloc = instance.get_typed_func(&store, "srvr_alloc")
+            .map_err(|err| format!("module does not export srvr_alloc(i32) -> i32: \"{err}\""))?;
+        let export = |name: &str| -> Result<Option<Extern>, PluginError> {Ok(instance.get_export(&store, name))};
+        let runtime = Runtime {
+            name: metadata.name.clone(),
+            fuel: limits.fuel,
+            alloc,
+            start: typed(&store, export("srvr_start")?, "srvr_start")?,
+            stop: typed(&store, export("srvr_stop")?, "srvr_stop")?,
+            event: typed(&store, export("srvr_event")?, "srvr_event")?,
+            command: typed(&store, export("srvr_command")?, "srvr_command")?,
+            task: typed(&store, export("srvr_task")?, "srvr_task")?,
+            memory,
+            store
+        };
+
+        //(R) The plugin, whose tasks can find their way back to it
+        let runtime = Arc::new(Mutex::new(runtime));
+        lock(&runtime).store.data_mut().runtime = Arc::downgrade(&runtime);
+        Ok(WasmPlugin {name: metadata.name, version, dependencies, min_server_version, runtime})
+    }
+}
+
+fn plugin_version(version: &str) -> Result<PluginVersion, PluginError> {
+    let version = Version::parse(version)
+        .map_err(|err| format!("invalid version \"{version}\": \"{err}\""))?;
+    Ok(PluginVersion::new((version.major as usize, version.minor as usize, version.patch as usize)))
+}
+
+//The exports the server calls are optional, but not with any signature
+fn typed<P: WasmParams, R: WasmResults>(store: &Store<Guest>, export: Option<Extern>, name: &str)
+    -> Result<Option<TypedFunc<P, R>>, PluginError>
+{
+    match export.and_then(Extern::into_func) {
+        Some(func) => func.typed(store)
+            .map(Some)
+            .map_err(|err| format!("module exports {name} with the wrong signature: \"{err}\"").into()),
+        None => Ok(None)
+    }
+}
+
+impl Plugin for WasmPlugin {
+    fn name(&self) -> &str {&self.name}
+    fn version(&self) -> &PluginVersion {&self.version}
+    fn dependencies(&self) -> Vec<Dependency<'_>> {
+        self.dependencies.iter()
+            .map(|(name, versions, optional)| match optional {
+                true => Dependency::optional(name, versions),
+                false => Dependency::required(name, versions)
+            })
+            .collect()
+    }
+    fn min_server_version(&self) -> PluginVersion {self.min_server_version}
+
+    fn start(&mut self, ctx: &mut PluginContext) {
+        //(1) Let the module say what it wants to hear about
+        let registrations = {
+            let mut runtime = lock(&self.runtime);
+            let guest = runtime.store.data_mut();
+            guest.scheduler = Some(ctx.scheduler());
+            guest.players = Some(ctx.players());
+            guest.registrations = Some(Vec::new());
+            let result = match runtime.start {
+                Some(start) => runtime.call(start, ()),
+                None => Ok(())
+            };
+            let registrations = runtime.store.data_mut().registrations.take().unwrap_or_default();
+            if let Err(err) = result {
+                //A plugin that failed to start is never stopped, so this is
+                //our only chance to let go of its scheduler
+                runtime.store.data_mut().scheduler = None;
+                panic!("{}", runtime.failure(err));
+            }
+            registrations
+        };
+
+        //(2) Hook it up, every one of these calls back into the module. A
+        //trap is a panic, which removes the listener or fails the command.
+        for registration in registrations {
+            let runtime = self.runtime.clone();
+            match registration {
+                Registration::Listen{kind, priority, handler} => {
+                    ctx.listen(kind, priority, move |dispatch| on_event(&runtime, handler, dispatch));
+                },
+                Registration::Command{name, description, permission, handler} => {
+                    let permission = (!permission.is_empty()).then_some(permission.as_str());
+                    ctx.command(&name, &description, permission, move |call| on_command(&runtime, handler, call));
+                }
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        let mut runtime = lock(&self.runtime);
+        let result = match runtime.stop {
+            Some(stop) => runtime.call(stop, ()),
+            None => Ok(())
+        };
+        runtime.store.data_mut().scheduler = None;
+        if let Err(err) = result {
+            panic!("{}", runtime.failure(err));
+        }
+    }
+}
+
+fn on_event(runtime: &Mutex<Runtime>, handler: i32, dispatch: &mut Dispatch) {
+    let event = event_json(dispatch.event(), dispatch.is_cancelled());
+    let mut runtime = lock(runtime);
+    match runtime.event(handler, &event) {
+        Ok(1) => dispatch.set_cancelled(true),
+        Ok(2) => dispatch.set_cancelled(false),
+        Ok(_) => {},
+        Err(err) => panic!("{}", runtime.failure(err))
+    }
+}
+
+fn on_command(runtime: &Mutex<Runtime>, handler: i32, call: &CommandCall) {
+    let player = match call.sender() {
+        CommandSender::Player(player) => Value::String(uuid(player.uuid)),
+        _ => Value::Null
+    };
+    let call_json = json!({"sender": call.sender().name(), "player": player, "args": call.args()}).to_string();
+    let mut runtime = lock(runtime);
+    match runtime.command(handler, &call_json) {
+        Ok(replies) => replies.iter().for_each(|reply| call.reply(reply)),
+        Err(err) => panic!("{}", runtime.failure(err))
+    }
+}
+
+//Tasks don't keep the plugin alive, it may have been reloaded since
+fn on_task(runtime: &Weak<Mutex<Runtime>>, handler: i32) {
+    if let Some(runtime) = runtime.upgrade() {
+        let mut runtime = lock(&runtime);
+        if let Err(err) = runtime.task(handler) {
+            panic!("{}", runtime.failure(err));
+        }
+    }
+}
+
+fn uuid(uuid: u128) -> String {
+    format!("{uuid:032x}")
+}
+
+fn event_json(event: &Event, cancelled: bool) -> String {
+    let player = |player: &Player| json!({"name": player.name.as_str(), "uuid": uuid(player.uuid)});
+    let position = |position: &Position| json!({"x": position.x, "y": position.y, "z": position.z});
+    let block = |block: &BlockPosition| json!({"x": block.x, "y": block.y, "z": block.z});
+    let mut json = match event {
+        Event::PlayerJoin{player: p} | Event::PlayerQuit{player: p} => json!({"player": player(p)}),
+        Event::Chat{player: p, message} => json!({"player": player(p), "message": message.as_str()}),
+        Event::Command{sender, line} => json!({"sender": sender.as_str(), "line": line.as_str()}),
+        Event::BlockPlace{player: p, position, face} => json!({"player": player(p), "position": block(position), "face": face}),
+        Event::BlockBreak{player: p, position} => json!({"player": player(p), "position": block(position)}),
+        Event::PlayerMove{player: p, from, to} => json!({"player": player(p), "from": position(from), "to": position(to)}),
+        Event::WorldLoad{world} => json!({"world": world.as_str()}),
+        Event::ServerTick{tick} => json!({"tick": tick})
+    };
+    json["kind"] = json!(format!("{:?}", event.kind()));
+    json["cancelled"] = json!(cancelled);
+    json.to_string()
+}
+
+//Copies a string out of the plugin's memory. Whatever the plugin passes, the
+//worst it gets is a trap.
+fn read(caller: &Caller<'_, Guest>, ptr: i32, len: i32) -> Result<String, Error> {
+    let (ptr, len) = (ptr as u32 as usize, len as u32 as usize);
+    if len > MAX_STRING {
+        return Err(Error::new(format!("string of {len} bytes is longer than {MAX_STRING}")));
+    }
+    let bytes = memory(caller)?.data(caller).get(ptr..ptr + len)
+        .ok_or_else(|| Error::new(format!("string at {ptr}..{} is out of bounds", ptr + len)))?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| Error::new("string is not UTF-8"))
+}
+
+fn write(caller: &mut Caller<'_, Guest>, ptr: i32, bytes: &[u8]) -> Result<(), Error> {
+    let ptr = ptr as u32 as usize;
+    memory(caller)?.write(caller, ptr, bytes)
+        .map_err(|_| Error::new(format!("buffer at {ptr}..{} is out of bounds", ptr + bytes.len())))
+}
+
+fn memory(caller: &Caller<'_, Guest>) -> Result<Memory, Error> {
+    caller.get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Error::new("module does not export its \"memory\""))
+}
+
+fn players_json(players: &[OnlinePlayer]) -> String {
+    let players: Vec<Value> = players.iter()
+        .map(|player| json!({
+            "name": player.name,
+            "uuid": uuid(player.uuid),
+            "ping": player.ping.as_millis() as u64,
+            "world": player.world
+        }))
+        .collect();
+    Value::Array(players).to_string()
+}
+
+fn host_functions(engine: &Engine) -> Result<Linker<Guest>, Error> {
+    let mut linker = Linker::new(engine);
+
+    linker.func_wrap(HOST_MODULE, "log", |caller: Caller<'_, Guest>, level: i32, ptr: i32, len: i32| {
+        let message = read(&caller, ptr, len)?;
+        let level = match level {
+            ..=1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace
+        };
+        caller.data().logger.log(level, message);
+        Ok(())
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "listen", |mut caller: Caller<'_, Guest>, kind: i32, priority: i32, handler: i32| {
+        let kind = *KINDS.get(kind as u32 as usize)
+            .ok_or_else(|| Error::new(format!("there is no event kind {kind}")))?;
+        let priority = *PRIORITIES.get(priority as u32 as usize)
+            .ok_or_else(|| Error::new(format!("there is no priority {priority}")))?;
+        match caller.data_mut().registrations.as_mut() {
+            Some(registrations) => registrations.push(Registration::Listen{kind, priority, handler}),
+            None => return Err(Error::new("listeners can only be added from srvr_start"))
+        }
+        Ok(())
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "command", |
+        mut caller: Caller<'_, Guest>,
+        name_ptr: i32, name_len: i32,
+        description_ptr: i32, description_len: i32,
+        permission_ptr: i32, permission_len: i32,
+        handler: i32
+    | {
+        let name = read(&caller, name_ptr, name_len)?;
+        let description = read(&caller, description_ptr, description_len)?;
+        let permission = read(&caller, permission_ptr, permission_len)?;
+        match caller.data_mut().registrations.as_mut() {
+            Some(registrations) => registrations.push(Registration::Command{name, description, permission, handler}),
+            None => return Err(Error::new("commands can only be added from srvr_start"))
+        }
+        Ok(())
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "schedule", |caller: Caller<'_, Guest>, delay: i64, period: i64, handler: i32| {
+        let guest = caller.data();
+        let scheduler = guest.scheduler.as_ref()
+            .ok_or_else(|| Error::new("tasks can only be scheduled once the plugin started"))?;
+        let runtime = guest.runtime.clone();
+        let (delay, period) = (delay.max(0) as u64, period.max(0) as u64);
+        let id = match period {
+            0 => scheduler.run_later(delay, move || on_task(&runtime, handler)),
+            period => scheduler.run_repeating(delay, period, move || on_task(&runtime, handler))
+        };
+        Ok(id.map_or(-1, |id| id.0 as i64))
+    })?;
+
+    //The scheduler only lets a plugin cancel its own tasks
+    linker.func_wrap(HOST_MODULE, "cancel", |caller: Caller<'_, Guest>, task: i64| {
+        if let Some(scheduler) = &caller.data().scheduler {
+            scheduler.cancel(TaskId(task as u64));
+        }
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "reply", |mut caller: Caller<'_, Guest>, ptr: i32, len: i32| {
+        let line = read(&caller, ptr, len)?;
+        match caller.data_mut().replies.as_mut() {
+            Some(replies) if replies.len() < MAX_REPLIES => replies.push(line),
+            Some(_) => return Err(Error::new(format!("a command can reply at most {MAX_REPLIES} lines"))),
+            None => return Err(Error::new("replies can only be sent from srvr_command"))
+        }
+        Ok(())
+    })?;
+
+    linker.func_wrap(HOST_MODULE, "players", |mut caller: Caller<'_, Guest>, ptr: i32, len: i32| {
+        let players = caller.data().players.as_ref()
+            .ok_or_else(|| Error::new("players can only be listed once the plugin started"))?
+            .online();
+        let json = players_json(&players);
+        if json.len() <= len.max(0) as usize {
+            write(&mut caller, ptr, json.as_bytes())?;
+        }
+        i32::try_from(json.len()).map_err(|_| Error::new("too many players to list"))
+    })?;
+
+    Ok(linker)
+}
+
+#[cfg(test)]
+mod wasm_test {
+    use std::{panic::{self, AssertUnwindSafe}, time::Duration};
+
+    use crate::{context::Host, events::EventBus, ffi::FfiStr, CommandMap, PlayerList, TaskScheduler};
+
+    use super::*;
+
+    //Listens to chat, answers "ping" and runs a task one tick after starting.
+    //Chat is cancelled once the task ran.
+    const PLUGIN: &str = r#"(module
+        (@custom "srvr-plugin" "name = \"wasm-test\"\nversion = \"1.2.0\"\n[dependencies]\ndatabase = \"^1.2\"\n[optional_dependencies]\neconomy = \"*\"")
+        (import "srvr" "log" (func $log (param i32 i32 i32)))
+        (import "srvr" "listen" (func $listen (param i32 i32 i32)))
+        (import "srvr" "command" (func $command (param i32 i32 i32 i32 i32 i32 i32)))
+        (import "srvr" "schedule" (func $schedule (param i64 i64 i32) (result i64)))
+        (import "srvr" "reply" (func $reply (param i32 i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 0) "ping")
+        (data (i32.const 16) "Answers")
+        (data (i32.const 32) "pong")
+        (data (i32.const 48) "task ran")
+        (global $next (mut i32) (i32.const 1024))
+        (global $ran (mut i32) (i32.const 0))
+        (func (export "srvr_alloc") (param $len i32) (result i32)
+            (global.get $next)
+            (global.set $next (i32.add (global.get $next) (local.get $len))))
+        (func (export "srvr_start")
+            (call $listen (i32.const 2) (i32.const 2) (i32.const 7))
+            (call $command (i32.const 0) (i32.const 4) (i32.const 16) (i32.const 7) (i32.const 0) (i32.const 0) (i32.const 1))
+            (drop (call $schedule (i64.const 1) (i64.const 0) (i32.const 3))))
+        (func (export "srvr_event") (param $handler i32) (param $ptr i32) (param $len i32) (result i32)
+            (global.get $ran))
+        (func (export "srvr_command") (param $handler i32) (param $ptr i32) (param $len i32)
+            (call $reply (i32.const 32) (i32.const 4)))
+        (func (export "srvr_task") (param $handler i32)
+            (call $log (i32.const 3) (i32.const 48) (i32.const 8))
+            (global.set $ran (i32.const 1))))"#;
+
+    //The smallest module that is a plugin, with `body` added
+    fn minimal(body: &str) -> String {
+        format!(r#"(module
+            (@custom "srvr-plugin" "name = \"wasm-test\"\nversion = \"1.0.0\"")
+            {body}
+            (memory (export "memory") 1)
+            (func (export "srvr_alloc") (param i32) (result i32) (i32.const 1024)))"#)
+    }
+
+    fn chat(message: &str) -> Event<'_> {
+        Event::Chat {player: Player {name: FfiStr::new("jeb_"), uuid: 1}, message: FfiStr::new(message)}
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut plugin = WasmPlugin::from_bytes(PLUGIN.as_bytes(), WasmLimits::default()).unwrap();
+        assert_eq!(plugin.name(), "wasm-test");
+        assert_eq!(plugin.version(), &PluginVersion::new((1, 2, 0)));
+        assert_eq!(format!("{:?}", plugin.dependencies()), "[database ^1.2, economy * (optional)]");
+
+        let (events, commands, scheduler) = (EventBus::new(), CommandMap::new(), Arc::new(TaskScheduler::new()));
+        let players = Arc::new(PlayerList::new());
+        scheduler.activate("wasm-test");
+        let host = Host::new(&events, &commands, &scheduler, &players, "wasm-test", "plugins/wasm-test");
+        let api = host.api();
+        plugin.start(&mut PluginContext::new(&api));
+        assert!(!events.dispatch(chat("hello")));
+        assert_eq!(commands.execute(CommandSender::Console, "ping", |_| true), Ok(vec!["pong".to_string()]));
+        assert_eq!(scheduler.len(), 1);
+        scheduler.tick();
+        scheduler.tick();
+        assert!(scheduler.is_empty());
+        assert!(events.dispatch(chat("hello")));
+
+        plugin.stop();
+        events.unregister("wasm-test");
+        commands.unregister("wasm-test");
+        scheduler.deactivate("wasm-test");
+    }
+
+    #[test]
+    fn players() {
+        //Answers "who" with the list of players, written straight into a reply
+        let module = minimal(r#"
+            (import "srvr" "command" (func $command (param i32 i32 i32 i32 i32 i32 i32)))
+            (import "srvr" "players" (func $players (param i32 i32) (result i32)))
+            (import "srvr" "reply" (func $reply (param i32 i32)))
+            (data (i32.const 0) "who")
+            (func (export "srvr_start")
+                (call $command (i32.const 0) (i32.const 3) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)))
+            (func (export "srvr_command") (param i32 i32 i32)
+                (call $reply (i32.const 256) (call $players (i32.const 256) (i32.const 512))))"#);
+        let mut plugin = WasmPlugin::from_bytes(module.as_bytes(), WasmLimits::default()).unwrap();
+        let (events, commands, scheduler) = (EventBus::new(), CommandMap::new(), Arc::new(TaskScheduler::new()));
+        let players = Arc::new(PlayerList::new());
+        let host = Host::new(&events, &commands, &scheduler, &players, "wasm-test", "plugins/wasm-test");
+        let api = host.api();
+        plugin.start(&mut PluginContext::new(&api));
+        assert_eq!(commands.execute(CommandSender::Console, "who", |_| true), Ok(vec!["[]".to_string()]));
+
+        players.set(vec![OnlinePlayer {name: "jeb_".to_string(), uuid: 1, ping: Duration::from_millis(42), world: "nether".to_string()}]);
+        let expected = r#"[{"name":"jeb_","ping":42,"uuid":"00000000000000000000000000000001","world":"nether"}]"#;
+        assert_eq!(commands.execute(CommandSender::Console, "who", |_| true), Ok(vec![expected.to_string()]));
+        plugin.stop();
+        commands.unregister("wasm-test");
+    }
+
+    #[test]
+    fn out_of_fuel() {
+        //Loops forever on every event, which costs it the listener
+        let module = minimal(r#"
+            (import "srvr" "listen" (func $listen (param i32 i32 i32)))
+            (func (export "srvr_start") (call $listen (i32.const 2) (i32.const 2) (i32.const 0)))
+            (func (export "srvr_event") (param i32 i32 i32) (result i32) (loop $forever (br $forever)) (i32.const 0))"#);
+        let limits = WasmLimits {fuel: 10_000, ..WasmLimits::default()};
+        let mut plugin = WasmPlugin::from_bytes(module.as_bytes(), limits).unwrap();
+        let (events, commands, scheduler) = (EventBus::new(), CommandMap::new(), Arc::new(TaskScheduler::new()));
+        let players = Arc::new(PlayerList::new());
+        let host = Host::new(&events, &commands, &scheduler, &players, "wasm-test", "plugins/wasm-test");
+        let api = host.api();
+        plugin.start(&mut PluginContext::new(&api));
+        assert_eq!(events.owned_by("wasm-test"), 1);
+        assert!(!events.dispatch(chat("hello")));
+        assert_eq!(events.owned_by("wasm-test"), 0);
+    }
+
+    #[test]
+    fn memory_is_isolated() {
+        //Pointing the server outside its memory only traps the plugin
+        let module = minimal(r#"
+            (import "srvr" "log" (func $log (param i32 i32 i32)))
+            (func (export "srvr_start") (call $log (i32.const 3) (i32.const 65530) (i32.const 100)))"#);
+        let mut plugin = WasmPlugin::from_bytes(module.as_bytes(), WasmLimits::default()).unwrap();
+        let (events, commands, scheduler) = (EventBus::new(), CommandMap::new(), Arc::new(TaskScheduler::new()));
+        let players = Arc::new(PlayerList::new());
+        let host = Host::new(&events, &commands, &scheduler, &players, "wasm-test", "plugins/wasm-test");
+        let api = host.api();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| plugin.start(&mut PluginContext::new(&api))));
+        assert!(result.is_err());
+
+        //And it can't have more memory than it's given
+        let module = minimal("(func (export \"srvr_start\") (drop (memory.grow (i32.const 1000))))")
+            .replace("(memory (export \"memory\") 1)", "(memory (export \"memory\") 1000)");
+        let err = WasmPlugin::from_bytes(module.as_bytes(), WasmLimits::default()).err().unwrap();
+        assert!(err.to_string().contains("could not instantiate"));
+    }
+
+    #[test]
+    fn rejected_modules() {
+        let err = |module: &str| WasmPlugin::from_bytes(module.as_bytes(), WasmLimits::default()).err().unwrap().to_string();
+        assert!(err("(module (memory (export \"memory\") 1))").contains("no \"srvr-plugin\" section"));
+        assert!(err(&minimal("").replace("1.0.0", "one")).contains("invalid version"));
+        assert!(err(&minimal("(import \"env\" \"exit\" (func))")).contains("could not instantiate"));
+        assert!(err(&minimal("(func (export \"srvr_task\"))")).contains("wrong signature"));
+    }
+
+}
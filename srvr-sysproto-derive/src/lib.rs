@@ -0,0 +1,349 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+  parse_macro_input, spanned::Spanned,
+  Data, DeriveInput, Error, Fields, GenericArgument, Ident, LitInt, PathArguments, Type
+};
+
+/*
+  Derive macros for the Packet and MCDataType traits of srvr-sysproto. Fields
+  are encoded in declaration order. A field without attributes uses the MCField
+  impl of its type, the #[mc(...)] attribute changes that:
+    #[mc(varint)] / #[mc(varlong)]  integer encoded as a VarInt/VarLong
+    #[mc(nbt)]                      NBT tag
+    #[mc(with = T)]                 encoded as the MCDataType T, using From
+    #[mc(array, ..)]                Vec<_> prefixed with its VarInt length
+    #[mc(option, ..)]               Option<_> prefixed with a boolean
+    #[mc(rest)]                     Vec<u8> with all remaining bytes (last field)
+  The array and option modifiers may be combined with one of the encodings
+  above, which is then used for the elements.
+*/
+
+#[proc_macro_derive(Packet, attributes(packet, mc))]
+pub fn derive_packet(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  expand_packet(input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+#[proc_macro_derive(MCDataType, attributes(mc))]
+pub fn derive_mc_data_type(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  expand_mc_data_type(input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+fn expand_packet(input: DeriveInput) -> syn::Result<TokenStream2> {
+  //(1) Find the packet ID and connection state
+  let mut id = None;
+  let mut state = None;
+  for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("packet")) {
+    attr.parse_nested_meta(|meta| {
+      if meta.path.is_ident("id") {
+        id = Some(meta.value()?.parse::<LitInt>()?);
+      } else if meta.path.is_ident("state") {
+        state = Some(meta.value()?.parse::<Ident>()?);
+      } else {
+        return Err(meta.error("expected `id` or `state`"));
+      }
+      Ok(())
+    })?;
+  }
+  let missing = |what| Error::new(input.ident.span(), format!("missing #[packet({what} = ...)]"));
+  let id = id.ok_or_else(|| missing("id"))?;
+  let state = state.ok_or_else(|| missing("state"))?;
+
+  //(2) Generate the field-by-field decode and encode
+  let (decode, encode) = expand_fields(&input)?;
+  let name = &input.ident;
+  let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+  Ok(quote! {
+    impl #impl_generics ::srvr_sysproto::packets::Packet for #name #ty_generics #where_clause {
+      const PACKET_ID: usize = #id;
+      const STATE: ::srvr_sysproto::packets::ConnectionState =
+        ::srvr_sysproto::packets::ConnectionState::#state;
+
+      fn decode(buf: &mut ::srvr_sysproto::raw_packet::RawPacketReader)
+        -> ::std::result::Result<Self, ::std::boxed::Box<dyn ::std::error::Error>>
+      {
+        ::std::result::Result::Ok(#decode)
+      }
+
+      fn encode(&self, buf: &mut ::srvr_sysproto::raw_packet::RawPacketWriter) {
+        #encode
+      }
+    }
+  })
+}
+
+fn expand_mc_data_type(input: DeriveInput) -> syn::Result<TokenStream2> {
+  let (decode, encode) = expand_fields(&input)?;
+  let name = &input.ident;
+  let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+  Ok(quote! {
+    impl #impl_generics ::srvr_sysproto::mc_dtypes::MCDataType for #name #ty_generics #where_clause {
+      fn decode(buf: &mut ::srvr_sysproto::raw_packet::RawPacketReader)
+        -> ::std::result::Result<Self, ::srvr_sysproto::mc_dtypes::MCDataTypeDecodeError>
+      {
+        ::std::result::Result::Ok(#decode)
+      }
+
+      fn encode(&self, buf: &mut ::srvr_sysproto::raw_packet::RawPacketWriter) {
+        #encode
+      }
+    }
+  })
+}
+
+/*
+  Field encodings
+*/
+enum Codec {
+  Default,
+  VarInt,
+  VarLong,
+  Nbt,
+  With(Box<Type>)
+}
+
+enum Container {
+  Plain,
+  Array,
+  Option,
+  Rest
+}
+
+fn parse_field_attrs(field: &syn::Field) -> syn::Result<(Container, Codec)> {
+  let mut container = Container::Plain;
+  let mut codec = Codec::Default;
+  for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("mc")) {
+    attr.parse_nested_meta(|meta| {
+      let path = &meta.path;
+      if path.is_ident("array") {
+        container = Container::Array;
+      } else if path.is_ident("option") {
+        container = Container::Option;
+      } else if path.is_ident("rest") {
+        container = Container::Rest;
+      } else if path.is_ident("varint") {
+        codec = Codec::VarInt;
+      } else if path.is_ident("varlong") {
+        codec = Codec::VarLong;
+      } else if path.is_ident("nbt") {
+        codec = Codec::Nbt;
+      } else if path.is_ident("with") {
+        codec = Codec::With(meta.value()?.parse()?);
+      } else {
+        return Err(meta.error("unknown mc attribute"));
+      }
+      Ok(())
+    })?;
+  }
+  if matches!(container, Container::Rest) && !matches!(codec, Codec::Default) {
+    return Err(Error::new(field.span(), "#[mc(rest)] can't be combined with an encoding"));
+  }
+  Ok((container, codec))
+}
+
+fn expand_fields(input: &DeriveInput) -> syn::Result<(TokenStream2, TokenStream2)> {
+  let fields = match &input.data {
+    Data::Struct(data) => &data.fields,
+    _ => return Err(Error::new(input.ident.span(), "can only be derived for structs"))
+  };
+
+  let mut decoders = Vec::new();
+  let mut encoders = Vec::new();
+  let mut names = Vec::new();
+  for (index, field) in fields.iter().enumerate() {
+    let (container, codec) = parse_field_attrs(field)?;
+    if matches!(container, Container::Rest) && index + 1 != fields.len() {
+      return Err(Error::new(field.span(), "#[mc(rest)] must be the last field"));
+    }
+
+    //Named fields are accessed by name, tuple fields by index
+    let member = match &field.ident {
+      Some(ident) => quote!(#ident),
+      None => {
+        let index = syn::Index::from(index);
+        quote!(#index)
+      }
+    };
+    decoders.push(decode_field(&container, &codec, &field.ty)?);
+    encoders.push(encode_field(&container, &codec, &field.ty, quote!(&self.#member))?);
+    names.push(member);
+  }
+
+  //Struct expressions evaluate their fields in the order they are written,
+  //which is exactly the order in which they appear on the wire
+  let decode = match fields {
+    Fields::Named(_) => quote!(Self { #(#names: #decoders),* }),
+    Fields::Unnamed(_) => quote!(Self ( #(#decoders),* )),
+    Fields::Unit => quote!(Self)
+  };
+  let encode = quote!(#(#encoders;)*);
+  Ok((decode, encode))
+}
+
+fn decode_field(container: &Container, codec: &Codec, ty: &Type) -> syn::Result<TokenStream2> {
+  Ok(match container {
+    Container::Plain => decode_value(codec, ty),
+    Container::Option => {
+      let inner = decode_value(codec, inner_type(ty, "Option")?);
+      quote!({
+        if <bool as ::srvr_sysproto::mc_dtypes::MCField>::decode_field(buf)? {
+          ::std::option::Option::Some(#inner)
+        } else {
+          ::std::option::Option::None
+        }
+      })
+    },
+    Container::Array => {
+      let elem_ty = inner_type(ty, "Vec")?;
+      let len = quote!(::srvr_sysproto::mc_dtypes::decode_array_len(buf)?);
+      if is_byte(elem_ty) && matches!(codec, Codec::Default) {
+        //Byte arrays are copied in one go
+        quote!({
+          let len = #len;
+          buf.read_bytes(len)
+        })
+      } else {
+        let inner = decode_value(codec, elem_ty);
+        quote!({
+          let len = #len;
+          let mut items = ::std::vec::Vec::with_capacity(len);
+          for _ in 0..len {
+            items.push(#inner);
+          }
+          items
+        })
+      }
+    },
+    Container::Rest => quote!({
+      let len = buf.remaining();
+      buf.read_bytes(len)
+    })
+  })
+}
+
+fn encode_field(container: &Container, codec: &Codec, ty: &Type, value: TokenStream2)
+  -> syn::Result<TokenStream2>
+{
+  Ok(match container {
+    Container::Plain => encode_value(codec, ty, value),
+    Container::Option => {
+      let inner = encode_value(codec, inner_type(ty, "Option")?, quote!(item));
+      quote!({
+        let value = #value;
+        ::srvr_sysproto::mc_dtypes::MCField::encode_field(&value.is_some(), buf);
+        if let ::std::option::Option::Some(item) = value {
+          #inner;
+        }
+      })
+    },
+    Container::Array => {
+      let elem_ty = inner_type(ty, "Vec")?;
+      let len = quote!(::srvr_sysproto::mc_dtypes::encode_array_len(value.len(), buf));
+      if is_byte(elem_ty) && matches!(codec, Codec::Default) {
+        quote!({
+          let value = #value;
+          #len;
+          buf.write_bytes(value);
+        })
+      } else {
+        let inner = encode_value(codec, elem_ty, quote!(item));
+        quote!({
+          let value = #value;
+          #len;
+          for item in value.iter() {
+            #inner;
+          }
+        })
+      }
+    },
+    Container::Rest => quote!(buf.write_bytes(#value))
+  })
+}
+
+fn decode_value(codec: &Codec, ty: &Type) -> TokenStream2 {
+  let dtypes = quote!(::srvr_sysproto::mc_dtypes);
+  match codec {
+    Codec::Default => quote!(<#ty as #dtypes::MCField>::decode_field(buf)?),
+    Codec::VarInt => quote!(
+      (i32::from(<#dtypes::MCVarInt as #dtypes::MCDataType>::decode(buf)?) as #ty)
+    ),
+    Codec::VarLong => quote!(
+      (i64::from(<#dtypes::MCVarLong as #dtypes::MCDataType>::decode(buf)?) as #ty)
+    ),
+    Codec::Nbt => quote!(<#dtypes::MCNbt as #dtypes::MCDataType>::decode(buf)?),
+    Codec::With(wire) => quote!(
+      <#ty as ::std::convert::From<#wire>>::from(<#wire as #dtypes::MCDataType>::decode(buf)?)
+    )
+  }
+}
+
+fn encode_value(codec: &Codec, ty: &Type, value: TokenStream2) -> TokenStream2 {
+  let dtypes = quote!(::srvr_sysproto::mc_dtypes);
+  match codec {
+    Codec::Default => quote!(<#ty as #dtypes::MCField>::encode_field(#value, buf)),
+    Codec::VarInt => quote!(
+      #dtypes::MCDataType::encode(&#dtypes::MCVarInt::from(*#value as i32), buf)
+    ),
+    Codec::VarLong => quote!(
+      #dtypes::MCDataType::encode(&#dtypes::MCVarLong::from(*#value as i64), buf)
+    ),
+    Codec::Nbt => quote!(#dtypes::MCDataType::encode(#value, buf)),
+    Codec::With(wire) => quote!(
+      #dtypes::MCDataType::encode(
+        &<#wire as ::std::convert::From<#ty>>::from(::std::clone::Clone::clone(#value)), buf
+      )
+    )
+  }
+}
+
+/*
+  Helpers for looking at field types
+*/
+fn inner_type<'a>(ty: &'a Type, container: &str) -> syn::Result<&'a Type> {
+  //Vec<T> -> T and Option<T> -> T, as far as we can tell from the syntax
+  if let Type::Path(path) = ty {
+    if let Some(segment) = path.path.segments.last() {
+      if segment.ident == container {
+        if let PathArguments::AngleBracketed(args) = &segment.arguments {
+          if let Some(GenericArgument::Type(inner)) = args.args.first() {
+            return Ok(inner);
+          }
+        }
+      }
+    }
+  }
+  Err(Error::new(ty.span(), format!("expected a {container}<_> field")))
+}
+
+fn is_byte(ty: &Type) -> bool {
+  matches!(ty, Type::Path(path) if path.path.is_ident("u8"))
+}
@@ -0,0 +1,140 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use aes::Aes128;
+use cfb8::{Decryptor, Encryptor, cipher::KeyIvInit};
+use sha1::{Digest, Sha1};
+
+use crate::raw_packet::RawPacketError;
+
+type Aes128Cfb8Enc = Encryptor<Aes128>;
+type Aes128Cfb8Dec = Decryptor<Aes128>;
+
+pub struct PacketCipher {
+  /*
+    Once encryption is enabled, every byte sent over the connection in either
+    direction is run through AES-128 in CFB8 mode. Both the key and the IV are
+    the shared secret that the client sent us. CFB8 works on one byte at a
+    time, so the cipher state simply carries over between reads and writes.
+  */
+  encryptor: Aes128Cfb8Enc,
+  decryptor: Aes128Cfb8Dec
+}
+
+impl PacketCipher {
+
+  pub fn new(shared_secret: &[u8]) -> Result<Self, RawPacketError> {
+    let invalid = |_| RawPacketError::BadEncryption(format!(
+      "shared secret must be 16 bytes long, got {}", shared_secret.len()
+    ));
+    Ok(PacketCipher {
+      encryptor: Aes128Cfb8Enc::new_from_slices(shared_secret, shared_secret)
+        .map_err(invalid)?,
+      decryptor: Aes128Cfb8Dec::new_from_slices(shared_secret, shared_secret)
+        .map_err(invalid)?
+    })
+  }
+
+  pub fn encrypt(&mut self, buf: &mut [u8]) {self.encryptor.encrypt(buf)}
+  pub fn decrypt(&mut self, buf: &mut [u8]) {self.decryptor.decrypt(buf)}
+
+}
+
+impl std::fmt::Debug for PacketCipher {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    //Don't leak the key into the logs
+    f.write_str("PacketCipher { .. }")
+  }
+}
+
+pub fn server_hash(server_id: &str, shared_secret: &[u8], public_key: &[u8]) -> String {
+  /*
+    The session server expects Minecraft's peculiar variant of a SHA-1 hex
+    digest: the digest is interpreted as a signed (two's complement) 160-bit
+    integer, which is then printed in hex without leading zeroes and with a
+    minus sign if it was negative.
+  */
+  let mut hasher = Sha1::new();
+  hasher.update(server_id.as_bytes());
+  hasher.update(shared_secret);
+  hasher.update(public_key);
+  let mut digest: [u8; 20] = hasher.finalize().into();
+
+  //(1) Negative numbers are printed as the magnitude of their two's complement
+  let negative = digest[0] & 0x80 != 0;
+  if negative {
+    let mut carry = true;
+    for byte in digest.iter_mut().rev() {
+      *byte = !*byte;
+      if carry {
+        let (sum, overflow) = byte.overflowing_add(1);
+        *byte = sum;
+        carry = overflow;
+      }
+    }
+  }
+
+  //(2) Print as hex and strip the leading zeroes
+  let hex: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+  let hex = hex.trim_start_matches('0');
+  match negative {
+    true => format!("-{hex}"),
+    false => hex.to_string()
+  }
+}
+
+#[cfg(test)]
+mod encryption_test {
+
+  use super::{PacketCipher, server_hash};
+
+  #[test]
+  fn server_hash_test() {
+    //Reference values from the protocol documentation
+    assert_eq!(server_hash("Notch", &[], &[]), "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48");
+    assert_eq!(server_hash("jeb_", &[], &[]), "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1");
+    assert_eq!(server_hash("simon", &[], &[]), "88e16a1019277b15d58faf0541e11910eb756f6");
+  }
+
+  #[test]
+  fn stream_cipher_test() {
+    //Encrypting in pieces must give the same result as encrypting in one go
+    let secret = [0x42u8; 16];
+    let plaintext = b"hello world! this is my plaintext.".to_vec();
+
+    let mut whole = plaintext.clone();
+    PacketCipher::new(&secret).unwrap().encrypt(&mut whole);
+
+    let mut pieces = plaintext.clone();
+    let mut cipher = PacketCipher::new(&secret).unwrap();
+    let (first, second) = pieces.split_at_mut(7);
+    cipher.encrypt(first);
+    cipher.encrypt(second);
+    assert_eq!(whole, pieces);
+
+    PacketCipher::new(&secret).unwrap().decrypt(&mut pieces);
+    assert_eq!(pieces, plaintext);
+  }
+
+}
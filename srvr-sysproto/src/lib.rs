@@ -22,11 +22,16 @@
   text of the license in any official language of the European Union.
 */
 
+//Lets the derive macros refer to this crate by name, also from within it
+extern crate self as srvr_sysproto;
+
 pub const PROTOCOL_VERSION: usize = 758;
+pub const VERSION_NAME: &str = "1.18.2";
 
 /*
   This file contains
 */
 pub mod raw_packet;
 pub mod mc_dtypes;
-pub mod packets;
\ No newline at end of file
+pub mod packets;
+pub mod encryption;
\ No newline at end of file
@@ -74,6 +74,53 @@ impl Display for MCDataTypeDecodeError {
 
 impl Error for MCDataTypeDecodeError{}
 
+/*
+  Rust types with a natural wire format. The derive macros encode every field
+  without an #[mc(...)] attribute through this trait
+*/
+pub trait MCField {
+  fn decode_field(buf: &mut RawPacketReader) -> Result<Self, Err> where Self: Sized;
+  fn encode_field(&self, buf: &mut RawPacketWriter);
+}
+
+impl<T: MCDataType> MCField for T {
+  fn decode_field(buf: &mut RawPacketReader) -> Result<Self, Err> {T::decode(buf)}
+  fn encode_field(&self, buf: &mut RawPacketWriter) {self.encode(buf)}
+}
+
+macro_rules! mc_field {
+  ($($rust_type:ty => $mc_type:ty),*) => {$(
+    impl MCField for $rust_type {
+      fn decode_field(buf: &mut RawPacketReader) -> Result<Self, Err> {
+        Ok(<$mc_type>::decode(buf)?.into())
+      }
+      fn encode_field(&self, buf: &mut RawPacketWriter) {
+        <$mc_type>::from(self.clone()).encode(buf)
+      }
+    }
+  )*};
+}
+
+mc_field!(
+  bool => MCBool, i8 => MCByte, u8 => MCUByte, i16 => MCShort, u16 => MCUShort,
+  i32 => MCInt, i64 => MCLong, f32 => MCFloat, f64 => MCDouble,
+  String => MCString, u128 => MCUuid
+);
+
+//Length prefix of arrays in derived types. Every element takes up at least one
+//byte, so a length larger than what's left of the packet is always invalid
+pub fn decode_array_len(buf: &mut RawPacketReader) -> Result<usize, Err> {
+  let len: i32 = MCVarInt::decode(buf)?.into();
+  if len < 0 || len as usize > buf.remaining() {
+    return Err(MCDataTypeDecodeError(format!("invalid array length {len}")));
+  }
+  Ok(len as usize)
+}
+
+pub fn encode_array_len(len: usize, buf: &mut RawPacketWriter) {
+  MCVarInt::from(len as i32).encode(buf);
+}
+
 /*
   Last step is to re-export all MC datatypes under this namespace
 */
@@ -102,6 +149,9 @@ pub use mc_uuid::MCUuid as MCUuid;
 pub use mc_position::MCPosition as MCPosition;
 pub use mc_nbt::NbtTag as MCNbt;
 
+//Derive macro for composite data types
+pub use srvr_sysproto_derive::MCDataType;
+
 /*
   Useful macros for testing
 */
@@ -124,4 +174,89 @@ macro_rules! correctness_test {
     let after_io = <$mc_dtype>::decode(&mut tmp_read).unwrap();
     assert_eq!(before_io, after_io);
   };
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod derive_test {
+
+  use crate::raw_packet::{RawPacketReader, RawPacketWriter};
+
+  use super::{MCDataType, MCPosition};
+
+  #[derive(Debug, Clone, PartialEq, MCDataType)]
+  struct Everything {
+    plain: i16,
+    name: String,
+    #[mc(varint)]
+    count: usize,
+    #[mc(varlong)]
+    big: i64,
+    #[mc(with = MCPosition)]
+    position: (i32, i32, i16),
+    #[mc(array)]
+    bytes: Vec<u8>,
+    #[mc(array, varint)]
+    ids: Vec<i32>,
+    #[mc(option)]
+    label: Option<String>,
+    #[mc(option, varint)]
+    missing: Option<u32>,
+    #[mc(rest)]
+    trailer: Vec<u8>
+  }
+
+  #[derive(Debug, Clone, PartialEq, MCDataType)]
+  struct Pair(#[mc(varint)] i32, bool);
+
+  fn roundtrip<T: MCDataType>(value: &T) -> (Vec<u8>, T) {
+    let mut writer = RawPacketWriter::new(0);
+    value.encode(&mut writer);
+    let bytes = writer.to_raw();
+    let decoded = T::decode(&mut RawPacketReader::from_raw(bytes.clone())).unwrap();
+    (bytes, decoded)
+  }
+
+  #[test]
+  fn field_attributes() {
+    let value = Everything {
+      plain: -2,
+      name: "srvr".to_string(),
+      count: 300,
+      big: 1,
+      position: (1, 2, 3),
+      bytes: vec![0xde, 0xad],
+      ids: vec![1, 128],
+      label: Some("hi".to_string()),
+      missing: None,
+      trailer: vec![0x01, 0x02, 0x03]
+    };
+    let (bytes, decoded) = roundtrip(&value);
+    assert_eq!(decoded, value);
+
+    //Spot-check the wire format of the prefixed fields
+    assert_eq!(&bytes[..9], &[0xff, 0xfe, 0x04, b's', b'r', b'v', b'r', 0xac, 0x02]);
+    assert_eq!(&bytes[bytes.len() - 15..], &[
+      0x02, 0xde, 0xad,             //byte array
+      0x02, 0x01, 0x80, 0x01,       //VarInt array
+      0x01, 0x02, b'h', b'i',       //present option
+      0x00,                         //absent option
+      0x01, 0x02, 0x03              //rest
+    ]);
+  }
+
+  #[test]
+  fn tuple_struct() {
+    let (bytes, decoded) = roundtrip(&Pair(-1, true));
+    assert_eq!(decoded, Pair(-1, true));
+    assert_eq!(bytes, vec![0xff, 0xff, 0xff, 0xff, 0x0f, 0x01]);
+  }
+
+  #[test]
+  fn bad_array_length() {
+    //Claims 5 elements but only has one byte left
+    let mut bytes = vec![0x00; 13];
+    bytes.extend_from_slice(&[0x05, 0xaa]);
+    let mut reader = RawPacketReader::from_raw(bytes);
+    assert!(Everything::decode(&mut reader).is_err());
+  }
+
+}
@@ -28,7 +28,7 @@ use crate::raw_packet::{RawPacketReader, RawPacketWriter};
 
 use super::{MCDataType, MCString, MCDataTypeDecodeError};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MCChat {
   chat: Value
 }
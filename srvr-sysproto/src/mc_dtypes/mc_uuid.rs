@@ -22,10 +22,16 @@
   text of the license in any official language of the European Union.
 */
 
+use std::str::FromStr;
+
 use byteorder::{BigEndian, ByteOrder};
+use md5::{Digest, Md5};
 
 use super::*;
 
+//Prefix used by vanilla to derive the UUIDs of players in offline mode
+const OFFLINE_PLAYER_PREFIX: &str = "OfflinePlayer:";
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct MCUuid(u128);
 
@@ -40,6 +46,59 @@ impl MCDataType for MCUuid {
   }
 }
 
+impl MCUuid {
+
+  /// Name-based (version 3) UUID of the given bytes, computed the same way as
+  /// Java's `UUID.nameUUIDFromBytes`: no namespace, just an MD5 hash with the
+  /// version and variant bits overwritten.
+  pub fn from_name_bytes(name: &[u8]) -> MCUuid {
+    let mut hash: [u8; 16] = Md5::digest(name).into();
+    hash[6] = (hash[6] & 0x0f) | 0x30; //version 3
+    hash[8] = (hash[8] & 0x3f) | 0x80; //IETF variant
+    MCUuid(u128::from_be_bytes(hash))
+  }
+
+  /// UUID that vanilla servers assign to a player in offline mode
+  pub fn offline_player(name: &str) -> MCUuid {
+    Self::from_name_bytes(format!("{OFFLINE_PLAYER_PREFIX}{name}").as_bytes())
+  }
+
+  /// UUID as 32 hex digits without hyphens, as used by the session server
+  pub fn to_simple_string(&self) -> String {format!("{:032x}", self.0)}
+
+}
+
+impl Display for MCUuid {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    //Hyphenated 8-4-4-4-12 form
+    let hex = self.to_simple_string();
+    write!(f, "{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+  }
+}
+
+impl FromStr for MCUuid {
+  type Err = MCDataTypeDecodeError;
+
+  fn from_str(uuid: &str) -> Result<MCUuid, Err> {
+    //(1) We accept both the hyphenated and the simple form
+    let invalid = || MCDataTypeDecodeError(format!("invalid UUID \"{uuid}\""));
+    let hex: String = match uuid.len() {
+      32 => uuid.to_string(),
+      36 => {
+        let groups: Vec<&str> = uuid.split('-').collect();
+        let lengths: Vec<usize> = groups.iter().map(|group| group.len()).collect();
+        if lengths != [8, 4, 4, 4, 12] {return Err(invalid())}
+        groups.concat()
+      },
+      _ => return Err(invalid())
+    };
+
+    //(2) Parse the hex digits (from_str_radix would also accept a sign)
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {return Err(invalid())}
+    u128::from_str_radix(&hex, 16).map(MCUuid).map_err(|_| invalid())
+  }
+}
+
 impl From<u128> for MCUuid{
   fn from(val: u128) -> Self {MCUuid(val)}
 }
@@ -65,4 +124,37 @@ mod mc_double_test {
     }
   }
 
+  #[test]
+  fn offline_player_test() {
+    use crate::mc_dtypes::MCUuid;
+
+    //Reference values from a vanilla server
+    assert_eq!(
+      MCUuid::offline_player("Notch").to_string(),
+      "b50ad385-829d-3141-a216-7e7d7539ba7f"
+    );
+    assert_eq!(
+      MCUuid::offline_player("jeb_").to_string(),
+      "a762f560-4fce-3236-812a-b80efff0b62b"
+    );
+  }
+
+  #[test]
+  fn string_test() {
+    use crate::mc_dtypes::MCUuid;
+
+    let uuid = MCUuid::from(0x069a79f444e94726a5befca90e38aaf5);
+    assert_eq!(uuid.to_string(), "069a79f4-44e9-4726-a5be-fca90e38aaf5");
+    assert_eq!(uuid.to_simple_string(), "069a79f444e94726a5befca90e38aaf5");
+
+    //Both forms parse back to the same UUID
+    assert_eq!("069a79f4-44e9-4726-a5be-fca90e38aaf5".parse::<MCUuid>().unwrap(), uuid);
+    assert_eq!("069a79f444e94726a5befca90e38aaf5".parse::<MCUuid>().unwrap(), uuid);
+
+    //Garbage does not
+    assert!("069a79f444e9-4726-a5be-fca90e38aaf5".parse::<MCUuid>().is_err());
+    assert!("+69a79f444e94726a5befca90e38aaf5".parse::<MCUuid>().is_err());
+    assert!("not a uuid".parse::<MCUuid>().is_err());
+  }
+
 }
\ No newline at end of file
@@ -28,12 +28,49 @@ use crate::{
   raw_packet::{RawPacketReader, RawPacketWriter}
 };
 
+/*
+  Round-trip test for packets, the counterpart of correctness_test! for data
+  types. Must be defined before the modules that use it
+*/
+#[cfg(test)]
+macro_rules! packet_test {
+  ($packet:expr) => {{
+    use crate::{
+      packets::Packet,
+      raw_packet::{RawPacketReader, RawPacketWriter}
+    };
+    fn decode_like<P: Packet>(_: &P, buf: &mut RawPacketReader) -> P {
+      P::decode(buf).unwrap()
+    }
+
+    //Encode the packet
+    let before_io = $packet;
+    let mut buf = RawPacketWriter::new(before_io.packet_id());
+    before_io.encode(&mut buf);
+
+    //Decode it again, which should consume every byte
+    let mut tmp_read = RawPacketReader::from_raw(buf.to_raw());
+    let after_io = decode_like(&before_io, &mut tmp_read);
+    assert_eq!(tmp_read.remaining(), 0);
+    assert_eq!(before_io, after_io);
+  }};
+}
+
 //Module structure
 mod server_bound;
 mod client_bound;
+mod registry;
+
+//Derive macro, see srvr-sysproto-derive for the supported attributes
+pub use srvr_sysproto_derive::Packet;
+
+pub use registry::{
+  ConnectionState, PacketDirection, PacketDecodeError, ServerBoundPacket, ClientBoundPacket
+};
 
 pub trait Packet {
   const PACKET_ID: usize;
+  const STATE: ConnectionState;
 
   fn decode(buf: &mut RawPacketReader) -> Result<Self, Box<dyn Error>> where Self: Sized;
   fn encode(&self, buf: &mut RawPacketWriter);
@@ -45,6 +82,7 @@ pub trait Packet {
 */
 //(A) Handshake procedure
 pub use server_bound::handshake::HandshakePacket as SB_Handshake;
+pub use server_bound::status_request::StatusRequestPacket as SB_StatusRequest;
 pub use server_bound::ping::PingPacket as SB_Ping;
 
 //(B) Login procedure
@@ -53,13 +91,30 @@ pub use server_bound::encryption_response::EncryptionResponsePacket as SB_Encryp
 
 //(C) Play
 pub use server_bound::teleport_confirm::TeleportConfirmPacket as SB_TeleportConfirm;
+pub use server_bound::keep_alive::KeepAlivePacket as SB_KeepAlive;
+pub use server_bound::chat_message::ChatMessagePacket as SB_ChatMessage;
+pub use server_bound::client_settings::ClientSettingsPacket as SB_ClientSettings;
+pub use server_bound::plugin_message::PluginMessagePacket as SB_PluginMessage;
+pub use server_bound::player_position::PlayerPositionPacket as SB_PlayerPosition;
+pub use server_bound::player_position_and_rotation::PlayerPositionAndRotationPacket
+  as SB_PlayerPositionAndRotation;
+pub use server_bound::player_rotation::PlayerRotationPacket as SB_PlayerRotation;
+pub use server_bound::player_movement::PlayerMovementPacket as SB_PlayerMovement;
+pub use server_bound::player_digging::PlayerDiggingPacket as SB_PlayerDigging;
+pub use server_bound::player_digging::{DIGGING_STARTED, DIGGING_CANCELLED, DIGGING_FINISHED};
+pub use server_bound::player_block_placement::PlayerBlockPlacementPacket as SB_PlayerBlockPlacement;
 
 /*
   Re-export of all Possible client-bound (outgoing) packages
 */
 //(A) Handshake procedure
 pub use client_bound::status::StatusPacket as CB_Status;
+pub use client_bound::status::{
+  ServerStatus, ServerStatusBuilder, StatusVersion, StatusPlayers, StatusPlayerSample
+};
 pub use client_bound::pong::PongPacket as CB_Pong;
+pub use client_bound::legacy_kick::LegacyKickPacket as CB_LegacyKick;
+pub use client_bound::legacy_kick::LEGACY_PING_ID;
 
 //(B) Login procedure
 pub use client_bound::login_disconnect::LoginDisconnectPacket as CB_LoginDisconnect;
@@ -69,4 +124,25 @@ pub use client_bound::set_compression::SetCompressionPacket as CB_SetCompression
 
 //(C) Play
 pub use client_bound::join_game::JoinGamePacket as CB_JoinGame;
-pub use client_bound::spawn_position::SpawnPositionPacket as CB_SpawnPosition;
\ No newline at end of file
+pub use client_bound::spawn_position::SpawnPositionPacket as CB_SpawnPosition;
+pub use client_bound::keep_alive::KeepAlivePacket as CB_KeepAlive;
+pub use client_bound::player_position_and_look::PlayerPositionAndLookPacket
+  as CB_PlayerPositionAndLook;
+pub use client_bound::player_position_and_look::{
+  RELATIVE_X, RELATIVE_Y, RELATIVE_Z, RELATIVE_YAW, RELATIVE_PITCH
+};
+pub use client_bound::chunk_data::ChunkDataPacket as CB_ChunkData;
+pub use client_bound::chunk_data::{BlockEntity, LightArray};
+pub use client_bound::update_view_position::UpdateViewPositionPacket as CB_UpdateViewPosition;
+pub use client_bound::player_info::PlayerInfoPacket as CB_PlayerInfo;
+pub use client_bound::player_info::{
+  PlayerInfoAction, PlayerInfoAdd, PlayerProperty, PlayerInfoGamemode, PlayerInfoLatency,
+  PlayerInfoDisplayName, PlayerInfoRemove
+};
+pub use client_bound::chat_message::ChatMessagePacket as CB_ChatMessage;
+pub use client_bound::chat_message::{
+  CHAT_POSITION_CHAT, CHAT_POSITION_SYSTEM, CHAT_POSITION_GAME_INFO
+};
+pub use client_bound::disconnect::DisconnectPacket as CB_Disconnect;
+pub use client_bound::time_update::TimeUpdatePacket as CB_TimeUpdate;
+pub use client_bound::plugin_message::PluginMessagePacket as CB_PluginMessage;
@@ -25,6 +25,7 @@
 //(A) Handshaking
 pub mod status;
 pub mod pong;
+pub mod legacy_kick;
 
 //(B) Login
 pub mod login_disconnect;
@@ -34,4 +35,13 @@ pub mod set_compression;
 
 //(C) Play
 pub mod join_game;
-pub mod spawn_position;
\ No newline at end of file
+pub mod spawn_position;
+pub mod keep_alive;
+pub mod player_position_and_look;
+pub mod chunk_data;
+pub mod update_view_position;
+pub mod player_info;
+pub mod chat_message;
+pub mod disconnect;
+pub mod time_update;
+pub mod plugin_message;
\ No newline at end of file
@@ -0,0 +1,58 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use crate::{
+  packets::Packet,
+  mc_dtypes::MCChat
+};
+
+//Where the message shows up on the client
+pub const CHAT_POSITION_CHAT: i8 = 0;
+pub const CHAT_POSITION_SYSTEM: i8 = 1;
+pub const CHAT_POSITION_GAME_INFO: i8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Packet)]
+#[packet(id = 0x0f, state = Play)]
+pub struct ChatMessagePacket {
+  pub message: MCChat,
+  pub position: i8,
+  //UUID of the player that sent the message, zero for system messages
+  pub sender: u128
+}
+
+#[cfg(test)]
+mod chat_message_test {
+  use serde_json::json;
+
+  use super::{ChatMessagePacket, CHAT_POSITION_CHAT};
+
+  #[test]
+  fn roundtrip_test() {
+    packet_test!(ChatMessagePacket{
+      message: json!({"text": "Hello world"}).into(),
+      position: CHAT_POSITION_CHAT,
+      sender: 0x069a79f444e94726a5befca90e38aaf5
+    });
+  }
+}
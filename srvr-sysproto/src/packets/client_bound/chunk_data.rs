@@ -0,0 +1,106 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use crate::{
+  packets::Packet,
+  mc_dtypes::{MCDataType, MCNbt}
+};
+
+#[derive(Debug, Clone, PartialEq, Packet)]
+#[packet(id = 0x22, state = Play)]
+pub struct ChunkDataPacket {
+  pub chunk_x: i32,
+  pub chunk_z: i32,
+  #[mc(nbt)]
+  pub heightmaps: MCNbt,
+  //Chunk sections, already serialized
+  #[mc(array)]
+  pub data: Vec<u8>,
+  #[mc(array)]
+  pub block_entities: Vec<BlockEntity>,
+  pub trust_edges: bool,
+  //Light masks are BitSets: one bit per section, starting one section below
+  //the world
+  #[mc(array)]
+  pub sky_light_mask: Vec<i64>,
+  #[mc(array)]
+  pub block_light_mask: Vec<i64>,
+  #[mc(array)]
+  pub empty_sky_light_mask: Vec<i64>,
+  #[mc(array)]
+  pub empty_block_light_mask: Vec<i64>,
+  //One array for every bit set in the corresponding mask
+  #[mc(array)]
+  pub sky_light: Vec<LightArray>,
+  #[mc(array)]
+  pub block_light: Vec<LightArray>
+}
+
+#[derive(Debug, Clone, PartialEq, MCDataType)]
+pub struct BlockEntity {
+  //Section-relative x and z: (x << 4) | z
+  pub packed_xz: u8,
+  pub y: i16,
+  #[mc(varint)]
+  pub entity_type: i32,
+  #[mc(nbt)]
+  pub data: MCNbt
+}
+
+//Half a byte of light per block, so 2048 bytes per section
+#[derive(Debug, Clone, PartialEq, MCDataType)]
+pub struct LightArray(#[mc(array)] pub Vec<u8>);
+
+#[cfg(test)]
+mod chunk_data_test {
+  use crate::mc_dtypes::MCNbt;
+
+  use super::{ChunkDataPacket, BlockEntity, LightArray};
+
+  #[test]
+  fn roundtrip_test() {
+    let heightmaps = MCNbt::Compound(None, vec![
+      MCNbt::LongArray(Some("MOTION_BLOCKING".to_string()), vec![0x0101010101; 37])
+    ]);
+    packet_test!(ChunkDataPacket{
+      chunk_x: -3,
+      chunk_z: 7,
+      heightmaps,
+      data: vec![0x00, 0x10, 0x00, 0x00, 0x01],
+      block_entities: vec![BlockEntity{
+        packed_xz: 0x3f,
+        y: -60,
+        entity_type: 7,
+        data: MCNbt::Compound(None, vec![MCNbt::String(Some("id".to_string()), "chest".to_string())])
+      }],
+      trust_edges: true,
+      sky_light_mask: vec![0b110],
+      block_light_mask: vec![],
+      empty_sky_light_mask: vec![0b001],
+      empty_block_light_mask: vec![0x03ffffff],
+      sky_light: vec![LightArray(vec![0xff; 2048]), LightArray(vec![0x00; 2048])],
+      block_light: vec![]
+    });
+  }
+}
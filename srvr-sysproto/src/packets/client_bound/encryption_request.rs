@@ -22,52 +22,14 @@
   text of the license in any official language of the European Union.
 */
 
-use std::error::Error;
+use crate::packets::Packet;
 
-use crate::{
-  packets::Packet,
-  raw_packet::{RawPacketReader, RawPacketWriter},
-  mc_dtypes::{MCDataType, MCLong, MCString, MCVarInt}
-};
-
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,Packet)]
+#[packet(id = 0x01, state = Login)]
 pub struct EncryptionRequestPacket {
-  server_id: String,
-  public_key: Vec<u8>,
-  token: Vec<u8>
+  pub server_id: String,
+  #[mc(array)]
+  pub public_key: Vec<u8>,
+  #[mc(array)]
+  pub token: Vec<u8>
 }
-
-impl Packet for EncryptionRequestPacket {
-
-  const PACKET_ID: usize = 0x01;
-
-  fn decode(buf: &mut RawPacketReader)
-    -> Result<EncryptionRequestPacket, Box<dyn Error>>
-  {
-    let server_id: String = MCString::decode(buf)?.into();
-    let key_len: i32 = MCVarInt::decode(buf)?.into();
-    let public_key = buf.read_bytes(key_len as usize);
-    let token_len: i32 = MCVarInt::decode(buf)?.into();
-    let token = buf.read_bytes(token_len as usize);
-
-    Ok(EncryptionRequestPacket{
-      server_id: server_id,
-      public_key: public_key,
-      token: token
-    })
-  }
-
-  fn encode(&self, buf: &mut RawPacketWriter) {
-    //(1) Get length of token and public key
-    let token_len = self.token.len();
-    let key_len = self.public_key.len();
-
-    //(2) Encode packet in correct order
-    MCString::from(self.server_id.clone()).encode(buf);
-    MCVarInt::from(key_len as i32).encode(buf);
-    buf.write_bytes(&self.public_key);
-    MCVarInt::from(token_len as i32).encode(buf);
-    buf.write_bytes(&self.token);
-  }
-
-}
\ No newline at end of file
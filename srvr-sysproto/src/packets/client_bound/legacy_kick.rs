@@ -0,0 +1,103 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+/*(Note to future self)
+  Clients older than 1.7 ping the server with a single 0xFE byte (optionally
+  followed by more data) instead of a handshake. The server answers with a
+  "kick" packet: 0xFF, followed by the length of the reason string in UTF-16
+  code units as a big-endian u16, followed by the UTF-16BE reason itself.
+  This packet is not VarInt-framed, so it does not implement Packet.
+*/
+
+pub const LEGACY_PING_ID: u8 = 0xfe;
+const LEGACY_KICK_ID: u8 = 0xff;
+
+//Protocol number we advertise to legacy clients, which makes them show the
+//server as incompatible (just like vanilla does)
+const LEGACY_PROTOCOL: usize = 127;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegacyKickPacket {
+  pub version_name: String,
+  pub motd: String,
+  pub online_players: usize,
+  pub max_players: usize
+}
+
+impl LegacyKickPacket {
+
+  /// Response for 1.4 - 1.6 clients, which send 0xFE 0x01
+  pub fn encode_v1_4(&self) -> Vec<u8> {
+    encode_kick(&format!("§1\0{}\0{}\0{}\0{}\0{}",
+      LEGACY_PROTOCOL, self.version_name, self.motd, self.online_players, self.max_players
+    ))
+  }
+
+  /// Response for beta 1.8 - 1.3 clients, which send just 0xFE. This format
+  /// uses § as its separator, so it can't appear in the MOTD.
+  pub fn encode_pre_v1_4(&self) -> Vec<u8> {
+    encode_kick(&format!("{}§{}§{}",
+      self.motd.replace('§', ""), self.online_players, self.max_players
+    ))
+  }
+
+}
+
+fn encode_kick(reason: &str) -> Vec<u8> {
+  let utf16: Vec<u16> = reason.encode_utf16().collect();
+
+  let mut bytes = Vec::with_capacity(3 + 2 * utf16.len());
+  bytes.push(LEGACY_KICK_ID);
+  bytes.extend_from_slice(&(utf16.len() as u16).to_be_bytes());
+  utf16.iter().for_each(|unit| bytes.extend_from_slice(&unit.to_be_bytes()));
+  bytes
+}
+
+#[cfg(test)]
+mod legacy_kick_test {
+
+  use super::LegacyKickPacket;
+
+  #[test]
+  fn encode_test() {
+    let kick = LegacyKickPacket {
+      version_name: "1.4.2".to_string(),
+      motd: "A Minecraft Server".to_string(),
+      online_players: 0,
+      max_players: 20
+    };
+
+    //1.4+ format: 0xFF, 36 UTF-16 code units, then "§1\0127\0..."
+    let bytes = kick.encode_v1_4();
+    assert_eq!(&bytes[..3], &[0xff, 0x00, 0x24]);
+    assert_eq!(&bytes[3..9], &[0x00, 0xa7, 0x00, 0x31, 0x00, 0x00]);
+    assert_eq!(bytes.len(), 3 + 2 * 0x24);
+
+    //Pre-1.4 format: "A Minecraft Server§0§20"
+    let bytes = kick.encode_pre_v1_4();
+    assert_eq!(&bytes[..3], &[0xff, 0x00, 0x17]);
+    assert_eq!(&bytes[bytes.len() - 6..], &[0x00, 0xa7, 0x00, 0x32, 0x00, 0x30]);
+  }
+
+}
@@ -22,31 +22,13 @@
   text of the license in any official language of the European Union.
 */
 
-use std::error::Error;
-
 use crate::{
   packets::Packet,
-  raw_packet::{RawPacketReader, RawPacketWriter},
-  mc_dtypes::{MCDataType, MCChat}
+  mc_dtypes::MCChat
 };
 
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,Packet)]
+#[packet(id = 0x00, state = Login)]
 pub struct LoginDisconnectPacket {
   pub reason: MCChat
 }
-
-impl Packet for LoginDisconnectPacket {
-
-  const PACKET_ID: usize = 0x00;
-
-  fn decode(buf: &mut RawPacketReader)
-    -> Result<LoginDisconnectPacket, Box<dyn Error>>
-  {
-    Ok(LoginDisconnectPacket{reason: MCChat::decode(buf)?})
-  }
-
-  fn encode(&self, buf: &mut RawPacketWriter) {
-    self.reason.encode(buf);
-  }
-
-}
\ No newline at end of file
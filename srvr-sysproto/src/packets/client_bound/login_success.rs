@@ -22,33 +22,11 @@
   text of the license in any official language of the European Union.
 */
 
-use std::error::Error;
+use crate::packets::Packet;
 
-use crate::{
-  packets::Packet,
-  raw_packet::{RawPacketReader, RawPacketWriter},
-  mc_dtypes::{MCDataType, MCUuid, MCString}
-};
-
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,Packet)]
+#[packet(id = 0x02, state = Login)]
 pub struct LoginSuccessPacket {
   pub uuid: u128,
   pub player_name: String
 }
-
-impl Packet for LoginSuccessPacket {
-
-  const PACKET_ID: usize = 0x02;
-
-  fn decode(buf: &mut RawPacketReader) -> Result<LoginSuccessPacket, Box<dyn Error>> {
-    let uuid: u128 = MCUuid::decode(buf)?.into();
-    let player_name: String = MCString::decode(buf)?.into();
-    Ok(LoginSuccessPacket{ uuid: uuid, player_name: player_name})
-  }
-
-  fn encode(&self, buf: &mut RawPacketWriter) {
-    MCUuid::from(self.uuid).encode(buf);
-    MCString::from(self.player_name.clone()).encode(buf);
-  }
-
-}
\ No newline at end of file
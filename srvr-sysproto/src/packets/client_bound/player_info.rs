@@ -0,0 +1,205 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::error::Error;
+
+use crate::{
+  packets::{Packet, ConnectionState},
+  raw_packet::{RawPacketReader, RawPacketWriter},
+  mc_dtypes::{
+    MCDataType, MCChat, MCVarInt, MCDataTypeDecodeError, decode_array_len, encode_array_len
+  }
+};
+
+/*
+  The layout of the player entries depends on the action, so unlike most
+  packets this one can't be derived
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerInfoPacket {
+  pub action: PlayerInfoAction
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerInfoAction {
+  AddPlayer(Vec<PlayerInfoAdd>),
+  UpdateGamemode(Vec<PlayerInfoGamemode>),
+  UpdateLatency(Vec<PlayerInfoLatency>),
+  UpdateDisplayName(Vec<PlayerInfoDisplayName>),
+  RemovePlayer(Vec<PlayerInfoRemove>)
+}
+
+#[derive(Debug, Clone, PartialEq, MCDataType)]
+pub struct PlayerInfoAdd {
+  pub uuid: u128,
+  pub name: String,
+  #[mc(array)]
+  pub properties: Vec<PlayerProperty>,
+  #[mc(varint)]
+  pub gamemode: i32,
+  //Latency in milliseconds
+  #[mc(varint)]
+  pub ping: i32,
+  #[mc(option)]
+  pub display_name: Option<MCChat>
+}
+
+#[derive(Debug, Clone, PartialEq, MCDataType)]
+pub struct PlayerProperty {
+  pub name: String,
+  pub value: String,
+  #[mc(option)]
+  pub signature: Option<String>
+}
+
+#[derive(Debug, Clone, PartialEq, MCDataType)]
+pub struct PlayerInfoGamemode {
+  pub uuid: u128,
+  #[mc(varint)]
+  pub gamemode: i32
+}
+
+#[derive(Debug, Clone, PartialEq, MCDataType)]
+pub struct PlayerInfoLatency {
+  pub uuid: u128,
+  #[mc(varint)]
+  pub ping: i32
+}
+
+#[derive(Debug, Clone, PartialEq, MCDataType)]
+pub struct PlayerInfoDisplayName {
+  pub uuid: u128,
+  #[mc(option)]
+  pub display_name: Option<MCChat>
+}
+
+#[derive(Debug, Clone, PartialEq, MCDataType)]
+pub struct PlayerInfoRemove {
+  pub uuid: u128
+}
+
+fn decode_entries<T: MCDataType>(buf: &mut RawPacketReader)
+  -> Result<Vec<T>, MCDataTypeDecodeError>
+{
+  let len = decode_array_len(buf)?;
+  (0..len).map(|_| T::decode(buf)).collect()
+}
+
+fn encode_entries<T: MCDataType>(entries: &[T], buf: &mut RawPacketWriter) {
+  encode_array_len(entries.len(), buf);
+  entries.iter().for_each(|entry| entry.encode(buf));
+}
+
+impl Packet for PlayerInfoPacket {
+
+  const PACKET_ID: usize = 0x36;
+  const STATE: ConnectionState = ConnectionState::Play;
+
+  fn decode(buf: &mut RawPacketReader) -> Result<Self, Box<dyn Error>> {
+    use PlayerInfoAction::*;
+    let action = match i32::from(MCVarInt::decode(buf)?) {
+      0 => AddPlayer(decode_entries(buf)?),
+      1 => UpdateGamemode(decode_entries(buf)?),
+      2 => UpdateLatency(decode_entries(buf)?),
+      3 => UpdateDisplayName(decode_entries(buf)?),
+      4 => RemovePlayer(decode_entries(buf)?),
+      other => return Err(format!("invalid player info action {other}").into())
+    };
+    Ok(PlayerInfoPacket{action})
+  }
+
+  fn encode(&self, buf: &mut RawPacketWriter) {
+    use PlayerInfoAction::*;
+    match &self.action {
+      AddPlayer(entries) => {
+        MCVarInt::from(0).encode(buf);
+        encode_entries(entries, buf);
+      },
+      UpdateGamemode(entries) => {
+        MCVarInt::from(1).encode(buf);
+        encode_entries(entries, buf);
+      },
+      UpdateLatency(entries) => {
+        MCVarInt::from(2).encode(buf);
+        encode_entries(entries, buf);
+      },
+      UpdateDisplayName(entries) => {
+        MCVarInt::from(3).encode(buf);
+        encode_entries(entries, buf);
+      },
+      RemovePlayer(entries) => {
+        MCVarInt::from(4).encode(buf);
+        encode_entries(entries, buf);
+      }
+    }
+  }
+
+}
+
+#[cfg(test)]
+mod player_info_test {
+  use serde_json::json;
+
+  use super::*;
+
+  const NOTCH: u128 = 0x069a79f444e94726a5befca90e38aaf5;
+
+  #[test]
+  fn roundtrip_test() {
+    packet_test!(PlayerInfoPacket{action: PlayerInfoAction::AddPlayer(vec![
+      PlayerInfoAdd{
+        uuid: NOTCH,
+        name: "Notch".to_string(),
+        properties: vec![PlayerProperty{
+          name: "textures".to_string(),
+          value: "e30=".to_string(),
+          signature: Some("c2ln".to_string())
+        }],
+        gamemode: 1,
+        ping: 35,
+        display_name: Some(json!({"text": "Notch", "color": "gold"}).into())
+      },
+      PlayerInfoAdd{
+        uuid: 1,
+        name: "jeb_".to_string(),
+        properties: vec![],
+        gamemode: 0,
+        ping: 0,
+        display_name: None
+      }
+    ])});
+    packet_test!(PlayerInfoPacket{action: PlayerInfoAction::UpdateGamemode(vec![
+      PlayerInfoGamemode{uuid: NOTCH, gamemode: 3}
+    ])});
+    packet_test!(PlayerInfoPacket{action: PlayerInfoAction::UpdateLatency(vec![
+      PlayerInfoLatency{uuid: NOTCH, ping: 250}
+    ])});
+    packet_test!(PlayerInfoPacket{action: PlayerInfoAction::UpdateDisplayName(vec![
+      PlayerInfoDisplayName{uuid: NOTCH, display_name: None}
+    ])});
+    packet_test!(PlayerInfoPacket{action: PlayerInfoAction::RemovePlayer(vec![
+      PlayerInfoRemove{uuid: NOTCH}
+    ])});
+  }
+}
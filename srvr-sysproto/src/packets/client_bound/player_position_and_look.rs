@@ -0,0 +1,63 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use crate::packets::Packet;
+
+//Flags that make the corresponding field relative to the current position
+pub const RELATIVE_X: i8 = 0x01;
+pub const RELATIVE_Y: i8 = 0x02;
+pub const RELATIVE_Z: i8 = 0x04;
+pub const RELATIVE_YAW: i8 = 0x08;
+pub const RELATIVE_PITCH: i8 = 0x10;
+
+#[derive(Debug, Clone, PartialEq, Packet)]
+#[packet(id = 0x38, state = Play)]
+pub struct PlayerPositionAndLookPacket {
+  pub x: f64,
+  pub y: f64,
+  pub z: f64,
+  pub yaw: f32,
+  pub pitch: f32,
+  pub flags: i8,
+  //The client answers with a Teleport Confirm carrying this ID
+  #[mc(varint)]
+  pub teleport_id: i32,
+  pub dismount_vehicle: bool
+}
+
+#[cfg(test)]
+mod player_position_and_look_test {
+  use super::{PlayerPositionAndLookPacket, RELATIVE_YAW, RELATIVE_PITCH};
+
+  #[test]
+  fn roundtrip_test() {
+    packet_test!(PlayerPositionAndLookPacket{
+      x: 0.5, y: -64.0, z: 1e7,
+      yaw: 90.0, pitch: -12.5,
+      flags: RELATIVE_YAW | RELATIVE_PITCH,
+      teleport_id: 42,
+      dismount_vehicle: false
+    });
+  }
+}
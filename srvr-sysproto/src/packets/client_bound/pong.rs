@@ -22,29 +22,10 @@
   text of the license in any official language of the European Union.
 */
 
-use std::error::Error;
+use crate::packets::Packet;
 
-use crate::{
-  packets::Packet,
-  raw_packet::{RawPacketReader, RawPacketWriter},
-  mc_dtypes::{MCDataType, MCLong}
-};
-
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,Packet)]
+#[packet(id = 0x01, state = Status)]
 pub struct PongPacket {
   pub payload: i64
 }
-
-impl Packet for PongPacket {
-
-  const PACKET_ID: usize = 0x01;
-
-  fn decode(buf: &mut RawPacketReader) -> Result<PongPacket, Box<dyn Error>> {
-    Ok(PongPacket{payload: MCLong::decode(buf)?.into()})
-  }
-
-  fn encode(&self, buf: &mut RawPacketWriter) {
-    MCLong::from(self.payload).encode(buf);
-  }
-
-}
\ No newline at end of file
@@ -22,33 +22,11 @@
   text of the license in any official language of the European Union.
 */
 
-use std::error::Error;
+use crate::packets::Packet;
 
-use crate::{
-  packets::Packet,
-  raw_packet::{RawPacketReader, RawPacketWriter},
-  mc_dtypes::{MCDataType, MCVarInt}
-};
-
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,Packet)]
+#[packet(id = 0x03, state = Login)]
 pub struct SetCompressionPacket {
+  #[mc(varint)]
   pub threshold_len: usize
 }
-
-impl Packet for SetCompressionPacket {
-
-  const PACKET_ID: usize = 0x03;
-
-  fn decode(buf: &mut RawPacketReader)
-    -> Result<SetCompressionPacket, Box<dyn Error>>
-  {
-    Ok(SetCompressionPacket{
-      threshold_len: i32::from(MCVarInt::decode(buf)?) as usize
-    })
-  }
-
-  fn encode(&self, buf: &mut RawPacketWriter) {
-    MCVarInt::from(self.threshold_len as i32).encode(buf);
-  }
-
-}
\ No newline at end of file
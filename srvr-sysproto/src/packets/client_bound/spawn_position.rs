@@ -22,37 +22,15 @@
   text of the license in any official language of the European Union.
 */
 
-use std::error::Error;
-
 use crate::{
   packets::Packet,
-  raw_packet::{RawPacketReader, RawPacketWriter},
-  mc_dtypes::{MCDataType, MCPosition, MCFloat}
+  mc_dtypes::MCPosition
 };
 
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,Packet)]
+#[packet(id = 0x4b, state = Play)]
 pub struct SpawnPositionPacket {
+  #[mc(with = MCPosition)]
   pub location: (i32, i32, i16),
   pub angle: f32
 }
-
-impl Packet for SpawnPositionPacket {
-  const PACKET_ID: usize = 0x4b;
-
-  fn decode(buf: &mut RawPacketReader)
-    -> Result<SpawnPositionPacket, Box<dyn Error>>
-  {
-    let position = MCPosition::decode(buf)?;
-    let angle = MCFloat::decode(buf)?;
-    Ok(SpawnPositionPacket{
-      location: position.into(),
-      angle: angle.into()
-    })
-  }
-
-  fn encode(&self, buf: &mut RawPacketWriter) {
-    MCPosition::from(self.location).encode(buf);
-    MCFloat::from(self.angle).encode(buf);
-  }
-
-}
\ No newline at end of file
@@ -22,33 +22,181 @@
   text of the license in any official language of the European Union.
 */
 
-use std::error::Error;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
 
 use crate::{
-  packets::Packet,
-  raw_packet::{RawPacketReader, RawPacketWriter},
-  mc_dtypes::{MCDataType, MCString}
+  packets::{Packet, CB_LegacyKick},
+  mc_dtypes::MCUuid
 };
 
-#[derive(Debug,Clone)]
+//Vanilla clients show at most this many names when hovering the player count
+const MAX_PLAYER_SAMPLE: usize = 12;
+
+#[derive(Debug,Clone,Packet)]
+#[packet(id = 0x00, state = Status)]
 pub struct StatusPacket {
   status: String
 }
 
-impl Packet for StatusPacket {
+impl StatusPacket {
+  pub fn new(status: String) -> StatusPacket {StatusPacket { status: status }}
+}
 
-  const PACKET_ID: usize = 0x00;
+impl From<&ServerStatus> for StatusPacket {
+  fn from(status: &ServerStatus) -> Self {StatusPacket::new(status.to_json())}
+}
+
+/// Typed contents of the status response, serialized to the JSON format the
+/// server list expects. Use [`ServerStatus::builder`] to create one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerStatus {
+  pub version: StatusVersion,
+  pub players: StatusPlayers,
+  pub description: Value,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub favicon: Option<String>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusVersion {
+  pub name: String,
+  pub protocol: usize
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusPlayers {
+  pub max: usize,
+  pub online: usize,
+  pub sample: Vec<StatusPlayerSample>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusPlayerSample {
+  pub name: String,
+  //Hyphenated UUID
+  pub id: String
+}
 
-  fn decode(buf: &mut RawPacketReader) -> Result<Self, Box<dyn Error>> {
-    Ok(StatusPacket{status: MCString::decode(buf)?.into()})
+impl StatusPlayerSample {
+  pub fn new(name: String, uuid: u128) -> Self {
+    StatusPlayerSample { name, id: MCUuid::from(uuid).to_string() }
   }
+}
+
+impl ServerStatus {
+
+  pub fn builder() -> ServerStatusBuilder {ServerStatusBuilder::new()}
 
-  fn encode(&self, buf: &mut RawPacketWriter) {
-    MCString::from(self.status.clone()).encode(buf);
+  pub fn to_json(&self) -> String {
+    //Serializing plain structs and json values can't fail
+    serde_json::to_string(self).unwrap()
+  }
+
+  pub fn motd(&self) -> String {
+    match &self.description {
+      Value::String(text) => text.clone(),
+      other => other.get("text").and_then(Value::as_str).unwrap_or_default().to_string()
+    }
   }
 
 }
 
-impl StatusPacket {
-  pub fn new(status: String) -> StatusPacket {StatusPacket { status: status }}
-}
\ No newline at end of file
+impl From<&ServerStatus> for CB_LegacyKick {
+  fn from(status: &ServerStatus) -> Self {
+    CB_LegacyKick {
+      version_name: status.version.name.clone(),
+      motd: status.motd(),
+      online_players: status.players.online,
+      max_players: status.players.max
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerStatusBuilder {
+  status: ServerStatus
+}
+
+impl ServerStatusBuilder {
+
+  pub fn new() -> Self {
+    ServerStatusBuilder { status: ServerStatus {
+      version: StatusVersion {
+        name: crate::VERSION_NAME.to_string(),
+        protocol: crate::PROTOCOL_VERSION
+      },
+      players: StatusPlayers { max: 0, online: 0, sample: Vec::new() },
+      description: json!({"text": ""}),
+      favicon: None
+    }}
+  }
+
+  pub fn motd(mut self, motd: &str) -> Self {
+    self.status.description = json!({"text": motd});
+    self
+  }
+
+  pub fn max_players(mut self, max: usize) -> Self {
+    self.status.players.max = max;
+    self
+  }
+
+  /// Sets the online player count and the names shown when hovering over it.
+  /// Only the first few players are included in the sample, like vanilla.
+  pub fn players(mut self, online: usize, mut sample: Vec<StatusPlayerSample>) -> Self {
+    sample.truncate(MAX_PLAYER_SAMPLE);
+    self.status.players.online = online;
+    self.status.players.sample = sample;
+    self
+  }
+
+  /// Favicon as a base64-encoded 64x64 PNG, without the data URI prefix
+  pub fn favicon(mut self, png_base64: Option<String>) -> Self {
+    self.status.favicon = png_base64.map(|png| format!("data:image/png;base64,{png}"));
+    self
+  }
+
+  pub fn build(self) -> ServerStatus {self.status}
+
+}
+
+impl Default for ServerStatusBuilder {
+  fn default() -> Self {Self::new()}
+}
+
+#[cfg(test)]
+mod status_test {
+
+  use serde_json::Value;
+
+  use super::{ServerStatus, StatusPlayerSample};
+
+  #[test]
+  fn json_test() {
+    let sample = (0..20)
+      .map(|i| StatusPlayerSample::new(format!("player{i}"), i))
+      .collect();
+    let status = ServerStatus::builder()
+      .motd("Hello world")
+      .max_players(100)
+      .players(20, sample)
+      .favicon(Some("iVBORw0KGgo=".to_string()))
+      .build();
+
+    let json: Value = serde_json::from_str(&status.to_json()).unwrap();
+    assert_eq!(json["version"]["protocol"], crate::PROTOCOL_VERSION);
+    assert_eq!(json["players"]["max"], 100);
+    assert_eq!(json["players"]["online"], 20);
+    assert_eq!(json["players"]["sample"].as_array().unwrap().len(), 12);
+    assert_eq!(json["players"]["sample"][1]["id"], "00000000-0000-0000-0000-000000000001");
+    assert_eq!(json["description"]["text"], "Hello world");
+    assert_eq!(json["favicon"], "data:image/png;base64,iVBORw0KGgo=");
+    assert_eq!(status.motd(), "Hello world");
+
+    //No favicon means no favicon field at all
+    let json: Value = serde_json::from_str(&ServerStatus::builder().build().to_json()).unwrap();
+    assert!(json.get("favicon").is_none());
+  }
+
+}
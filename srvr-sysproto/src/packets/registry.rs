@@ -0,0 +1,263 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use std::{error::Error, fmt::{self, Display}};
+
+use crate::raw_packet::RawPacketReader;
+
+use super::*;
+
+/*
+  The meaning of a packet ID depends on the state of the connection and on the
+  direction the packet travels in. The registries below map every (state, id)
+  pair we know about to a typed packet, one registry per direction.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+  Handshaking,
+  Status,
+  Login,
+  Play
+}
+
+impl Display for ConnectionState {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ConnectionState::Handshaking => write!(f, "handshaking"),
+      ConnectionState::Status => write!(f, "status"),
+      ConnectionState::Login => write!(f, "login"),
+      ConnectionState::Play => write!(f, "play")
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PacketDirection {
+  ServerBound,
+  ClientBound
+}
+
+impl Display for PacketDirection {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PacketDirection::ServerBound => write!(f, "server-bound"),
+      PacketDirection::ClientBound => write!(f, "client-bound")
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacketDecodeError {
+  //No packet with this ID exists in this state (or we don't support it)
+  UnknownPacket{state: ConnectionState, direction: PacketDirection, id: usize},
+  //The packet is known, but its body could not be decoded
+  Malformed{state: ConnectionState, direction: PacketDirection, id: usize, reason: String}
+}
+
+impl Display for PacketDecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PacketDecodeError::UnknownPacket{state, direction, id} =>
+        write!(f, "unknown {direction} packet {id:#04x} in {state} state"),
+      PacketDecodeError::Malformed{state, direction, id, reason} =>
+        write!(f, "malformed {direction} packet {id:#04x} in {state} state: {reason}")
+    }
+  }
+}
+
+impl Error for PacketDecodeError {}
+
+macro_rules! packet_registry {
+  (
+    $(#[$meta:meta])*
+    $name:ident: $direction:ident {
+      $($state:ident {
+        $($variant:ident($packet:ty)),* $(,)?
+      })*
+    }
+  ) => {
+    //Every packet has to be registered under the state it declares
+    $($(const _: () = assert!(matches!(<$packet as Packet>::STATE, ConnectionState::$state));)*)*
+
+    $(#[$meta])*
+    #[derive(Debug, Clone)]
+    pub enum $name {
+      $($($variant($packet),)*)*
+    }
+
+    impl $name {
+      pub const DIRECTION: PacketDirection = PacketDirection::$direction;
+
+      pub fn decode(state: ConnectionState, buf: &mut RawPacketReader)
+        -> Result<Self, PacketDecodeError>
+      {
+        let id = buf.get_package_id();
+        let malformed = |reason: Box<dyn Error>| PacketDecodeError::Malformed{
+          state, direction: Self::DIRECTION, id, reason: reason.to_string()
+        };
+        match state {
+          $(ConnectionState::$state => match id {
+            $(<$packet as Packet>::PACKET_ID => {
+              <$packet as Packet>::decode(buf).map($name::$variant).map_err(malformed)
+            },)*
+            _ => Err(PacketDecodeError::UnknownPacket{state, direction: Self::DIRECTION, id})
+          },)*
+        }
+      }
+
+      pub fn state(&self) -> ConnectionState {
+        match self {
+          $($($name::$variant(_) => <$packet as Packet>::STATE,)*)*
+        }
+      }
+
+      pub fn packet_id(&self) -> usize {
+        match self {
+          $($($name::$variant(packet) => packet.packet_id(),)*)*
+        }
+      }
+    }
+  };
+}
+
+packet_registry!{
+  ///Every packet a client may send us, by connection state
+  ServerBoundPacket: ServerBound {
+    Handshaking {
+      Handshake(SB_Handshake)
+    }
+    Status {
+      StatusRequest(SB_StatusRequest),
+      Ping(SB_Ping)
+    }
+    Login {
+      LoginStart(SB_LoginStart),
+      EncryptionResponse(SB_EncryptionResponse)
+    }
+    Play {
+      TeleportConfirm(SB_TeleportConfirm),
+      ChatMessage(SB_ChatMessage),
+      ClientSettings(SB_ClientSettings),
+      PluginMessage(SB_PluginMessage),
+      KeepAlive(SB_KeepAlive),
+      PlayerPosition(SB_PlayerPosition),
+      PlayerPositionAndRotation(SB_PlayerPositionAndRotation),
+      PlayerRotation(SB_PlayerRotation),
+      PlayerMovement(SB_PlayerMovement),
+      PlayerDigging(SB_PlayerDigging),
+      PlayerBlockPlacement(SB_PlayerBlockPlacement)
+    }
+  }
+}
+
+packet_registry!{
+  ///Every packet we may send to a client, by connection state
+  ClientBoundPacket: ClientBound {
+    Handshaking {}
+    Status {
+      Status(CB_Status),
+      Pong(CB_Pong)
+    }
+    Login {
+      LoginDisconnect(CB_LoginDisconnect),
+      EncryptionRequest(CB_EncryptionRequest),
+      LoginSuccess(CB_LoginSuccess),
+      SetCompression(CB_SetCompression)
+    }
+    Play {
+      ChatMessage(CB_ChatMessage),
+      PluginMessage(CB_PluginMessage),
+      Disconnect(CB_Disconnect),
+      KeepAlive(CB_KeepAlive),
+      ChunkData(CB_ChunkData),
+      JoinGame(CB_JoinGame),
+      PlayerInfo(CB_PlayerInfo),
+      PlayerPositionAndLook(CB_PlayerPositionAndLook),
+      UpdateViewPosition(CB_UpdateViewPosition),
+      SpawnPosition(CB_SpawnPosition),
+      TimeUpdate(CB_TimeUpdate)
+    }
+  }
+}
+
+#[cfg(test)]
+mod registry_test {
+
+  use crate::raw_packet::{PacketStream, RawPacketReader};
+
+  use super::{ServerBoundPacket, ConnectionState, PacketDirection, PacketDecodeError};
+
+  async fn read(bytes: &[u8]) -> RawPacketReader {
+    RawPacketReader::read(&mut PacketStream::new(bytes)).await.unwrap()
+  }
+
+  #[tokio::test]
+  async fn same_id_per_state() {
+    //0x00 with an empty body is a status request in the status state...
+    let mut packet = read(&[0x01, 0x00]).await;
+    let decoded = ServerBoundPacket::decode(ConnectionState::Status, &mut packet).unwrap();
+    assert!(matches!(decoded, ServerBoundPacket::StatusRequest(_)));
+    assert_eq!(decoded.state(), ConnectionState::Status);
+
+    //...but a login start in the login state
+    let mut packet = read(&[0x06, 0x00, 0x04, b'j', b'e', b'b', b'_']).await;
+    match ServerBoundPacket::decode(ConnectionState::Login, &mut packet).unwrap() {
+      ServerBoundPacket::LoginStart(login) => assert_eq!(login.player_name, "jeb_"),
+      other => panic!("expected login start, got {other:?}")
+    }
+  }
+
+  #[tokio::test]
+  async fn handshake_next_state() {
+    //Protocol 758, "localhost", port 25565, next state login
+    let mut bytes = vec![0x10, 0x00, 0xf6, 0x05, 0x09];
+    bytes.extend_from_slice(b"localhost");
+    bytes.extend_from_slice(&[0x63, 0xdd, 0x02]);
+    let mut packet = read(&bytes).await;
+    match ServerBoundPacket::decode(ConnectionState::Handshaking, &mut packet).unwrap() {
+      ServerBoundPacket::Handshake(handshake) => {
+        assert_eq!(handshake.get_protocol(), 758);
+        assert_eq!(handshake.get_server_port(), 25565);
+        assert_eq!(handshake.next_state(), Some(ConnectionState::Login));
+      },
+      other => panic!("expected handshake, got {other:?}")
+    }
+  }
+
+  #[tokio::test]
+  async fn unknown_packet() {
+    //There is no 0x01 packet during the handshake
+    let mut packet = read(&[0x01, 0x01]).await;
+    assert_eq!(
+      ServerBoundPacket::decode(ConnectionState::Handshaking, &mut packet).unwrap_err(),
+      PacketDecodeError::UnknownPacket{
+        state: ConnectionState::Handshaking,
+        direction: PacketDirection::ServerBound,
+        id: 0x01
+      }
+    );
+  }
+
+}
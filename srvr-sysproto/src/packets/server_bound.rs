@@ -24,6 +24,7 @@
 
 //(A) Handshake procedure
 pub mod handshake;
+pub mod status_request;
 pub mod ping;
 
 //(B) Login
@@ -31,4 +32,14 @@ pub mod login_start;
 pub mod encryption_response;
 
 //(C) Play
-pub mod teleport_confirm;
\ No newline at end of file
+pub mod teleport_confirm;
+pub mod keep_alive;
+pub mod chat_message;
+pub mod client_settings;
+pub mod plugin_message;
+pub mod player_position;
+pub mod player_position_and_rotation;
+pub mod player_rotation;
+pub mod player_movement;
+pub mod player_digging;
+pub mod player_block_placement;
\ No newline at end of file
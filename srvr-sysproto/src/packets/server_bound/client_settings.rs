@@ -0,0 +1,62 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use crate::packets::Packet;
+
+#[derive(Debug, Clone, PartialEq, Packet)]
+#[packet(id = 0x05, state = Play)]
+pub struct ClientSettingsPacket {
+  pub locale: String,
+  pub view_distance: i8,
+  //0: enabled, 1: commands only, 2: hidden
+  #[mc(varint)]
+  pub chat_mode: i32,
+  pub chat_colors: bool,
+  //Bit mask of the visible skin layers
+  pub displayed_skin_parts: u8,
+  //0: left, 1: right
+  #[mc(varint)]
+  pub main_hand: i32,
+  pub enable_text_filtering: bool,
+  pub allow_server_listings: bool
+}
+
+#[cfg(test)]
+mod client_settings_test {
+  use super::ClientSettingsPacket;
+
+  #[test]
+  fn roundtrip_test() {
+    packet_test!(ClientSettingsPacket{
+      locale: "en_us".to_string(),
+      view_distance: 12,
+      chat_mode: 0,
+      chat_colors: true,
+      displayed_skin_parts: 0x7f,
+      main_hand: 1,
+      enable_text_filtering: false,
+      allow_server_listings: true
+    });
+  }
+}
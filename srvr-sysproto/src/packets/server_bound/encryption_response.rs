@@ -22,47 +22,13 @@
   text of the license in any official language of the European Union.
 */
 
-use std::error::Error;
+use crate::packets::Packet;
 
-use crate::{
-  packets::Packet,
-  raw_packet::{RawPacketReader, RawPacketWriter},
-  mc_dtypes::{MCDataType, MCVarInt}
-};
-
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,Packet)]
+#[packet(id = 0x01, state = Login)]
 pub struct EncryptionResponsePacket {
-  secret_key: Vec<u8>,
-  token: Vec<u8>
+  #[mc(array)]
+  pub secret_key: Vec<u8>,
+  #[mc(array)]
+  pub token: Vec<u8>
 }
-
-impl Packet for EncryptionResponsePacket {
-
-  const PACKET_ID: usize = 0x01;
-
-  fn decode(buf: &mut RawPacketReader)
-    -> Result<EncryptionResponsePacket, Box<dyn Error>>
-  {
-    let secret_len: i32 = MCVarInt::decode(buf)?.into();
-    let secret_key = buf.read_bytes(secret_len as usize);
-    let token_len: i32 = MCVarInt::decode(buf)?.into();
-    let token = buf.read_bytes(token_len as usize);
-
-    Ok(EncryptionResponsePacket {
-      secret_key: secret_key, token: token
-    })
-  }
-
-  fn encode(&self, buf: &mut RawPacketWriter) {
-    //(1) Get token and key length
-    let token_len = self.token.len();
-    let key_len = self.secret_key.len();
-
-    //(2) Write in correct order
-    MCVarInt::from(key_len as i32).encode(buf);
-    buf.write_bytes(&self.secret_key);
-    MCVarInt::from(token_len as i32).encode(buf);
-    buf.write_bytes(&self.token);
-  }
-
-}
\ No newline at end of file
@@ -22,49 +22,19 @@
   text of the license in any official language of the European Union.
 */
 
-use std::error::Error;
+use crate::packets::{Packet, ConnectionState};
 
-use crate::{
-  packets::Packet,
-  raw_packet::{RawPacketReader, RawPacketWriter},
-  mc_dtypes::{MCVarInt, MCDataType, MCString, MCUShort}
-};
-
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,Packet)]
+#[packet(id = 0x00, state = Handshaking)]
 pub struct HandshakePacket {
+  #[mc(varint)]
   proto_ver: usize,
   server_addr: String,
   server_port: u16,
+  #[mc(varint)]
   next_state: u8
 }
 
-impl Packet for HandshakePacket {
-
-  const PACKET_ID: usize = 0x00;
-
-  fn decode(buf: &mut RawPacketReader) -> Result<HandshakePacket, Box<dyn Error>> {
-    let proto_ver: i32 = MCVarInt::decode(buf)?.into();
-    let server_addr: String = MCString::decode(buf)?.into();
-    let server_port: u16 = MCUShort::decode(buf)?.into();
-    let next_state: i32 = MCVarInt::decode(buf)?.into();
-
-    Ok(HandshakePacket{
-      proto_ver: proto_ver as usize,
-      server_addr: server_addr,
-      server_port: server_port,
-      next_state: next_state as u8
-    })
-  }
-
-  fn encode(&self, buf: &mut RawPacketWriter) {
-    MCVarInt::from(self.proto_ver as i32).encode(buf);
-    MCString::new(self.server_addr.clone()).encode(buf);
-    MCUShort::from(self.server_port).encode(buf);
-    MCVarInt::from(self.next_state as i32).encode(buf);
-  }
-
-}
-
 impl HandshakePacket {
 
   pub fn new() -> Self {todo!()}
@@ -74,4 +44,13 @@ impl HandshakePacket {
   pub fn get_server_port(&self) -> u16 {self.server_port}
   pub fn next_state_code(&self) -> u8 {self.next_state}
 
+  pub fn next_state(&self) -> Option<ConnectionState> {
+    //Only the status and login states can be requested by a handshake
+    match self.next_state {
+      0x01 => Some(ConnectionState::Status),
+      0x02 => Some(ConnectionState::Login),
+      _ => None
+    }
+  }
+
 }
\ No newline at end of file
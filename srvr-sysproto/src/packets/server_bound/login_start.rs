@@ -22,31 +22,10 @@
   text of the license in any official language of the European Union.
 */
 
-use std::error::Error;
+use crate::packets::Packet;
 
-use crate::{
-  packets::Packet,
-  raw_packet::{RawPacketReader, RawPacketWriter},
-  mc_dtypes::{MCDataType, MCString}
-};
-
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,Packet)]
+#[packet(id = 0x00, state = Login)]
 pub struct LoginStartPacket {
   pub player_name: String
 }
-
-impl Packet for LoginStartPacket {
-
-  const PACKET_ID: usize = 0x00;
-
-  fn decode(buf: &mut RawPacketReader)
-    -> Result<LoginStartPacket, Box<dyn Error>>
-  {
-    Ok(LoginStartPacket{player_name: MCString::decode(buf)?.into()})
-  }
-
-  fn encode(&self, buf: &mut RawPacketWriter) {
-    MCString::from(self.player_name.to_string()).encode(buf);
-  }
-
-}
\ No newline at end of file
@@ -0,0 +1,59 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use crate::{
+  packets::Packet,
+  mc_dtypes::MCPosition
+};
+
+#[derive(Debug, Clone, PartialEq, Packet)]
+#[packet(id = 0x2e, state = Play)]
+pub struct PlayerBlockPlacementPacket {
+  //0: main hand, 1: off hand
+  #[mc(varint)]
+  pub hand: i32,
+  //As (x, z, y) of the block that was clicked, not the one being placed
+  #[mc(with = MCPosition)]
+  pub location: (i32, i32, i16),
+  //Same values as the face in player digging
+  #[mc(varint)]
+  pub face: i32,
+  pub cursor_x: f32,
+  pub cursor_y: f32,
+  pub cursor_z: f32,
+  pub inside_block: bool
+}
+
+#[cfg(test)]
+mod player_block_placement_test {
+  use super::PlayerBlockPlacementPacket;
+
+  #[test]
+  fn roundtrip_test() {
+    packet_test!(PlayerBlockPlacementPacket{
+      hand: 0, location: (5, 2, 64), face: 1,
+      cursor_x: 0.5, cursor_y: 1.0, cursor_z: 0.25, inside_block: false
+    });
+  }
+}
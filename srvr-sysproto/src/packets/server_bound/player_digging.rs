@@ -0,0 +1,56 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use crate::{
+  packets::Packet,
+  mc_dtypes::MCPosition
+};
+
+//Values of the status field
+pub const DIGGING_STARTED: i32 = 0;
+pub const DIGGING_CANCELLED: i32 = 1;
+pub const DIGGING_FINISHED: i32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Packet)]
+#[packet(id = 0x1a, state = Play)]
+pub struct PlayerDiggingPacket {
+  //Digging (0-2), but also dropping items (3-4), eating (5) and swapping hands (6)
+  #[mc(varint)]
+  pub status: i32,
+  //As (x, z, y)
+  #[mc(with = MCPosition)]
+  pub location: (i32, i32, i16),
+  //0: bottom, 1: top, 2: north, 3: south, 4: west, 5: east
+  pub face: i8
+}
+
+#[cfg(test)]
+mod player_digging_test {
+  use super::PlayerDiggingPacket;
+
+  #[test]
+  fn roundtrip_test() {
+    packet_test!(PlayerDiggingPacket{status: 2, location: (-12, 300, -64), face: 1});
+  }
+}
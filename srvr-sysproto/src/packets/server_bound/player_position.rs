@@ -0,0 +1,45 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use crate::packets::Packet;
+
+#[derive(Debug, Clone, PartialEq, Packet)]
+#[packet(id = 0x11, state = Play)]
+pub struct PlayerPositionPacket {
+  pub x: f64,
+  //Y coordinate of the player's feet
+  pub y: f64,
+  pub z: f64,
+  pub on_ground: bool
+}
+
+#[cfg(test)]
+mod player_position_test {
+  use super::PlayerPositionPacket;
+
+  #[test]
+  fn roundtrip_test() {
+    packet_test!(PlayerPositionPacket{x: 8.5, y: -59.0, z: -1024.25, on_ground: true});
+  }
+}
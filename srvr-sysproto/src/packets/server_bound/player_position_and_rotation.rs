@@ -0,0 +1,48 @@
+/*
+  Copyright (C) 2022 Raúl Wolters
+  
+  This file is part of srvr.
+  
+  srvr is free software: you can redistribute it and/or modify it under the
+  terms of the European Union Public License (EUPL), provided that you publish
+  your modifications under the terms of the EUPL or another compatible license
+  as specified by the EUPL v1.2 or higher.
+
+  As the copyright holder is a citizen of the Kingdom of the Netherlands, this
+  license agreement shall be governed by dutch law, as specified in clause 15
+  of the EUPL v1.2.
+
+  srvr is distributed in the hope that it will be useful, but WITHOUT ANY
+  WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+  A PARTICULAR PURPOSE.  See the European Union Public License for more details.
+  
+  You should have received a copy of the European Union Public License in a
+  official language of the European Union along with srvr. If not, see
+  <https://joinup.ec.europa.eu/collection/eupl/eupl-text-eupl-12> for the full
+  text of the license in any official language of the European Union.
+*/
+
+use crate::packets::Packet;
+
+#[derive(Debug, Clone, PartialEq, Packet)]
+#[packet(id = 0x12, state = Play)]
+pub struct PlayerPositionAndRotationPacket {
+  pub x: f64,
+  pub y: f64,
+  pub z: f64,
+  pub yaw: f32,
+  pub pitch: f32,
+  pub on_ground: bool
+}
+
+#[cfg(test)]
+mod player_position_and_rotation_test {
+  use super::PlayerPositionAndRotationPacket;
+
+  #[test]
+  fn roundtrip_test() {
+    packet_test!(PlayerPositionAndRotationPacket{
+      x: 0.0, y: 70.0, z: 0.0, yaw: -179.9, pitch: 90.0, on_ground: false
+    });
+  }
+}
@@ -22,31 +22,11 @@
   text of the license in any official language of the European Union.
 */
 
-use std::error::Error;
+use crate::packets::Packet;
 
-use crate::{
-  packets::Packet,
-  raw_packet::{RawPacketReader, RawPacketWriter},
-  mc_dtypes::{MCDataType, MCVarInt}
-};
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Packet)]
+#[packet(id = 0x00, state = Play)]
 pub struct TeleportConfirmPacket {
+  #[mc(varint)]
   teleport_id: usize
 }
-
-impl Packet for TeleportConfirmPacket {
-  const PACKET_ID: usize = 0x00;
-
-  fn decode(buf: &mut RawPacketReader)
-    -> Result<TeleportConfirmPacket, Box<dyn Error>>
-  {
-    Ok(TeleportConfirmPacket{
-      teleport_id: i32::from(MCVarInt::decode(buf)?) as usize
-    })
-  }
-
-  fn encode(&self, buf: &mut RawPacketWriter) {
-    MCVarInt::from(self.teleport_id as i32).encode(buf);
-  }
-}
\ No newline at end of file
@@ -23,17 +23,248 @@
 */
 
 use std::{
-  error::Error}
-;
+  error::Error,
+  fmt::{Display, Formatter},
+  io::{self, Read, Write}
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 
 use tokio::{
-  io::{AsyncReadExt, AsyncWriteExt},
+  io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
   net::TcpStream
 };
 
-use crate::mc_dtypes::{MCVarInt, MCDataType};
+use crate::{
+  mc_dtypes::{MCVarInt, MCDataType},
+  encryption::PacketCipher
+};
 
 const MAX_PACKAGE_LEN: usize = 2097151;
+const MAX_UNCOMPRESSED_LEN: usize = 8388608;
+const MAX_VARINT_BYTES: usize = 5;
+const READ_CHUNK_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub struct PacketStream<S = TcpStream> {
+  /*
+    A PacketStream wraps the connection to a single client. Packets may arrive
+    in pieces (slow links), or several packets may arrive at once (Nagle). We
+    therefore keep all bytes that we received but did not yet turn into a
+    packet in a receive buffer, and only ever cut complete frames off of it.
+
+    Because received bytes are moved into the buffer right away, reading is
+    cancel-safe: a read that times out does not lose any data.
+
+    Once the server has sent a Set Compression packet, every packet in both
+    directions uses the compressed format. The threshold is stored here so
+    that the reader and writer can handle this transparently.
+
+    The same goes for encryption: once it is enabled, all bytes are decrypted
+    as soon as they arrive and encrypted right before they are sent, so the
+    receive buffer only ever contains plaintext.
+  */
+  stream: S,
+  rx_buf: Vec<u8>,
+  compression_threshold: Option<usize>,
+  cipher: Option<PacketCipher>
+}
+
+impl<S> PacketStream<S> {
+
+  pub fn new(stream: S) -> Self {
+    PacketStream { stream, rx_buf: Vec::new(), compression_threshold: None, cipher: None }
+  }
+
+  /// Enables AES/CFB8 encryption with the given shared secret for all further
+  /// reads and writes. Bytes that were already received but not yet read as
+  /// packets were sent after the client enabled encryption, so they are
+  /// decrypted as well.
+  pub fn enable_encryption(&mut self, shared_secret: &[u8]) -> Result<(), RawPacketError> {
+    let mut cipher = PacketCipher::new(shared_secret)?;
+    cipher.decrypt(&mut self.rx_buf);
+    self.cipher = Some(cipher);
+    Ok(())
+  }
+
+  pub fn is_encrypted(&self) -> bool {self.cipher.is_some()}
+
+  /// Switches the connection to the compressed packet format. Packets of at
+  /// least `threshold` bytes are zlib-compressed, smaller ones are sent as-is.
+  /// Passing None switches back to the uncompressed format.
+  pub fn set_compression(&mut self, threshold: Option<usize>) {
+    self.compression_threshold = threshold;
+  }
+
+  pub fn compression_threshold(&self) -> Option<usize> {self.compression_threshold}
+
+  pub fn get_ref(&self) -> &S {&self.stream}
+  pub fn get_mut(&mut self) -> &mut S {&mut self.stream}
+  pub fn into_inner(self) -> S {self.stream}
+
+  fn try_split_frame(&mut self) -> Result<Option<Vec<u8>>, RawPacketError> {
+    //(1) Try to decode the length prefix, we may not have all of it yet
+    let (package_len, prefix_len) = match peek_varint(&self.rx_buf, MAX_VARINT_BYTES)? {
+      Some(prefix) => prefix,
+      None => return Ok(None)
+    };
+
+    //(2) Check that the length is sensible before we wait for the body
+    if package_len < 0 {
+      return Err(RawPacketError::MalformedVarInt);
+    }
+    let package_len = package_len as usize;
+    if package_len == 0 {
+      return Err(RawPacketError::EmptyPacket);
+    }
+    if package_len > MAX_PACKAGE_LEN {
+      return Err(RawPacketError::PacketTooLong(package_len));
+    }
+
+    //(3) If the entire body is buffered we can cut the frame off
+    if self.rx_buf.len() < prefix_len + package_len {
+      return Ok(None);
+    }
+    let frame = self.rx_buf[prefix_len..prefix_len + package_len].to_vec();
+    self.rx_buf.drain(..prefix_len + package_len);
+    Ok(Some(frame))
+  }
+
+}
+
+impl<S: AsyncRead + Unpin> PacketStream<S> {
+
+  /// Waits until at least `min_len` bytes have been received and returns all
+  /// received bytes that have not been read as packets yet, without consuming
+  /// them. Used to sniff non-standard traffic (legacy pings) before framing.
+  pub async fn peek(&mut self, min_len: usize) -> Result<&[u8], RawPacketError> {
+    while self.rx_buf.len() < min_len {
+      self.fill_buf().await?;
+    }
+    Ok(&self.rx_buf)
+  }
+
+  async fn read_frame(&mut self) -> Result<Vec<u8>, RawPacketError> {
+    loop {
+      //(1) We might already have a full frame lying around
+      if let Some(frame) = self.try_split_frame()? {
+        return Ok(frame);
+      }
+
+      //(2) If not, we have to wait for more bytes to arrive
+      self.fill_buf().await?;
+    }
+  }
+
+  async fn fill_buf(&mut self) -> Result<(), RawPacketError> {
+    let mut chunk = [0u8; READ_CHUNK_SIZE];
+    let bytes_read = self.stream.read(&mut chunk).await?;
+    if bytes_read == 0 {
+      return Err(match self.rx_buf.len() {
+        0 => RawPacketError::Disconnected,
+        _ => RawPacketError::UnexpectedEof
+      });
+    }
+    if let Some(cipher) = &mut self.cipher {
+      cipher.decrypt(&mut chunk[..bytes_read]);
+    }
+    self.rx_buf.extend_from_slice(&chunk[..bytes_read]);
+    Ok(())
+  }
+
+}
+
+impl<S: AsyncWrite + Unpin> PacketStream<S> {
+
+  async fn write_frame(&mut self, mut frame: Vec<u8>) -> Result<(), io::Error> {
+    if let Some(cipher) = &mut self.cipher {
+      cipher.encrypt(&mut frame);
+    }
+    self.stream.write_all(&frame).await?;
+    self.stream.flush().await
+  }
+
+}
+
+fn peek_varint(bytes: &[u8], max_bytes: usize)
+  -> Result<Option<(i32, usize)>, RawPacketError>
+{
+  /*
+    Decodes a VarInt at the start of the slice without consuming anything.
+    Returns the value and the number of bytes it occupied, or None if the
+    slice ends before the VarInt does.
+  */
+  let mut val = 0i32;
+  for (idx, byte) in bytes.iter().take(max_bytes).enumerate() {
+    val |= ((byte & 0x7f) as i32) << (7 * idx);
+    if byte & 0x80 == 0 {
+      return Ok(Some((val, idx + 1)));
+    }
+  }
+
+  //We ran out of bytes: either the VarInt is too long or we need more data
+  match bytes.len() >= max_bytes {
+    true => Err(RawPacketError::MalformedVarInt),
+    false => Ok(None)
+  }
+}
+
+fn decompress_frame(frame: Vec<u8>, threshold: usize) -> Result<Vec<u8>, RawPacketError> {
+  //(1) The frame starts with the length of the uncompressed body
+  let (data_len, prefix_len) = match peek_varint(&frame, MAX_VARINT_BYTES)? {
+    Some(prefix) => prefix,
+    None => return Err(RawPacketError::MalformedVarInt)
+  };
+  if data_len < 0 {
+    return Err(RawPacketError::MalformedVarInt);
+  }
+  let data_len = data_len as usize;
+
+  //(2) A data length of zero means that the body was not compressed
+  if data_len == 0 {
+    return Ok(frame[prefix_len..].to_vec());
+  }
+  if data_len < threshold {
+    return Err(RawPacketError::BadCompression(format!(
+      "compressed packet of {data_len} bytes is below the threshold of {threshold} bytes"
+    )));
+  }
+  if data_len > MAX_UNCOMPRESSED_LEN {
+    return Err(RawPacketError::PacketTooLong(data_len));
+  }
+
+  //(3) Inflate the body, making sure it is exactly as long as advertised
+  let mut body = Vec::with_capacity(data_len);
+  ZlibDecoder::new(&frame[prefix_len..])
+    .take(data_len as u64 + 1)
+    .read_to_end(&mut body)
+    .map_err(|err| RawPacketError::BadCompression(format!("{err}")))?;
+  if body.len() != data_len {
+    return Err(RawPacketError::BadCompression(format!(
+      "expected {data_len} bytes after decompression, got {}", body.len()
+    )));
+  }
+
+  Ok(body)
+}
+
+fn compress_body(body: Vec<u8>, threshold: usize) -> Result<Vec<u8>, io::Error> {
+  let mut compressed = RawPacketWriter::empty(body.len() + MAX_VARINT_BYTES);
+
+  if body.len() < threshold {
+    //(1a) Small packets are sent uncompressed with a data length of zero
+    MCVarInt::from(0).encode(&mut compressed);
+    compressed.write_bytes(&body);
+  } else {
+    //(1b) Large packets are prefixed with their uncompressed length
+    MCVarInt::from(body.len() as i32).encode(&mut compressed);
+    let mut encoder = ZlibEncoder::new(compressed.to_raw(), Compression::default());
+    encoder.write_all(&body)?;
+    return encoder.finish();
+  }
+
+  Ok(compressed.to_raw())
+}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RawPacketReader {
@@ -49,46 +280,26 @@ pub struct RawPacketReader {
 
 impl RawPacketReader {
 
-  pub async fn read(stream: &mut TcpStream) -> Result<Self, Box<dyn Error>> {
-    /*(1)
-      We first have to find the length of the package. This is encoded in an
-      up-to 3-byte varint. If we read too many bytes, we'll have to account for
-      that.
-    */
-    let mut tmp_buf = vec![0u8;3];
-    stream.read(&mut tmp_buf).await?;
-    let mut tmp_reader= RawPacketReader::from_raw(tmp_buf);
-
-    let package_len: i32 = MCVarInt::decode(&mut tmp_reader)?.into();
-  
-    //Ignore legacy ping packets
-    if package_len as usize == tmp_reader.ptr || package_len == 0 {
-      return Ok(RawPacketReader{data: Vec::new(), ptr: 0usize, id: usize::MAX})
-    }
-
-    let excess = 3 - tmp_reader.ptr; //bytes that are not part of package_len
-    let bytes_to_read = (package_len as usize) - excess;
-
-    //(2) Read the remaining contents
-    let mut big_buf = vec![0u8;bytes_to_read];
-    stream.readable().await?;
-    stream.try_read(&mut big_buf)?;
-    let mut reader = match excess {
-      0 => RawPacketReader::from_raw(big_buf),
-      1 | 2 => {
-        let mut complete_buf = tmp_reader.to_raw()[3-excess..3].to_vec();
-        complete_buf.append(&mut big_buf);
-        RawPacketReader::from_raw(complete_buf)
-      } other => {
-        todo!(); //should error the fuck out
-      }
-    };
+  pub async fn read<S: AsyncRead + Unpin>(stream: &mut PacketStream<S>)
+    -> Result<Self, RawPacketError>
+  {
+    //(1) Wait until a complete frame has been received
+    let mut frame = stream.read_frame().await?;
+    if let Some(threshold) = stream.compression_threshold {
+      frame = decompress_frame(frame, threshold)?;
+    }
 
-    //(3) Set the package ID
-    let package_id: i32 = MCVarInt::decode(&mut reader)?.into();
-    reader.id = package_id as usize;
+    //(2) Decode the package ID, which must be contained in the frame
+    let (package_id, id_len) = match peek_varint(&frame, MAX_VARINT_BYTES)? {
+      Some(id) => id,
+      None => return Err(RawPacketError::MalformedVarInt)
+    };
+    if package_id < 0 {
+      return Err(RawPacketError::MalformedVarInt);
+    }
 
-    Ok(reader)
+    //(R) The reader starts right after the ID
+    Ok(RawPacketReader {data: frame, ptr: id_len, id: package_id as usize})
   }
 
   pub fn get_package_id(&self) -> usize {self.id}
@@ -99,6 +310,7 @@ impl RawPacketReader {
 
   pub fn to_raw(self) -> Vec<u8> {self.data}
   pub fn raw_view(&self) -> &Vec<u8> {&self.data}
+  pub fn remaining(&self) -> usize {self.data.len().saturating_sub(self.ptr)}
 
   pub fn read_byte(&mut self) -> u8 {
     let rtrn = self.data[self.ptr];
@@ -142,25 +354,33 @@ pub struct RawPacketWriter {
 
 impl RawPacketWriter {
 
-  pub async fn write(mut self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
-    //(1) First we should encode the package ID, since its length is included
-    //in the package length
-    let mut tmp_writer = RawPacketWriter::empty(0);
-    MCVarInt::from(self.id as i32).encode(&mut tmp_writer);
-    let mut id_varint_buf = tmp_writer.to_raw();
-    let package_len = self.bytes.len() + id_varint_buf.len();
-
-    //(2) Next, we'll encode the package length
-    tmp_writer = RawPacketWriter::empty(0);
-    MCVarInt::from(package_len as i32).encode(&mut tmp_writer);
-    let mut full_buf = tmp_writer.to_raw();
+  pub async fn write<S: AsyncWrite + Unpin>(mut self, stream: &mut PacketStream<S>)
+    -> Result<(), Box<dyn Error>>
+  {
+    //(1) First we should encode the package ID, since it is part of the
+    //(possibly compressed) package body
+    let mut body = RawPacketWriter::empty(self.bytes.len() + MAX_VARINT_BYTES);
+    MCVarInt::from(self.id as i32).encode(&mut body);
+    body.bytes.append(&mut self.bytes);
+    let mut body = body.to_raw();
+
+    //(2) In the compressed format the body is preceded by its uncompressed
+    //length, or by zero if the body was too small to bother compressing
+    if let Some(threshold) = stream.compression_threshold {
+      body = compress_body(body, threshold)?;
+    }
+    let package_len = body.len();
+    if package_len > MAX_PACKAGE_LEN {
+      return Err(RawPacketError::PacketTooLong(package_len).into());
+    }
 
-    //(3) We still have to append the ID and the package data
-    full_buf.append(&mut id_varint_buf);
-    full_buf.append(&mut self.bytes);
+    //(3) Next, we'll encode the package length and append the body
+    let mut full_buf = RawPacketWriter::empty(package_len + MAX_VARINT_BYTES);
+    MCVarInt::from(package_len as i32).encode(&mut full_buf);
+    full_buf.bytes.append(&mut body);
 
     //(4) Now we write the bytes to the stream
-    stream.write(&full_buf).await?;
+    stream.write_frame(full_buf.to_raw()).await?;
     Ok(())
   }
 
@@ -189,4 +409,221 @@ impl RawPacketWriter {
 
 impl From<RawPacketWriter> for Vec<u8> {
   fn from(rpw: RawPacketWriter) -> Self {rpw.bytes}
-}
\ No newline at end of file
+}
+
+#[derive(Debug)]
+pub enum RawPacketError {
+  //Client closed the connection in between two packets
+  Disconnected,
+  //Client closed the connection halfway through a packet
+  UnexpectedEof,
+  //Length prefix exceeds MAX_PACKAGE_LEN
+  PacketTooLong(usize),
+  //Length prefix or packet ID is not a valid VarInt
+  MalformedVarInt,
+  //Packet of length zero, so it doesn't even have an ID
+  EmptyPacket,
+  //Compressed packet could not be inflated or had the wrong length
+  BadCompression(String),
+  //Encryption could not be enabled
+  BadEncryption(String),
+  Io(io::Error)
+}
+
+impl Display for RawPacketError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    use RawPacketError::*;
+    match self {
+      Disconnected => write!(f, "client disconnected"),
+      UnexpectedEof => write!(f, "client disconnected halfway through a packet"),
+      PacketTooLong(len) => write!(f,
+        "packet length {len} exceeds the maximum of {MAX_PACKAGE_LEN} bytes"
+      ),
+      MalformedVarInt => write!(f, "encountered malformed VarInt"),
+      EmptyPacket => write!(f, "received packet of length zero"),
+      BadCompression(reason) => write!(f, "badly compressed packet: {reason}"),
+      BadEncryption(reason) => write!(f, "could not enable encryption: {reason}"),
+      Io(err) => write!(f, "I/O error: \"{err}\"")
+    }
+  }
+}
+
+impl Error for RawPacketError {}
+
+impl From<io::Error> for RawPacketError {
+  fn from(err: io::Error) -> Self {RawPacketError::Io(err)}
+}
+
+#[cfg(test)]
+mod raw_packet_test {
+
+  use tokio::io::{duplex, AsyncWriteExt};
+
+  use super::{PacketStream, RawPacketReader, RawPacketWriter, RawPacketError};
+
+  #[tokio::test]
+  async fn coalesced_packets() {
+    //Two packets in a single read
+    let bytes: &[u8] = &[0x02, 0x00, 0xaa, 0x03, 0x01, 0xbb, 0xcc];
+    let mut stream = PacketStream::new(bytes);
+
+    let first = RawPacketReader::read(&mut stream).await.unwrap();
+    assert_eq!(first.get_package_id(), 0x00);
+    assert_eq!(first.raw_view(), &vec![0x00, 0xaa]);
+
+    let mut second = RawPacketReader::read(&mut stream).await.unwrap();
+    assert_eq!(second.get_package_id(), 0x01);
+    assert_eq!(second.read_bytes(2), vec![0xbb, 0xcc]);
+
+    assert!(matches!(
+      RawPacketReader::read(&mut stream).await,
+      Err(RawPacketError::Disconnected)
+    ));
+  }
+
+  #[tokio::test]
+  async fn partial_reads() {
+    //Packet of 300 bytes, which requires a two-byte length prefix, that
+    //trickles in one byte at a time
+    let (client, server) = duplex(1);
+    let mut writer = RawPacketWriter::new(0x05);
+    writer.write_bytes(&[0x42; 299]);
+
+    let mut expected = vec![0xac, 0x02, 0x05];
+    expected.extend_from_slice(&[0x42; 299]);
+
+    let sender = tokio::spawn(async move {
+      let mut client = client;
+      for byte in expected {
+        client.write_all(&[byte]).await.unwrap();
+      }
+    });
+
+    let mut stream = PacketStream::new(server);
+    let mut packet = RawPacketReader::read(&mut stream).await.unwrap();
+    assert_eq!(packet.get_package_id(), 0x05);
+    assert_eq!(packet.read_bytes(299), vec![0x42; 299]);
+    sender.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn write_read_roundtrip() {
+    let (client, server) = duplex(64);
+    let mut tx = PacketStream::new(client);
+    let mut rx = PacketStream::new(server);
+
+    let mut writer = RawPacketWriter::new(0x7f);
+    writer.write_bytes(b"hello");
+    writer.write(&mut tx).await.unwrap();
+
+    let mut packet = RawPacketReader::read(&mut rx).await.unwrap();
+    assert_eq!(packet.get_package_id(), 0x7f);
+    assert_eq!(packet.read_bytes(5), b"hello".to_vec());
+  }
+
+  #[tokio::test]
+  async fn compressed_roundtrip() {
+    let (client, server) = duplex(4096);
+    let mut tx = PacketStream::new(client);
+    let mut rx = PacketStream::new(server);
+    tx.set_compression(Some(64));
+    rx.set_compression(Some(64));
+
+    //One packet below and one above the threshold
+    for len in [10usize, 1000] {
+      let mut writer = RawPacketWriter::new(0x22);
+      writer.write_bytes(&vec![0x11; len]);
+      writer.write(&mut tx).await.unwrap();
+
+      let mut packet = RawPacketReader::read(&mut rx).await.unwrap();
+      assert_eq!(packet.get_package_id(), 0x22);
+      assert_eq!(packet.read_bytes(len), vec![0x11; len]);
+    }
+  }
+
+  #[tokio::test]
+  async fn compressed_format() {
+    //Packets below the threshold have a data length of zero
+    let mut buf = Vec::new();
+    let mut tx = PacketStream::new(&mut buf);
+    tx.set_compression(Some(256));
+    let mut writer = RawPacketWriter::new(0x03);
+    writer.write_bytes(&[0xaa, 0xbb]);
+    writer.write(&mut tx).await.unwrap();
+    assert_eq!(buf, vec![0x04, 0x00, 0x03, 0xaa, 0xbb]);
+
+    //Compressed packets that claim to be below the threshold are rejected
+    let mut rx = PacketStream::new(&[0x03, 0x02, 0x03, 0xaa][..]);
+    rx.set_compression(Some(256));
+    assert!(matches!(
+      RawPacketReader::read(&mut rx).await,
+      Err(RawPacketError::BadCompression(_))
+    ));
+  }
+
+  #[tokio::test]
+  async fn encrypted_roundtrip() {
+    let (client, server) = duplex(4096);
+    let mut tx = PacketStream::new(client);
+    let mut rx = PacketStream::new(server);
+
+    //First packet is sent in plain text, the rest is encrypted
+    RawPacketWriter::new(0x01).write(&mut tx).await.unwrap();
+    tx.enable_encryption(&[0x13; 16]).unwrap();
+    tx.set_compression(Some(16));
+    for id in 0x02..0x05 {
+      let mut writer = RawPacketWriter::new(id);
+      writer.write_bytes(&[id as u8; 100]);
+      writer.write(&mut tx).await.unwrap();
+    }
+
+    //Everything has been received before the reader enables encryption
+    assert_eq!(RawPacketReader::read(&mut rx).await.unwrap().get_package_id(), 0x01);
+    rx.enable_encryption(&[0x13; 16]).unwrap();
+    rx.set_compression(Some(16));
+    for id in 0x02..0x05 {
+      let mut packet = RawPacketReader::read(&mut rx).await.unwrap();
+      assert_eq!(packet.get_package_id(), id);
+      assert_eq!(packet.read_bytes(100), vec![id as u8; 100]);
+    }
+  }
+
+  #[tokio::test]
+  async fn malformed_frames() {
+    //Length prefix that exceeds the maximum package length
+    let mut stream = PacketStream::new(&[0x80, 0x80, 0x80, 0x01][..]);
+    assert!(matches!(
+      RawPacketReader::read(&mut stream).await,
+      Err(RawPacketError::PacketTooLong(2097152))
+    ));
+
+    //Length prefix that does not terminate within five bytes
+    let mut stream = PacketStream::new(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x01][..]);
+    assert!(matches!(
+      RawPacketReader::read(&mut stream).await,
+      Err(RawPacketError::MalformedVarInt)
+    ));
+
+    //Zero-length packet
+    let mut stream = PacketStream::new(&[0x00][..]);
+    assert!(matches!(
+      RawPacketReader::read(&mut stream).await,
+      Err(RawPacketError::EmptyPacket)
+    ));
+
+    //Connection dropped halfway through a packet
+    let mut stream = PacketStream::new(&[0x05, 0x00, 0x01][..]);
+    assert!(matches!(
+      RawPacketReader::read(&mut stream).await,
+      Err(RawPacketError::UnexpectedEof)
+    ));
+
+    //Packet ID that does not terminate within the frame
+    let mut stream = PacketStream::new(&[0x02, 0x80, 0x80][..]);
+    assert!(matches!(
+      RawPacketReader::read(&mut stream).await,
+      Err(RawPacketError::MalformedVarInt)
+    ));
+  }
+
+}
@@ -22,34 +22,131 @@
   text of the license in any official language of the European Union.
 */
 
-use std::fs::File;
+use std::{
+  fs::File,
+  io::{self, Read, Seek, SeekFrom, Write}
+};
 
 use log::info;
+use serde::{Serialize, Deserialize};
 
 use crate::{
   world_builder::WorldBuilderError,
   worldgen::generator_api::BoxedWorldGenerator
 };
 
+/// Everything about a world that is kept in its save file. Missing fields get
+/// their default, so empty saves (like the ones that ship with srvr) load as
+/// a new world
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldData {
+  //Ticks the world has been running for
+  pub age: u64,
+  //The block players appear on when they first join
+  pub spawn: [i32; 3]
+}
+
+impl Default for WorldData {
+  fn default() -> Self {
+    WorldData {age: 0, spawn: [0, 64, 0]}
+  }
+}
+
 #[derive(Debug)]
 pub struct World {
-  
+  name: String,
+  file_handle: File,
+  data: WorldData
 }
 
 impl World {
 
+  pub fn name(&self) -> &str {&self.name}
+  pub fn data(&self) -> &WorldData {&self.data}
+  pub fn data_mut(&mut self) -> &mut WorldData {&mut self.data}
+
+  /// Called once per server tick
+  pub fn tick(&mut self) {
+    self.data.age += 1;
+  }
+
+  /// Writes the world over its save file. This waits for the disk, so async
+  /// code should call it from a blocking thread.
+  pub fn save(&mut self) -> io::Result<()> {
+    //(1) Replace the old save with what we have in memory
+    info!("Saving world \"{}\"...", self.name);
+    let contents = toml::to_string(&self.data)
+      .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    self.file_handle.set_len(0)?;
+    self.file_handle.seek(SeekFrom::Start(0))?;
+    self.file_handle.write_all(contents.as_bytes())?;
+
+    //(R) Make sure it actually hit the disk
+    self.file_handle.sync_all()
+  }
+
   pub fn load(gen: BoxedWorldGenerator, file_handle: File, name: String)
     -> Result<Self, WorldBuilderError>
   {
     info!("Loading world \"{name}\"...");
-    todo!()
+    Self::read(file_handle, name)
   }
 
   pub fn new(gen: BoxedWorldGenerator, file_handle: File, name: String)
   -> Result<Self, WorldBuilderError>
   {
     info!("Creating new world \"{name}\"...");
-    todo!();
+    let mut world = World {name, file_handle, data: WorldData::default()};
+    world.save()
+      .map_err(|err| format!("Could not write world file, reason: \"{err}\""))?;
+    Ok(world)
   }
 
-}
\ No newline at end of file
+  fn read(mut file_handle: File, name: String) -> Result<Self, WorldBuilderError> {
+    let mut contents = String::new();
+    file_handle.read_to_string(&mut contents)
+      .map_err(|err| format!("Could not read world file, reason: \"{err}\""))?;
+    let data = toml::from_str(&contents)
+      .map_err(|err| format!("Invalid world file, reason: \"{err}\""))?;
+    Ok(World {name, file_handle, data})
+  }
+
+}
+
+#[cfg(test)]
+mod world_test {
+  use std::{env, fs::{self, OpenOptions}};
+
+  use super::*;
+
+  #[test]
+  fn save() {
+    let path = env::temp_dir().join("srvr-world-test.srvrsave");
+    fs::write(&path, "age = 0\nspawn = [0, 64, 0]\n").unwrap();
+    let open = || OpenOptions::new().read(true).write(true).open(&path).unwrap();
+
+    //(1) Change the world and save it
+    let mut world = World::read(open(), "test".to_string()).unwrap();
+    world.tick();
+    world.data_mut().spawn = [10, 70, -3];
+    world.save().unwrap();
+
+    //(2) Saving twice must not leave the old save behind
+    world.save().unwrap();
+    let saved = World::read(open(), "test".to_string()).unwrap();
+    assert_eq!(saved.data(), &WorldData {age: 1, spawn: [10, 70, -3]});
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn empty_save() {
+    let path = env::temp_dir().join("srvr-empty-world-test.srvrsave");
+    fs::write(&path, "").unwrap();
+    let file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+
+    let world = World::read(file, "test".to_string()).unwrap();
+    assert_eq!(world.data(), &WorldData::default());
+    fs::remove_file(&path).unwrap();
+  }
+}
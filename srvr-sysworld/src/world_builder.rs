@@ -25,7 +25,7 @@
 use std::{
   error::Error,
   fmt::{Display, Formatter, self},
-  path::PathBuf, fs::{File, self}
+  path::PathBuf, fs::{File, OpenOptions, self}
 };
 
 use log::{info, error};
@@ -52,7 +52,10 @@ impl WorldBuilder {
     save_folder.push(&(world_name.clone() + WORLD_FILE_EXT));
     if save_folder.exists() {
       //(2a) Load the saved game
-      let save_file = File::open(save_folder).unwrap();
+      let save_file = match OpenOptions::new().read(true).write(true).open(&save_folder) {
+        Ok(file) => file,
+        Err(err) => return Err(format!("Could not open world file, reason: \"{err}\"").into())
+      };
       return Ok(World::load(gen, save_file, world_name)?)
     } else {
       //(2b) Try to create a new file, then generate a new world